@@ -8,6 +8,7 @@ import {
   BIN_GHOST_ZIP,
 } from "./utils";
 import { decompressFolder } from "./decompress";
+import { announce, listenForGhostNotify } from "./phase";
 import "./firewall";
 
 // Determine if we're running from a pkg bundle
@@ -33,6 +34,7 @@ async function getGhostPath() {
     fs.mkdirSync(HOM_ROOT, { recursive: true });
     // copy the zip file to the bin root
     console.log("Decompressing Ghost data...");
+    announce("decompressing");
     await decompressFolder(BIN_GHOST_ZIP, BIN_GHOST_FOLDER);
     console.log("Ghost data extracted successfully!");
 
@@ -60,13 +62,67 @@ async function getGhostPath() {
     return BIN_GHOST_FOLDER;
   } catch (error) {
     console.error("Failed to extract Ghost data:", error);
+    announce("failed", `extracting Ghost: ${(error as Error).message}`);
     process.exit(1);
   }
 }
 
+// Point Ghost's content dir (and SQLite database) somewhere else, used to keep
+// all user data inside freedom-kit's encrypted volume
+function redirectContent(ghostPath: string, contentPath: string) {
+  // first run in a fresh volume: seed it with the bundled themes and data
+  if (!fs.existsSync(contentPath)) {
+    console.log("Seeding content directory:", contentPath);
+    fs.cpSync(join(ghostPath, "content"), contentPath, { recursive: true });
+  }
+
+  // Ghost reads nconf overrides from the environment
+  process.env.paths__contentPath = contentPath;
+  process.env.database__connection__filename = join(
+    contentPath,
+    "data",
+    "ghost-local.db"
+  );
+}
+
+// Ghost is reached through freedom-kit's proxy on this loopback port, the
+// URL Ghost generates links for has to match it
+function overrideUrl(port: string) {
+  process.env.url = `http://localhost:${port}/`;
+}
+
+// Listen where freedom-kit tells us instead of the port in
+// config.production.json: a loopback port, or a Unix socket path. Ghost hands
+// server.port to http.Server#listen, which treats a path as a socket.
+function overrideListen(listen: string) {
+  process.env.server__host = "127.0.0.1";
+  process.env.server__port = listen;
+
+  if (!/^\d+$/.test(listen)) {
+    // the socket (and every file Ghost writes) is private to the user
+    process.umask(0o077);
+    fs.rmSync(listen, { force: true });
+  }
+}
+
 async function start() {
   const ghostPath = await getGhostPath();
 
+  const port = process.env.FREEDOM_KIT_GHOST_PORT;
+  if (port) {
+    overrideUrl(port);
+  }
+
+  const listen = process.env.FREEDOM_KIT_GHOST_LISTEN;
+  if (listen) {
+    overrideListen(listen);
+  }
+
+  const contentPath = process.env.FREEDOM_KIT_CONTENT_PATH;
+  if (contentPath) {
+    redirectContent(ghostPath, contentPath);
+  }
+
   console.log("Starting Ghost from:", ghostPath);
   console.log("Ghost will run in the same process (network isolation applied)");
   console.log("");
@@ -78,16 +134,25 @@ async function start() {
   const originalCwd = process.cwd();
   process.chdir(ghostPath);
 
+  // Ghost creates and migrates a missing database during boot, which is
+  // by far the slowest part of a first run
+  const databasePath =
+    process.env.database__connection__filename ??
+    join(ghostPath, "content", "data", "ghost-local.db");
+  announce(fs.existsSync(databasePath) ? "booting" : "migrating");
+  listenForGhostNotify();
+
   // Import and start Ghost directly in this process
   // This ensures our http/https patches apply to Ghost's code
   try {
     const ghostBoot = require(join(ghostPath, "current", "core", "boot"));
     console.log("🚀 Booting Ghost with network isolation...");
-    ghostBoot();
+    await ghostBoot();
     console.log("✅ Ghost started successfully");
   } catch (error) {
     console.error(`Failed to start Ghost: ${(error as Error).message}`);
     console.error((error as Error).stack);
+    announce("failed", (error as Error).message);
     process.chdir(originalCwd);
     process.exit(1);
   }
@@ -0,0 +1,33 @@
+// Startup phases reported to freedom-kit on stdout, one per line.
+// The app parses lines starting with PHASE_PREFIX, everything else is logged.
+export const PHASE_PREFIX = "@@freedom-kit:phase ";
+
+export type Phase =
+  | "decompressing"
+  | "migrating"
+  | "booting"
+  | "ready"
+  | "failed";
+
+export function announce(phase: Phase, detail?: string) {
+  const line = detail
+    ? `${PHASE_PREFIX}${phase} ${detail}`
+    : `${PHASE_PREFIX}${phase}`;
+  process.stdout.write(`${line.replace(/\n/g, " ")}\n`);
+}
+
+// Ghost reports the end of its boot through process.send when it runs under
+// a process manager (see core/server/notify.js). We are not forked with an
+// IPC channel, so install a stand-in that turns the message into a phase.
+export function listenForGhostNotify() {
+  if (process.send) return;
+
+  (process as any).send = (message: any) => {
+    if (message?.started) {
+      announce("ready");
+    } else if (message?.error) {
+      announce("failed", message.error.message ?? String(message.error));
+    }
+    return true;
+  };
+}
@@ -1,3 +1,5 @@
+import { join } from "node:path";
+import { homedir } from "node:os";
 import {
   NetworkName,
   NETWORK_CONFIG as ALL_NETWORK_CONFIG,
@@ -22,3 +24,7 @@ if (!RPC_URL) {
 }
 
 export const PORT = process.env.PORT || 8080;
+
+/** where the wallet and its database live, moved into the encrypted volume when unlocked */
+export const APP_DATA_DIR =
+  process.env.FREEDOM_KIT_WALLET_DIR || join(homedir(), ".railgun-freedom-kit");
@@ -1,11 +1,10 @@
-import { join } from "node:path";
-import { homedir } from "node:os";
 import fs from "node:fs";
 import { start, stop } from "./engine";
 import { startApiServer } from "./api";
 import { logger } from "./utils";
+import { APP_DATA_DIR } from "./env";
 
-const appDataDir = join(homedir(), ".railgun-freedom-kit");
+const appDataDir = APP_DATA_DIR;
 
 // mkdir if it does not exist
 if (!fs.existsSync(appDataDir)) {
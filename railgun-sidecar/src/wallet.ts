@@ -5,9 +5,9 @@ import {
   createDecipheriv,
 } from "node:crypto";
 import { join } from "node:path";
-import { homedir } from "node:os";
 import { readFileSync, writeFileSync, existsSync } from "node:fs";
 import { Wallet } from "ethers";
+import { APP_DATA_DIR } from "./env";
 import {
   createRailgunWallet as createRailgunWalletBase,
   loadWalletByID,
@@ -21,7 +21,7 @@ let currentWallet: {
   publicViewingKey: string;
 } | null = null;
 
-const WALLET_FILE_PATH = join(homedir(), ".railgun-freedom-kit", "wallet.enc");
+const WALLET_FILE_PATH = join(APP_DATA_DIR, "wallet.enc");
 
 /** Derive encryption key from password using PBKDF2 */
 function deriveKeyFromPassword(password: string, salt: Buffer): Buffer {
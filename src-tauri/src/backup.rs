@@ -0,0 +1,683 @@
+use crate::crypto::{DecryptReader, EncryptWriter};
+use crate::paths::AppPaths;
+use anyhow::{bail, Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// magic bytes at the start of every backup file
+const BACKUP_MAGIC: &[u8; 8] = b"FKBACKUP";
+/// first bytes of every SQLite database file
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+/// name of the manifest entry, always the last entry in the archive
+const MANIFEST_NAME: &str = "manifest.json";
+/// version of the archive layout
+const MANIFEST_VERSION: u32 = 1;
+
+/// archive prefixes for each part of the installation
+const SECTION_TOR: &str = "tor";
+const SECTION_GHOST_CONTENT: &str = "ghost/content";
+const SECTION_GHOST_CONFIG: &str = "ghost/config.production.json";
+const SECTION_WALLET: &str = "wallet/wallet.enc";
+
+/// list of files in the archive with their hashes
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    created_at: u64,
+    app_version: String,
+    sections: Vec<String>,
+    /// archive path -> file entry
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ManifestEntry {
+    size: u64,
+    sha256: String,
+}
+
+/// summary of a created backup
+#[derive(Serialize, Debug)]
+pub struct BackupReport {
+    pub path: String,
+    pub sections: Vec<String>,
+    pub files: usize,
+    pub bytes: u64,
+    pub warnings: Vec<String>,
+}
+
+/// summary of a restore (or a dry-run check)
+#[derive(Serialize, Debug)]
+pub struct RestoreReport {
+    pub dry_run: bool,
+    pub created_at: u64,
+    pub sections: Vec<String>,
+    pub files: usize,
+    pub bytes: u64,
+    /// where existing data was moved before being replaced
+    pub moved_aside: Vec<String>,
+}
+
+/// options for [`restore_backup`]
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+pub struct RestoreOptions {
+    /// only decrypt and verify the archive, don't touch the installation
+    #[serde(default)]
+    pub dry_run: bool,
+    /// replace existing data (it is moved aside, not deleted)
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// reader that hashes everything passing through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    fn finish(self) -> ManifestEntry {
+        ManifestEntry {
+            size: self.bytes,
+            sha256: hex::encode(self.hasher.finalize()),
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// collect regular files below `dir`, skipping symlinks and lock files
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+
+        if file_type.is_symlink() {
+            warn!("skipping symlink in backup: {:?}", path);
+        } else if file_type.is_dir() {
+            collect_files(&path, out)?;
+        } else if file_type.is_file() && path.extension().is_none_or(|ext| ext != "lock") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// append one file to the archive and record its hash
+fn append_file<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    manifest: &mut Manifest,
+    source: &Path,
+    archive_path: &str,
+) -> Result<()> {
+    let file = File::open(source).with_context(|| format!("Failed to open {:?}", source))?;
+    let size = file.metadata()?.len();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o600);
+    header.set_mtime(now_secs());
+    header.set_cksum();
+
+    // limit to the size in the header in case the file grows while we read it
+    let mut reader = HashingReader::new(BufReader::new(file).take(size));
+    builder
+        .append_data(&mut header, archive_path, &mut reader)
+        .with_context(|| format!("Failed to add {:?} to backup", source))?;
+
+    manifest
+        .files
+        .insert(archive_path.to_string(), reader.finish());
+    Ok(())
+}
+
+/// whether `path` is an SQLite database, going by its header
+fn is_sqlite(path: &Path) -> bool {
+    let mut magic = [0u8; SQLITE_MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| &magic == SQLITE_MAGIC)
+}
+
+/// WAL, shared memory or rollback journal of the database next to it
+fn is_sqlite_sidecar(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    ["-wal", "-shm", "-journal"].iter().any(|suffix| {
+        path.strip_suffix(suffix)
+            .is_some_and(|database| is_sqlite(Path::new(database)))
+    })
+}
+
+/// Append a consistent snapshot of the SQLite database at `source`
+///
+/// `VACUUM INTO` reads the database in one transaction, so Ghost can keep
+/// writing to it and its WAL is folded in.
+fn append_database<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    manifest: &mut Manifest,
+    source: &Path,
+    archive_path: &str,
+) -> Result<()> {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    let snapshot = source.with_file_name(format!(".{}.backup-{}", name, now_secs()));
+
+    let result = (|| -> Result<()> {
+        let conn = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database {:?}", source))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute("VACUUM INTO ?1", [snapshot.to_string_lossy()])
+            .with_context(|| format!("Failed to snapshot database {:?}", source))?;
+        drop(conn);
+        append_file(builder, manifest, &snapshot, archive_path)
+    })();
+    let _ = fs::remove_file(&snapshot);
+    result
+}
+
+/// append every file below `dir` under the archive prefix `prefix`
+fn append_dir<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    manifest: &mut Manifest,
+    dir: &Path,
+    prefix: &str,
+) -> Result<()> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+
+    for file in files {
+        // the snapshot of the database already has everything they hold
+        if is_sqlite_sidecar(&file) {
+            continue;
+        }
+        let relative = file.strip_prefix(dir)?;
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let archive_path = format!("{}/{}", prefix, relative);
+        if is_sqlite(&file) {
+            append_database(builder, manifest, &file, &archive_path)?;
+        } else {
+            append_file(builder, manifest, &file, &archive_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// create an encrypted backup of the Tor identity, Ghost content and wallet
+///
+/// The archive is a tar stream encrypted with XChaCha20-Poly1305 under an
+/// Argon2id-derived key. A manifest with SHA-256 hashes of every file is
+/// stored as the last entry and checked on restore.
+///
+/// SQLite databases, Ghost's included, go in as a snapshot taken with
+/// `VACUUM INTO`, consistent even while Ghost is writing to them.
+pub fn create_backup(
+    paths: &AppPaths,
+    destination: &Path,
+    passphrase: &str,
+) -> Result<BackupReport> {
+    info!("creating backup...");
+
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        created_at: now_secs(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        sections: Vec::new(),
+        files: BTreeMap::new(),
+    };
+    let mut warnings = Vec::new();
+
+    // write to a temporary file first so a failed backup never replaces a good one
+    let partial = destination.with_extension("partial");
+    let file = File::create(&partial).with_context(|| format!("Failed to create {:?}", partial))?;
+    let writer = EncryptWriter::with_passphrase(BufWriter::new(file), BACKUP_MAGIC, passphrase)?;
+    let mut builder = tar::Builder::new(writer);
+
+    let result = (|| -> Result<()> {
+        if paths.tor_dir.is_dir() {
+            append_dir(&mut builder, &mut manifest, &paths.tor_dir, SECTION_TOR)?;
+            manifest.sections.push(SECTION_TOR.to_string());
+        } else {
+            warnings.push("Tor identity not found, the backup has no onion address".to_string());
+        }
+
+        let content_dir = paths.ghost_content_dir();
+        if content_dir.is_dir() {
+            append_dir(
+                &mut builder,
+                &mut manifest,
+                &content_dir,
+                SECTION_GHOST_CONTENT,
+            )?;
+            manifest.sections.push(SECTION_GHOST_CONTENT.to_string());
+        } else {
+            warnings.push(format!("Ghost content not found at {:?}", content_dir));
+        }
+
+        let config = paths.ghost_config();
+        if config.is_file() {
+            if ghost_uses_external_database(&config) {
+                warnings.push(
+                    "Ghost is configured with an external database, it is not included".to_string(),
+                );
+            }
+            append_file(&mut builder, &mut manifest, &config, SECTION_GHOST_CONFIG)?;
+            manifest.sections.push(SECTION_GHOST_CONFIG.to_string());
+        }
+
+        let wallet = paths.wallet_file();
+        if wallet.is_file() {
+            append_file(&mut builder, &mut manifest, &wallet, SECTION_WALLET)?;
+            manifest.sections.push(SECTION_WALLET.to_string());
+        }
+
+        // the manifest goes last, it can only be written once all hashes are known
+        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.created_at);
+        header.set_cksum();
+        builder.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+
+        let writer = builder.into_inner()?;
+        writer
+            .finish()?
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    fs::rename(&partial, destination)
+        .with_context(|| format!("Failed to move backup to {:?}", destination))?;
+
+    let report = BackupReport {
+        path: destination.to_string_lossy().into_owned(),
+        sections: manifest.sections,
+        files: manifest.files.len(),
+        bytes: manifest.files.values().map(|f| f.size).sum(),
+        warnings,
+    };
+    info!(
+        "✅ backup created ({} files, {} bytes)",
+        report.files, report.bytes
+    );
+    Ok(report)
+}
+
+/// whether the Ghost config points at a database outside the content dir
+fn ghost_uses_external_database(config: &Path) -> bool {
+    fs::read_to_string(config)
+        .ok()
+        .and_then(|raw| serde_json::from_str::<serde_json::Value>(&raw).ok())
+        .and_then(|c| {
+            c.pointer("/database/client")
+                .and_then(|v| v.as_str())
+                .map(|client| client != "sqlite3")
+        })
+        .unwrap_or(false)
+}
+
+/// make sure an archive path only contains plain components
+fn safe_relative_path(path: &Path) -> Result<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            _ => bail!("Backup contains an unsafe path: {:?}", path),
+        }
+    }
+    Ok(out)
+}
+
+/// decrypt, verify and (unless dry-running) restore a backup
+///
+/// Files are extracted to a staging directory first. Only when every hash
+/// matches the manifest are they moved into place; existing data is moved
+/// aside with a `.pre-restore-<timestamp>` suffix rather than deleted.
+pub fn restore_backup(
+    paths: &AppPaths,
+    source: &Path,
+    passphrase: &str,
+    options: RestoreOptions,
+) -> Result<RestoreReport> {
+    info!(
+        "{} backup...",
+        if options.dry_run {
+            "verifying"
+        } else {
+            "restoring"
+        }
+    );
+
+    if !options.dry_run && !paths.ghost_dir.join("current").is_dir() {
+        bail!("Ghost is not installed yet, start freedom-kit once before restoring a backup");
+    }
+
+    let file = File::open(source).with_context(|| format!("Failed to open {:?}", source))?;
+    let reader = DecryptReader::with_passphrase(BufReader::new(file), BACKUP_MAGIC, passphrase)?;
+    let mut archive = tar::Archive::new(reader);
+
+    let staging = paths.data_dir.join(format!(".restore-{}", now_secs()));
+    if !options.dry_run {
+        fs::create_dir_all(&staging)?;
+    }
+
+    let result = (|| -> Result<(Manifest, BTreeMap<String, ManifestEntry>)> {
+        let mut seen = BTreeMap::new();
+        let mut manifest: Option<Manifest> = None;
+
+        for entry in archive.entries().context("Failed to read backup")? {
+            let mut entry = entry.context("Backup is corrupted or the passphrase is wrong")?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let path = safe_relative_path(&entry.path()?)?;
+            let name = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if name == MANIFEST_NAME {
+                let mut raw = Vec::new();
+                entry.read_to_end(&mut raw)?;
+                manifest = Some(serde_json::from_slice(&raw).context("Invalid backup manifest")?);
+                continue;
+            }
+
+            let mut reader = HashingReader::new(&mut entry);
+            if options.dry_run {
+                io::copy(&mut reader, &mut io::sink())?;
+            } else {
+                let target = staging.join(&path);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut out = File::create(&target)?;
+                io::copy(&mut reader, &mut out)?;
+                out.sync_all()?;
+            }
+            seen.insert(name, reader.finish());
+        }
+
+        let manifest = manifest.context("Backup has no manifest, it may be truncated")?;
+        Ok((manifest, seen))
+    })();
+
+    let (manifest, seen) = match result {
+        Ok(found) => found,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+
+    if manifest.version != MANIFEST_VERSION {
+        let _ = fs::remove_dir_all(&staging);
+        bail!("Unsupported backup version {}", manifest.version);
+    }
+    if manifest.files != seen {
+        let _ = fs::remove_dir_all(&staging);
+        bail!("Backup integrity check failed, file hashes do not match the manifest");
+    }
+
+    let mut report = RestoreReport {
+        dry_run: options.dry_run,
+        created_at: manifest.created_at,
+        sections: manifest.sections.clone(),
+        files: seen.len(),
+        bytes: seen.values().map(|f| f.size).sum(),
+        moved_aside: Vec::new(),
+    };
+
+    if options.dry_run {
+        info!("✅ backup verified ({} files)", report.files);
+        return Ok(report);
+    }
+
+    let result = install_staged(paths, &staging, &manifest, options, &mut report.moved_aside);
+    let _ = fs::remove_dir_all(&staging);
+    result?;
+
+    info!("✅ backup restored ({} files)", report.files);
+    Ok(report)
+}
+
+/// move staged sections into their final locations
+fn install_staged(
+    paths: &AppPaths,
+    staging: &Path,
+    manifest: &Manifest,
+    options: RestoreOptions,
+    moved_aside: &mut Vec<String>,
+) -> Result<()> {
+    // the same directory the backup was taken from
+    let content_dir = paths.ghost_content_dir();
+    let targets = [
+        (SECTION_TOR, paths.tor_dir.clone()),
+        (SECTION_GHOST_CONTENT, content_dir.clone()),
+        (SECTION_GHOST_CONFIG, paths.ghost_config()),
+        (SECTION_WALLET, paths.wallet_file()),
+    ];
+
+    // check everything up front so we don't stop half way
+    if !options.overwrite {
+        for (section, target) in &targets {
+            if manifest.sections.iter().any(|s| s == section) && target.exists() {
+                bail!(
+                    "{:?} already exists, restore with overwrite enabled to replace it",
+                    target
+                );
+            }
+        }
+    }
+
+    let suffix = format!("pre-restore-{}", now_secs());
+    for (section, target) in &targets {
+        if !manifest.sections.iter().any(|s| s == section) {
+            continue;
+        }
+
+        if target.exists() {
+            let mut aside = target.clone().into_os_string();
+            aside.push(format!(".{}", suffix));
+            fs::rename(target, &aside)
+                .with_context(|| format!("Failed to move {:?} aside", target))?;
+            moved_aside.push(PathBuf::from(aside).to_string_lossy().into_owned());
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(staging.join(section), target)
+            .with_context(|| format!("Failed to restore {:?}", target))?;
+
+        #[cfg(unix)]
+        if *section == SECTION_TOR {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(target, fs::Permissions::from_mode(0o700))?;
+        }
+    }
+
+    // the config carries absolute paths from the machine the backup was made on
+    if manifest.sections.iter().any(|s| s == SECTION_GHOST_CONFIG) {
+        rewrite_ghost_config_paths(&paths.ghost_config(), &content_dir)?;
+    }
+    Ok(())
+}
+
+/// point the restored Ghost config at this machine's content dir
+fn rewrite_ghost_config_paths(config_path: &Path, content_dir: &Path) -> Result<()> {
+    let raw = fs::read_to_string(config_path)?;
+    let mut config: serde_json::Value = serde_json::from_str(&raw)?;
+
+    if let Some(path) = config.pointer_mut("/paths/contentPath") {
+        *path = content_dir.to_string_lossy().into_owned().into();
+    }
+    if let Some(filename) = config.pointer_mut("/database/connection/filename") {
+        *filename = content_dir
+            .join("data")
+            .join("ghost-local.db")
+            .to_string_lossy()
+            .into_owned()
+            .into();
+    }
+
+    fs::write(config_path, serde_json::to_string_pretty(&config)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn install(root: &Path) -> AppPaths {
+        let data_dir = root.join("data");
+        let paths = AppPaths {
+            tor_dir: data_dir.join("tor"),
+            data_dir,
+            ghost_dir: root.join("ghost"),
+            wallet_dir: root.join("wallet"),
+            ghost_content: None,
+        };
+        fs::create_dir_all(&paths.data_dir).unwrap();
+        fs::create_dir_all(paths.ghost_dir.join("current")).unwrap();
+        paths
+    }
+
+    /// an install with an onion key and a Ghost database Ghost is writing to
+    fn busy_install() -> (TempDir, AppPaths, Connection) {
+        let root = tempfile::tempdir().unwrap();
+        let paths = install(root.path());
+        fs::create_dir_all(paths.tor_dir.join("keystore/hss")).unwrap();
+        fs::write(paths.tor_dir.join("keystore/hss/ks_hs_id"), b"onion key").unwrap();
+
+        fs::create_dir_all(paths.ghost_database().parent().unwrap()).unwrap();
+        let ghost = Connection::open(paths.ghost_database()).unwrap();
+        ghost.pragma_update(None, "journal_mode", "WAL").unwrap();
+        ghost
+            .execute_batch("CREATE TABLE posts (title TEXT); INSERT INTO posts VALUES ('hello');")
+            .unwrap();
+        // still in the WAL, not yet in the database file
+        assert!(paths.ghost_database().with_extension("db-wal").exists());
+        (root, paths, ghost)
+    }
+
+    fn backup(paths: &AppPaths) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("blog.fkbackup");
+        create_backup(paths, &file, "backup passphrase").unwrap();
+        (dir, file)
+    }
+
+    fn verify(file: &Path, passphrase: &str) -> Result<RestoreReport> {
+        let root = tempfile::tempdir().unwrap();
+        let options = RestoreOptions {
+            dry_run: true,
+            overwrite: false,
+        };
+        restore_backup(&install(root.path()), file, passphrase, options)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_root, paths, _ghost) = busy_install();
+        let (_dir, file) = backup(&paths);
+
+        let root = tempfile::tempdir().unwrap();
+        let restored = install(root.path());
+        restore_backup(
+            &restored,
+            &file,
+            "backup passphrase",
+            RestoreOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read(restored.tor_dir.join("keystore/hss/ks_hs_id")).unwrap(),
+            b"onion key"
+        );
+        assert!(!restored.ghost_database().with_extension("db-wal").exists());
+        let conn = Connection::open(restored.ghost_database()).unwrap();
+        let title: String = conn
+            .query_row("SELECT title FROM posts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "hello");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_refused() {
+        let (_root, paths, _ghost) = busy_install();
+        let (_dir, file) = backup(&paths);
+        assert!(verify(&file, "backup passphrase").is_ok());
+        assert!(verify(&file, "wrong passphrase").is_err());
+    }
+
+    #[test]
+    fn tampered_chunk_is_refused() {
+        let (_root, paths, _ghost) = busy_install();
+        let (_dir, file) = backup(&paths);
+        let mut data = fs::read(&file).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 1;
+        fs::write(&file, data).unwrap();
+        assert!(verify(&file, "backup passphrase").is_err());
+    }
+
+    #[test]
+    fn truncated_archive_is_refused() {
+        let (_root, paths, _ghost) = busy_install();
+        let (_dir, file) = backup(&paths);
+        let len = fs::metadata(&file).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_len(len - 100)
+            .unwrap();
+        assert!(verify(&file, "backup passphrase").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! QR codes and a printable card for handing out the blog's address
+//!
+//! Everything is drawn locally, nothing is looked up or fetched, so cards
+//! can be made offline before a meetup.
+
+mod qr;
+mod words;
+
+pub use qr::Qr;
+pub use words::checksum_words;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// pixels per module in the PNG files, sharp enough to print
+const PNG_SCALE: usize = 10;
+/// size of the standalone SVG files, they scale to anything
+const SVG_PIXELS: usize = 400;
+/// business card size in mm
+const CARD_WIDTH: f32 = 85.0;
+const CARD_HEIGHT: f32 = 55.0;
+/// between the front and the back on the printed sheet, in mm
+const CARD_GAP: f32 = 5.0;
+/// characters of the onion address per line on the card
+const ADDRESS_LINE: usize = 14;
+/// longer titles are cut short to fit next to the QR code
+const TITLE_CHARS: usize = 16;
+
+/// what goes on the card
+pub struct Card<'a> {
+    /// the blog's onion URL, as `HiddenServiceManager::onion_url` gives it
+    pub onion_url: &'a str,
+    /// heading on the front, the blog's name
+    pub title: Option<&'a str>,
+    /// Railgun address for tips, printed on the back
+    pub railgun_address: Option<&'a str>,
+}
+
+/// what was written
+#[derive(Serialize, Debug)]
+pub struct CardReport {
+    /// the link the QR code opens
+    pub onion_url: String,
+    pub checksum_words: Vec<String>,
+    /// the onion QR code, for showing it in the app
+    pub qr_svg: String,
+    pub files: Vec<String>,
+}
+
+/// Write QR codes (PNG and SVG) and `card.svg` into the `destination` folder
+///
+/// The card is credit card sized with the onion QR code, the address and
+/// its [`checksum_words`] on the front, and the Railgun address on the back
+/// when there is one.
+pub fn write_card(destination: &Path, card: &Card) -> Result<CardReport> {
+    let host = onion_host(card.onion_url)?;
+    let link = format!("http://{}", host);
+    let words = checksum_words(&host);
+    let onion_qr = Qr::encode(&link)?;
+
+    let mut files: Vec<(&str, Vec<u8>)> = vec![
+        ("onion-qr.svg", onion_qr.to_svg(SVG_PIXELS).into_bytes()),
+        ("onion-qr.png", onion_qr.to_png(PNG_SCALE)?),
+    ];
+    let railgun = match card.railgun_address {
+        Some(address) => {
+            let address = railgun_address(address)?;
+            let qr = Qr::encode(address)?;
+            files.push(("railgun-qr.svg", qr.to_svg(SVG_PIXELS).into_bytes()));
+            files.push(("railgun-qr.png", qr.to_png(PNG_SCALE)?));
+            Some((address, qr))
+        }
+        None => None,
+    };
+    let railgun = railgun.as_ref().map(|(address, qr)| (*address, qr));
+    files.push((
+        "card.svg",
+        card_svg(card.title, &host, &words, &onion_qr, railgun).into_bytes(),
+    ));
+
+    fs::create_dir_all(destination)
+        .with_context(|| format!("Failed to create {:?}", destination))?;
+    for (name, contents) in &files {
+        let path = destination.join(name);
+        fs::write(&path, contents).with_context(|| format!("Failed to write {:?}", path))?;
+    }
+
+    info!("address card written with {} files", files.len());
+    Ok(CardReport {
+        onion_url: link,
+        checksum_words: words.iter().map(|word| word.to_string()).collect(),
+        qr_svg: onion_qr.to_svg(SVG_PIXELS),
+        files: files
+            .iter()
+            .map(|(name, _)| destination.join(name).to_string_lossy().into_owned())
+            .collect(),
+    })
+}
+
+/// The `.onion` host in `url`, with the port when it is not 80
+pub fn onion_host(url: &str) -> Result<String> {
+    let url = url.trim().to_ascii_lowercase();
+    let host = url
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_end_matches('/');
+    let host = host.strip_suffix(":80").unwrap_or(host);
+    let name = host.split(':').next().unwrap_or(host);
+    let valid = name.strip_suffix(".onion").is_some_and(|name| {
+        name.len() == 56
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || (b'2'..=b'7').contains(&b))
+    });
+    if !valid {
+        bail!("{:?} is not a v3 onion address", url);
+    }
+    Ok(host.to_string())
+}
+
+fn railgun_address(address: &str) -> Result<&str> {
+    let address = address.trim();
+    if !address.starts_with("0zk")
+        || address.len() < 40
+        || !address.bytes().all(|b| b.is_ascii_alphanumeric())
+    {
+        bail!("{:?} is not a Railgun address", address);
+    }
+    Ok(address)
+}
+
+/// Printable SVG in mm, the front above the optional back
+fn card_svg(
+    title: Option<&str>,
+    host: &str,
+    words: &[&str],
+    onion_qr: &Qr,
+    railgun: Option<(&str, &Qr)>,
+) -> String {
+    let height = match railgun {
+        Some(_) => 2.0 * CARD_HEIGHT + CARD_GAP,
+        None => CARD_HEIGHT,
+    };
+    let mut svg = format!(
+        r##"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}" font-family="Helvetica, Arial, sans-serif">
+"##,
+        w = CARD_WIDTH,
+        h = height
+    );
+
+    // front: what Tor Browser needs
+    card_outline(&mut svg, 0.0);
+    qr_at(&mut svg, onion_qr, 0.0);
+    let title = title.map(str::trim).filter(|title| !title.is_empty());
+    let title = match title {
+        Some(title) if title.chars().count() > TITLE_CHARS => {
+            format!(
+                "{}…",
+                title.chars().take(TITLE_CHARS - 1).collect::<String>()
+            )
+        }
+        Some(title) => title.to_string(),
+        None => "Onion blog".to_string(),
+    };
+    text(&mut svg, 8.0, 3.4, "bold", &title);
+    text(&mut svg, 12.5, 2.2, "normal", "Open in Tor Browser:");
+    let mut y = 16.5;
+    for line in address_lines(host) {
+        mono(&mut svg, y, 2.5, &line);
+        y += 3.3;
+    }
+    text(&mut svg, y + 2.5, 2.2, "normal", "Check words:");
+    y += 6.5;
+    for pair in words.chunks(2) {
+        mono(&mut svg, y, 2.4, &pair.join(" "));
+        y += 3.2;
+    }
+
+    // back: tips
+    if let Some((address, qr)) = railgun {
+        let top = CARD_HEIGHT + CARD_GAP;
+        card_outline(&mut svg, top);
+        qr_at(&mut svg, qr, top);
+        text(&mut svg, top + 8.0, 3.4, "bold", "Tips");
+        text(&mut svg, top + 12.5, 2.2, "normal", "Railgun (private):");
+        let short = format!("{}…{}", &address[..10], &address[address.len() - 8..]);
+        mono(&mut svg, top + 16.5, 2.4, &short);
+        text(
+            &mut svg,
+            top + 21.0,
+            2.2,
+            "normal",
+            "Scan for the full address",
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// the address in short lines, `.onion` and the port on a line of their own
+fn address_lines(host: &str) -> Vec<String> {
+    let (name, rest) = host.split_at(host.find('.').unwrap_or(host.len()));
+    let mut lines: Vec<String> = name
+        .as_bytes()
+        .chunks(ADDRESS_LINE)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect();
+    lines.push(rest.to_string());
+    lines
+}
+
+/// cut line around a card starting at `top`
+fn card_outline(svg: &mut String, top: f32) {
+    let _ = writeln!(
+        svg,
+        r##"<rect x="0.1" y="{}" width="{}" height="{}" rx="3" fill="#ffffff" stroke="#bbbbbb" stroke-width="0.2"/>"##,
+        top + 0.1,
+        CARD_WIDTH - 0.2,
+        CARD_HEIGHT - 0.2
+    );
+}
+
+/// `qr` filling the left of a card starting at `top`
+fn qr_at(svg: &mut String, qr: &Qr, top: f32) {
+    let side = CARD_HEIGHT - 8.0;
+    let _ = writeln!(
+        svg,
+        r##"<g transform="translate(4 {}) scale({})" shape-rendering="crispEdges"><path d="{}" fill="#000000"/></g>"##,
+        top + 4.0,
+        side / qr.size() as f32,
+        qr.svg_path()
+    );
+}
+
+/// line of text in the right column
+fn text(svg: &mut String, y: f32, size: f32, weight: &str, content: &str) {
+    let _ = writeln!(
+        svg,
+        r##"<text x="55" y="{}" font-size="{}" font-weight="{}" fill="#000000">{}</text>"##,
+        y,
+        size,
+        weight,
+        escape(content)
+    );
+}
+
+/// line of monospaced text in the right column, for addresses and words
+fn mono(svg: &mut String, y: f32, size: f32, content: &str) {
+    let _ = writeln!(
+        svg,
+        r##"<text x="55" y="{}" font-size="{}" font-family="Courier New, monospace" fill="#000000">{}</text>"##,
+        y,
+        size,
+        escape(content)
+    );
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONION: &str = "http://fakefakefakefakefakefakefakefakefakefakefakefakefakefake.onion:80";
+    const RAILGUN: &str = "0zk1qyk9nn5zvjwprfv2qe6cdmzk4wwvvz7w3qjk8xrandom3example8address4mock";
+
+    #[test]
+    fn onion_urls_are_checked_and_cleaned_up() {
+        let host = "fakefakefakefakefakefakefakefakefakefakefakefakefakefake.onion";
+        assert_eq!(onion_host(ONION).unwrap(), host);
+        assert_eq!(onion_host(&ONION.to_uppercase()).unwrap(), host);
+        assert_eq!(
+            onion_host(&format!("http://{}:8080/", host)).unwrap(),
+            format!("{}:8080", host)
+        );
+        assert!(onion_host("http://example.com").is_err());
+        assert!(onion_host("http://fakefake.onion").is_err());
+        assert!(onion_host(&ONION.replace("fake.onion", "fak1.onion")).is_err());
+    }
+
+    #[test]
+    fn one_changed_character_changes_the_words() {
+        let host = onion_host(ONION).unwrap();
+        let words = checksum_words(&host);
+        assert_eq!(words.len(), words::CHECKSUM_WORDS);
+        assert_eq!(words, checksum_words(&host.to_uppercase()));
+        assert_ne!(words, checksum_words(&host.replacen('f', "g", 1)));
+    }
+
+    #[test]
+    fn writes_qr_codes_and_a_card() {
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("card");
+        let report = write_card(
+            &destination,
+            &Card {
+                onion_url: ONION,
+                title: Some("Notes & <Lies>"),
+                railgun_address: Some(RAILGUN),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            report.onion_url,
+            "http://fakefakefakefakefakefakefakefakefakefakefakefakefakefake.onion"
+        );
+        assert_eq!(report.files.len(), 5);
+        for file in &report.files {
+            assert!(Path::new(file).is_file(), "{} is missing", file);
+        }
+
+        let qr = Qr::encode(&report.onion_url).unwrap();
+        let png = fs::read(destination.join("onion-qr.png")).unwrap();
+        let info = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(info.info().width as usize, qr.size() * PNG_SCALE);
+        assert_eq!(report.qr_svg, qr.to_svg(SVG_PIXELS));
+
+        let card = fs::read_to_string(destination.join("card.svg")).unwrap();
+        assert!(card.contains("Notes &amp; &lt;Lies&gt;"));
+        assert!(card.contains(">fakefakefakefa<"));
+        assert!(card.contains(">.onion<"));
+        assert!(card.contains(&report.checksum_words[..2].join(" ")));
+        assert!(card.contains("0zk1qyk9nn…ess4mock"));
+    }
+
+    #[test]
+    fn no_railgun_means_no_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = Card {
+            onion_url: ONION,
+            title: None,
+            railgun_address: None,
+        };
+        let report = write_card(dir.path(), &card).unwrap();
+        assert_eq!(report.files.len(), 3);
+        let svg = fs::read_to_string(dir.path().join("card.svg")).unwrap();
+        assert!(svg.contains(r#"height="55mm""#));
+        assert!(!svg.contains("Tips"));
+
+        let card = Card {
+            railgun_address: Some("0x1234"),
+            ..card
+        };
+        assert!(write_card(dir.path(), &card).is_err());
+    }
+}
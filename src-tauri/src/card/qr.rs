@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use qrcode::{Color, EcLevel, QrCode};
+use std::fmt::Write;
+
+/// light modules around the code, scanners need them to find it
+const QUIET_ZONE: usize = 4;
+
+/// A QR code as a square of dark and light modules
+pub struct Qr {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl Qr {
+    /// Encode `text`, with enough error correction for a creased or smudged card
+    pub fn encode(text: &str) -> Result<Self> {
+        let code = QrCode::with_error_correction_level(text, EcLevel::M)
+            .with_context(|| format!("Failed to encode {} characters as a QR code", text.len()))?;
+        Ok(Self {
+            width: code.width(),
+            dark: code
+                .to_colors()
+                .into_iter()
+                .map(|color| color == Color::Dark)
+                .collect(),
+        })
+    }
+
+    /// side length in modules, quiet zone included
+    pub fn size(&self) -> usize {
+        self.width + 2 * QUIET_ZONE
+    }
+
+    fn dark_modules(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.dark
+            .iter()
+            .enumerate()
+            .filter(|(_, dark)| **dark)
+            .map(|(i, _)| (i % self.width + QUIET_ZONE, i / self.width + QUIET_ZONE))
+    }
+
+    /// SVG path data for the dark modules, one unit per module
+    pub fn svg_path(&self) -> String {
+        let mut path = String::new();
+        for (x, y) in self.dark_modules() {
+            let _ = write!(path, "M{},{}h1v1h-1z", x, y);
+        }
+        path
+    }
+
+    /// Standalone SVG `pixels` wide, it scales without getting blurry
+    pub fn to_svg(&self, pixels: usize) -> String {
+        let size = self.size();
+        format!(
+            r##"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="{pixels}" height="{pixels}" viewBox="0 0 {size} {size}" shape-rendering="crispEdges">
+<rect width="{size}" height="{size}" fill="#ffffff"/>
+<path d="{path}" fill="#000000"/>
+</svg>
+"##,
+            pixels = pixels,
+            size = size,
+            path = self.svg_path()
+        )
+    }
+
+    /// Grayscale PNG with `scale` pixels per module
+    pub fn to_png(&self, scale: usize) -> Result<Vec<u8>> {
+        let side = self.size() * scale;
+        let mut pixels = vec![255u8; side * side];
+        for (x, y) in self.dark_modules() {
+            for row in y * scale..(y + 1) * scale {
+                pixels[row * side + x * scale..row * side + (x + 1) * scale].fill(0);
+            }
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(png)
+    }
+}
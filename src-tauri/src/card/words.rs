@@ -0,0 +1,48 @@
+use sha2::{Digest, Sha256};
+
+/// words a card lists under the address
+pub const CHECKSUM_WORDS: usize = 8;
+
+/// one word per byte value, short and easy to tell apart when read out
+const WORDS: [&str; 256] = [
+    "acorn", "adult", "agent", "album", "alley", "amber", "anchor", "angle", "ankle", "apple",
+    "apron", "arch", "arena", "armor", "arrow", "aspen", "atlas", "attic", "autumn", "avenue",
+    "badge", "bagel", "baker", "bamboo", "banjo", "barley", "barrel", "basil", "basket", "beacon",
+    "beaver", "bench", "berry", "bison", "blanket", "blossom", "border", "bottle", "bramble",
+    "bridge", "brook", "bucket", "bundle", "butter", "cabin", "cactus", "camel", "candle", "canoe",
+    "canvas", "canyon", "carbon", "carpet", "carrot", "castle", "cedar", "cellar", "cement",
+    "cherry", "cider", "cinema", "circus", "citrus", "clover", "cobalt", "cocoa", "comet",
+    "copper", "coral", "cotton", "cougar", "cradle", "crater", "cricket", "crystal", "cup",
+    "curtain", "dagger", "daisy", "dancer", "delta", "desert", "dial", "diamond", "dinner",
+    "dolphin", "donkey", "dragon", "drum", "eagle", "easel", "echo", "elbow", "ember", "engine",
+    "fabric", "falcon", "feather", "fence", "ferry", "fiddle", "finch", "flute", "forest",
+    "fossil", "fox", "galaxy", "garden", "garlic", "gazelle", "geyser", "ginger", "glacier",
+    "globe", "goblet", "gopher", "granite", "grape", "gravel", "guitar", "hammer", "harbor",
+    "harvest", "hazel", "helmet", "hermit", "heron", "honey", "hornet", "husky", "igloo", "indigo",
+    "insect", "iris", "island", "ivory", "jacket", "jaguar", "jelly", "jigsaw", "jungle", "kayak",
+    "kernel", "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "lemon", "lentil", "lilac",
+    "lizard", "lobster", "locket", "lotus", "lumber", "magnet", "mango", "maple", "marble",
+    "meadow", "melon", "meteor", "mitten", "monkey", "mosaic", "muffin", "mustard", "napkin",
+    "nectar", "needle", "nickel", "noodle", "nutmeg", "oasis", "ocean", "olive", "onion", "orange",
+    "orbit", "orchid", "otter", "oyster", "paddle", "pancake", "panther", "papaya", "parrot",
+    "peach", "peanut", "pebble", "pelican", "pepper", "piano", "pickle", "pigeon", "pillow",
+    "pilot", "pine", "planet", "plum", "pocket", "pony", "poppy", "potato", "pumpkin", "puzzle",
+    "quartz", "quill", "rabbit", "radish", "raven", "reef", "ribbon", "river", "robin", "rocket",
+    "rose", "saddle", "salmon", "sandal", "satin", "scarf", "shovel", "silver", "skate", "sparrow",
+    "spider", "stable", "summit", "sunset", "swan", "tablet", "tango", "teapot", "thunder",
+    "tiger", "timber", "toast", "tomato", "torch", "tulip", "tunnel", "turnip", "turtle", "valley",
+    "velvet", "violin", "wagon", "walnut", "walrus", "wheat", "whistle", "willow", "window",
+];
+
+/// Words derived from `onion_host`, for checking a copy of the address
+///
+/// They come from a hash of the address, so a single wrong character gives
+/// different words. This catches typos and lookalike addresses someone did
+/// not bother to grind for, it is not a replacement for the whole address.
+pub fn checksum_words(onion_host: &str) -> Vec<&'static str> {
+    let digest = Sha256::digest(onion_host.to_ascii_lowercase().as_bytes());
+    digest[..CHECKSUM_WORDS]
+        .iter()
+        .map(|byte| WORDS[*byte as usize])
+        .collect()
+}
@@ -0,0 +1,418 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use std::io::{self, Read, Write};
+
+/// size of a plaintext chunk in the encrypted stream
+const CHUNK_SIZE: usize = 64 * 1024;
+/// size of the Poly1305 tag appended to every chunk
+const TAG_SIZE: usize = 16;
+/// length of the Argon2 salt stored in the header
+pub const SALT_SIZE: usize = 16;
+/// length of the STREAM nonce prefix (24 byte XChaCha nonce minus 5 byte counter)
+pub const NONCE_SIZE: usize = 19;
+/// length of a full XChaCha20 nonce used by [`seal`]
+const XNONCE_SIZE: usize = 24;
+/// format version written after the magic
+const FORMAT_VERSION: u8 = 1;
+
+/// a 256-bit symmetric key, wiped on drop
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+    /// generate a fresh random key
+    pub fn random() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// wrap raw key bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// get the raw key bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        // volatile writes so the compiler can't elide the wipe
+        for byte in self.0.iter_mut() {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// generate a random salt for passphrase derivation
+pub fn random_salt() -> [u8; SALT_SIZE] {
+    let mut salt = [0u8; SALT_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// derive a key from a passphrase using Argon2id (64 MiB, 3 passes)
+pub fn derive_key(passphrase: &str, salt: &[u8; SALT_SIZE]) -> Result<SecretKey> {
+    if passphrase.is_empty() {
+        bail!("Passphrase must not be empty");
+    }
+
+    let params = Params::new(64 * 1024, 3, 1, Some(32))
+        .map_err(|e| anyhow!("Invalid Argon2 params: {}", e))?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+    let mut key = [0u8; 32];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive key: {}", e))?;
+
+    Ok(SecretKey(key))
+}
+
+/// encrypt a small message, returns `nonce || ciphertext || tag`
+pub fn seal(key: &SecretKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; XNONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(key.as_bytes().into());
+    let ciphertext = cipher
+        .encrypt(&XNonce::from(nonce), plaintext)
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// decrypt a message produced by [`seal`]
+pub fn open(key: &SecretKey, sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < XNONCE_SIZE + TAG_SIZE {
+        bail!("Encrypted message is too short");
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(XNONCE_SIZE);
+    let nonce: [u8; XNONCE_SIZE] = nonce.try_into()?;
+    let cipher = XChaCha20Poly1305::new(key.as_bytes().into());
+    cipher
+        .decrypt(&XNonce::from(nonce), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed (wrong key or corrupted data)"))
+}
+
+/// size overhead added by [`seal`]
+pub const SEAL_OVERHEAD: usize = XNONCE_SIZE + TAG_SIZE;
+
+/// random nonce prefix for a headerless stream
+pub fn random_stream_nonce() -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+/// streaming XChaCha20-Poly1305 writer
+///
+/// The output starts with `magic || version || salt || nonce`, followed by
+/// authenticated 64 KiB chunks. The final chunk is tagged as last, so
+/// truncation is detected on decryption. Call [`EncryptWriter::finish`]
+/// to flush the final chunk.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// start an encrypted stream keyed by a passphrase
+    pub fn with_passphrase(inner: W, magic: &[u8; 8], passphrase: &str) -> Result<Self> {
+        let salt = random_salt();
+        let key = derive_key(passphrase, &salt)?;
+        Self::new(inner, magic, &key, salt)
+    }
+
+    /// start an encrypted stream, writing the header to `inner`
+    ///
+    /// `salt` is stored in the header so the reader can re-derive the key,
+    /// pass a random one when the key does not come from a passphrase.
+    pub fn new(
+        mut inner: W,
+        magic: &[u8; 8],
+        key: &SecretKey,
+        salt: [u8; SALT_SIZE],
+    ) -> Result<Self> {
+        let nonce = random_stream_nonce();
+
+        inner.write_all(magic)?;
+        inner.write_all(&[FORMAT_VERSION])?;
+        inner.write_all(&salt)?;
+        inner.write_all(&nonce)?;
+
+        Ok(Self::headerless(inner, key, &nonce))
+    }
+
+    /// start a stream without any plaintext header
+    ///
+    /// For containers where the output must be indistinguishable from
+    /// random data, the caller stores the nonce somewhere encrypted.
+    pub fn headerless(inner: W, key: &SecretKey, nonce: &[u8; NONCE_SIZE]) -> Self {
+        let aead = XChaCha20Poly1305::new(key.as_bytes().into());
+        let encryptor = EncryptorBE32::from_aead(aead, nonce.as_slice().into());
+
+        Self {
+            inner,
+            encryptor: Some(encryptor),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// encrypt the final chunk and return the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        let encryptor = self
+            .encryptor
+            .take()
+            .context("Encrypted stream already finished")?;
+        let chunk = encryptor
+            .encrypt_last(self.buffer.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt final chunk"))?;
+        self.inner.write_all(&chunk)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let encryptor = self
+            .encryptor
+            .as_mut()
+            .ok_or_else(|| io::Error::other("encrypted stream already finished"))?;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // only flush a full chunk once we know more data follows it,
+        // the last chunk must go through `encrypt_last`
+        if self.buffer.len() == CHUNK_SIZE {
+            let chunk = encryptor
+                .encrypt_next(self.buffer.as_slice())
+                .map_err(|_| io::Error::other("failed to encrypt chunk"))?;
+            self.inner.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+
+        let take = (CHUNK_SIZE - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..take]);
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// streaming reader for data produced by [`EncryptWriter`]
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// decrypted bytes not yet handed out
+    plain: Vec<u8>,
+    plain_pos: usize,
+    /// one byte of lookahead used to detect the final chunk
+    carry: Option<u8>,
+    finished: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// open a stream written by [`EncryptWriter::with_passphrase`]
+    pub fn with_passphrase(inner: R, magic: &[u8; 8], passphrase: &str) -> Result<Self> {
+        Self::new(inner, magic, |salt| derive_key(passphrase, salt))
+    }
+
+    /// read and check the header, then prepare to decrypt
+    ///
+    /// `key_for_salt` receives the salt from the header and returns the key.
+    pub fn new(
+        mut inner: R,
+        magic: &[u8; 8],
+        key_for_salt: impl FnOnce(&[u8; SALT_SIZE]) -> Result<SecretKey>,
+    ) -> Result<Self> {
+        let mut header = [0u8; 8 + 1 + SALT_SIZE + NONCE_SIZE];
+        inner
+            .read_exact(&mut header)
+            .context("File is too short to be an encrypted archive")?;
+
+        if &header[..8] != magic {
+            bail!("Not a freedom-kit encrypted file (bad magic)");
+        }
+        if header[8] != FORMAT_VERSION {
+            bail!("Unsupported format version {}", header[8]);
+        }
+
+        let mut salt = [0u8; SALT_SIZE];
+        salt.copy_from_slice(&header[9..9 + SALT_SIZE]);
+        let key = key_for_salt(&salt)?;
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&header[9 + SALT_SIZE..]);
+        Ok(Self::headerless(inner, &key, &nonce))
+    }
+
+    /// read a stream written by [`EncryptWriter::headerless`]
+    ///
+    /// `inner` must end exactly where the stream ends (use `Read::take`).
+    pub fn headerless(inner: R, key: &SecretKey, nonce: &[u8; NONCE_SIZE]) -> Self {
+        let aead = XChaCha20Poly1305::new(key.as_bytes().into());
+        let decryptor = DecryptorBE32::from_aead(aead, nonce.as_slice().into());
+
+        Self {
+            inner,
+            decryptor: Some(decryptor),
+            plain: Vec::new(),
+            plain_pos: 0,
+            carry: None,
+            finished: false,
+        }
+    }
+
+    /// decrypt the next chunk into the plaintext buffer
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE + 1);
+        if let Some(byte) = self.carry.take() {
+            chunk.push(byte);
+        }
+
+        // read one byte past a full chunk to learn whether this one is last
+        let want = CHUNK_SIZE + TAG_SIZE + 1;
+        let mut limited = (&mut self.inner).take((want - chunk.len()) as u64);
+        limited.read_to_end(&mut chunk)?;
+
+        let is_last = chunk.len() < want;
+        if !is_last {
+            self.carry = chunk.pop();
+        }
+
+        let plain = if is_last {
+            self.finished = true;
+            let decryptor = self
+                .decryptor
+                .take()
+                .ok_or_else(|| io::Error::other("encrypted stream already finished"))?;
+            decryptor.decrypt_last(chunk.as_slice())
+        } else {
+            let decryptor = self
+                .decryptor
+                .as_mut()
+                .ok_or_else(|| io::Error::other("encrypted stream already finished"))?;
+            decryptor.decrypt_next(chunk.as_slice())
+        };
+
+        self.plain = plain.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decryption failed (wrong passphrase or corrupted data)",
+            )
+        })?;
+        self.plain_pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.plain_pos >= self.plain.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let available = &self.plain[self.plain_pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.plain_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: &[u8; 8] = b"FKTEST01";
+
+    /// more than two chunks, the last one partial
+    fn plaintext() -> Vec<u8> {
+        (0..CHUNK_SIZE * 2 + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn encrypt(key: &SecretKey, plain: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptWriter::new(Vec::new(), MAGIC, key, random_salt()).unwrap();
+        writer.write_all(plain).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(key: &SecretKey, sealed: &[u8]) -> Result<Vec<u8>> {
+        let key = SecretKey::from_bytes(*key.as_bytes());
+        let mut reader = DecryptReader::new(sealed, MAGIC, |_| Ok(key))?;
+        let mut plain = Vec::new();
+        reader.read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = SecretKey::random();
+        for plain in [Vec::new(), b"short".to_vec(), plaintext()] {
+            assert_eq!(decrypt(&key, &encrypt(&key, &plain)).unwrap(), plain);
+        }
+
+        let sealed = seal(&key, b"header").unwrap();
+        assert_eq!(open(&key, &sealed).unwrap(), b"header");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_refused() {
+        let mut writer = EncryptWriter::with_passphrase(Vec::new(), MAGIC, "right").unwrap();
+        writer.write_all(b"secret").unwrap();
+        let sealed = writer.finish().unwrap();
+
+        let mut plain = Vec::new();
+        let mut reader = DecryptReader::with_passphrase(sealed.as_slice(), MAGIC, "right").unwrap();
+        reader.read_to_end(&mut plain).unwrap();
+        assert_eq!(plain, b"secret");
+        let mut reader = DecryptReader::with_passphrase(sealed.as_slice(), MAGIC, "wrong").unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn tampered_chunk_is_refused() {
+        let key = SecretKey::random();
+        let mut sealed = encrypt(&key, &plaintext());
+        // a byte in the second chunk
+        let at = sealed.len() - CHUNK_SIZE;
+        sealed[at] ^= 1;
+        assert!(decrypt(&key, &sealed).is_err());
+
+        let mut header = seal(&key, b"header").unwrap();
+        header[XNONCE_SIZE] ^= 1;
+        assert!(open(&key, &header).is_err());
+    }
+
+    #[test]
+    fn truncated_stream_is_refused() {
+        let key = SecretKey::random();
+        let sealed = encrypt(&key, &plaintext());
+        let header = 8 + 1 + SALT_SIZE + NONCE_SIZE;
+        // cut inside the last chunk and right after a full one
+        for len in [sealed.len() - 10, header + 2 * (CHUNK_SIZE + TAG_SIZE)] {
+            assert!(decrypt(&key, &sealed[..len]).is_err(), "cut at {}", len);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use crate::hardening::HardeningReport;
+use crate::logging::{self, LogEntry};
+use crate::paths::AppPaths;
+use crate::settings::Settings;
+use crate::tor::TorMetrics;
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::info;
+
+/// log entries put in a bundle
+pub const LOG_ENTRIES: usize = 2000;
+/// folder the files sit in inside the ZIP
+const BUNDLE_DIR: &str = "freedom-kit-diagnostics";
+
+/// versions of the app and what it runs on
+#[derive(Serialize, Debug)]
+pub struct Versions {
+    pub app: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// the bundled Ghost, when it has been extracted
+    pub ghost: Option<String>,
+}
+
+impl Versions {
+    pub fn collect(paths: &AppPaths) -> Self {
+        let ghost = fs::read_to_string(paths.ghost_dir.join("package.json"))
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+            .and_then(|package| package.get("version")?.as_str().map(str::to_string));
+        Self {
+            app: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            ghost,
+        }
+    }
+}
+
+/// how a sidecar process ended
+#[derive(Serialize, Clone, Debug)]
+pub struct SidecarExit {
+    pub sidecar: String,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    /// RFC 3339, UTC
+    pub time: String,
+}
+
+impl SidecarExit {
+    pub fn now(sidecar: &str, code: Option<i32>, signal: Option<i32>) -> Self {
+        Self {
+            sidecar: sidecar.to_string(),
+            code,
+            signal,
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+/// the state of the app when the bundle was made
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub created_at: String,
+    pub versions: Versions,
+    pub tor: TorMetrics,
+    pub storage: HardeningReport,
+    pub sidecar_exits: Vec<SidecarExit>,
+}
+
+/// what was written
+#[derive(Serialize, Debug)]
+pub struct DiagnosticsReport {
+    pub path: String,
+    pub files: Vec<String>,
+    pub log_entries: usize,
+}
+
+/// Write a ZIP for a bug report to `destination`
+///
+/// Holds `report`, the settings, the recent `logs` and Ghost's config, all
+/// scrubbed with [`scrub`]. Nothing is sent anywhere, the user decides
+/// whether and where to share the file.
+pub fn export(
+    destination: &Path,
+    report: &Report,
+    settings: &Settings,
+    logs: &[LogEntry],
+    ghost_config: &Path,
+) -> Result<DiagnosticsReport> {
+    let mut files: Vec<(&str, Vec<u8>)> = vec![
+        ("report.json", to_json(report)?),
+        ("settings.json", to_json(&scrub_settings(settings)?)?),
+    ];
+
+    let mut lines = Vec::new();
+    for entry in logs {
+        let entry = LogEntry {
+            message: scrub(&entry.message),
+            ..entry.clone()
+        };
+        serde_json::to_writer(&mut lines, &entry)?;
+        lines.push(b'\n');
+    }
+    files.push(("logs.jsonl", lines));
+
+    if let Ok(raw) = fs::read_to_string(ghost_config) {
+        files.push(("ghost-config.json", scrub_ghost_config(&raw)?));
+    }
+
+    let tmp = destination.with_extension("zip.tmp");
+    let mut zip = zip::ZipWriter::new(BufWriter::new(
+        File::create(&tmp).with_context(|| format!("Failed to create {:?}", tmp))?,
+    ));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    for (name, contents) in &files {
+        zip.start_file(format!("{}/{}", BUNDLE_DIR, name), options)?;
+        zip.write_all(contents)?;
+    }
+    zip.finish()?.flush()?;
+    fs::rename(&tmp, destination).with_context(|| format!("Failed to write {:?}", destination))?;
+
+    info!("diagnostics bundle written with {} log entries", logs.len());
+    Ok(DiagnosticsReport {
+        path: destination.to_string_lossy().into_owned(),
+        files: files.iter().map(|(name, _)| name.to_string()).collect(),
+        log_entries: logs.len(),
+    })
+}
+
+/// pretty JSON with every string scrubbed
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut value = serde_json::to_value(value)?;
+    scrub_json(&mut value);
+    Ok(serde_json::to_vec_pretty(&value)?)
+}
+
+/// Ghost's config, it spells out the user's home in its paths
+///
+/// Strings are scrubbed once they are unescaped, a config that does not
+/// parse is scrubbed as text.
+fn scrub_ghost_config(raw: &str) -> Result<Vec<u8>> {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(config) => to_json(&config),
+        Err(_) => Ok(scrub(raw).into_bytes()),
+    }
+}
+
+/// The settings, bridges reduced to their transport
+///
+/// Bridge lines are handed out one by one and are themselves a secret.
+fn scrub_settings(settings: &Settings) -> Result<Value> {
+    let mut value = serde_json::to_value(settings)?;
+    if let Some(Value::Array(bridges)) = value.pointer_mut("/tor/bridges") {
+        for bridge in bridges.iter_mut() {
+            let transport = bridge
+                .as_str()
+                .and_then(|line| line.split_whitespace().next())
+                .filter(|first| first.chars().all(|c| c.is_ascii_alphanumeric()))
+                .map(|transport| format!("{} ", transport))
+                .unwrap_or_default();
+            *bridge = Value::String(format!("{}[bridge]", transport));
+        }
+    }
+    Ok(value)
+}
+
+fn scrub_json(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = scrub(text),
+        Value::Array(items) => items.iter_mut().for_each(scrub_json),
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                let secret = logging::is_secret_name(name) || name.eq_ignore_ascii_case("pass");
+                if secret && !field.is_null() && !field.is_boolean() {
+                    *field = Value::String("[redacted]".to_string());
+                } else {
+                    scrub_json(field);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Remove what identifies the user from text
+///
+/// On top of [`logging::redact`] (home directory, onion addresses and
+/// secrets), IP addresses other than loopback become `[ip]` and Railgun
+/// `0zk` addresses become `[0zk]`.
+pub fn scrub(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric() || c == ':') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == ':'))
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        out.push_str(&scrub_address(word));
+        rest = after;
+    }
+    out.push_str(rest);
+    logging::redact(&out)
+}
+
+/// `word` with an IP or `0zk` address in it replaced, a port is kept
+fn scrub_address(word: &str) -> String {
+    if word.starts_with("0zk") && word.len() >= 40 {
+        return "[0zk]".to_string();
+    }
+
+    // the end of a sentence, or the port after an IPv4 address
+    let (word, dot) = match word.strip_suffix('.') {
+        Some(word) => (word, "."),
+        None => (word, ""),
+    };
+    let (host, port) = match word.parse::<IpAddr>() {
+        Ok(_) => (word, ""),
+        Err(_) => match word.rsplit_once(':') {
+            Some((host, _)) if host.parse::<std::net::Ipv4Addr>().is_ok() => {
+                (host, &word[host.len()..])
+            }
+            _ => return format!("{}{}", word, dot),
+        },
+    };
+    match host.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_loopback() && !ip.is_unspecified() => format!("[ip]{}{}", port, dot),
+        _ => format!("{}{}", word, dot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+
+    #[test]
+    fn scrubs_onion_addresses() {
+        assert_eq!(
+            scrub(&format!("published at http://{}.onion/about/", ONION)),
+            "published at http://[onion]/about/"
+        );
+    }
+
+    #[test]
+    fn scrubs_ip_addresses_but_not_loopback() {
+        assert_eq!(
+            scrub("guard 203.0.113.7:9001 failed."),
+            "guard [ip]:9001 failed."
+        );
+        assert_eq!(scrub("resolved 203.0.113.7."), "resolved [ip].");
+        assert_eq!(
+            scrub("connecting to [2001:db8::1]:443"),
+            "connecting to [[ip]]:443"
+        );
+        assert_eq!(scrub("bound 2001:db8::1"), "bound [ip]");
+        assert_eq!(
+            scrub("ghost on 127.0.0.1:2368 and [::1]:2368, listening on 0.0.0.0"),
+            "ghost on 127.0.0.1:2368 and [::1]:2368, listening on 0.0.0.0"
+        );
+        assert_eq!(scrub("at 12:30 ghost 5.0.1"), "at 12:30 ghost 5.0.1");
+    }
+
+    #[test]
+    fn scrubs_0zk_addresses() {
+        let address = format!(
+            "0zk1q{}",
+            "8hxknrs97q8pjxaagwthzc0df99rzmhl2xnlxmgv9akv32sua0kf".repeat(2)
+        );
+        assert_eq!(scrub_address(&address), "[0zk]");
+        assert_eq!(
+            scrub(&format!("shielded to {}, done", address)),
+            "shielded to [0zk], done"
+        );
+        assert_eq!(scrub_address("0zk"), "0zk");
+    }
+
+    #[test]
+    fn scrubs_home_in_ghost_config() {
+        let Some(home) = dirs::home_dir().filter(|home| home.as_os_str().len() > 1) else {
+            return;
+        };
+        let content = home.join(".ghost-freedom-kit").join("content");
+        let raw = serde_json::json!({
+            "url": "http://localhost:2368/",
+            "paths": { "contentPath": content },
+            "database": { "connection": { "password": "hunter2" } },
+        })
+        .to_string();
+        let home = home.to_string_lossy().into_owned();
+
+        let scrubbed = String::from_utf8(scrub_ghost_config(&raw).unwrap()).unwrap();
+        let config: Value = serde_json::from_str(&scrubbed).unwrap();
+        let expected = std::path::Path::new("~")
+            .join(".ghost-freedom-kit")
+            .join("content");
+        assert_eq!(
+            config.pointer("/paths/contentPath").unwrap(),
+            &Value::String(expected.to_string_lossy().into_owned())
+        );
+        assert_eq!(
+            config.pointer("/database/connection/password").unwrap(),
+            "[redacted]"
+        );
+        assert!(!scrubbed.contains(&home));
+
+        // a broken config is still scrubbed as text
+        let broken = format!("{{\"paths\": {{\"contentPath\": \"{}/content\"", home);
+        let scrubbed = String::from_utf8(scrub_ghost_config(&broken).unwrap()).unwrap();
+        assert!(!scrubbed.contains(&home));
+    }
+
+    #[test]
+    fn reduces_bridges_to_their_transport() {
+        let mut settings = Settings::default();
+        settings.tor.bridges = vec![
+            format!("obfs4 192.0.2.3:443 {} cert=abc iat-mode=0", "A".repeat(40)),
+            format!("192.0.2.4:9001 {}", "B".repeat(40)),
+            "snowflake 192.0.2.5:80 url=https://snowflake.example/".to_string(),
+        ];
+        let value = scrub_settings(&settings).unwrap();
+        assert_eq!(
+            value.pointer("/tor/bridges").unwrap(),
+            &serde_json::json!(["obfs4 [bridge]", "[bridge]", "snowflake [bridge]"])
+        );
+    }
+}
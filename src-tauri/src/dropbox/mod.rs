@@ -0,0 +1,297 @@
+pub mod server;
+pub mod strip;
+
+use crate::crypto::{self, DecryptReader, EncryptWriter, SecretKey};
+use crate::paths::AppPaths;
+use crate::wipe;
+use anyhow::{bail, Context, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use strip::Kind;
+use tracing::{info, warn};
+
+pub use server::{DropBoxServer, DROPBOX_PORT};
+
+/// magic at the start of every encrypted submitted file
+const FILE_MAGIC: &[u8; 8] = b"FKDROPBX";
+/// encrypted description of a submission, next to its files
+const SUBMISSION_FILE: &str = "submission";
+/// submissions still being received live here until they are complete
+const INCOMING_DIR: &str = ".incoming";
+
+/// one upload from a source
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Submission {
+    pub id: String,
+    pub received_at: u64,
+    pub message: Option<String>,
+    pub files: Vec<SubmittedFile>,
+}
+
+/// a file inside a submission
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubmittedFile {
+    /// name the source's browser sent, reduced to a safe file name
+    pub name: String,
+    /// type detected from the content, `None` when unknown
+    pub content_type: Option<String>,
+    pub size: u64,
+    /// whether identifying metadata was removed on receipt
+    pub metadata_stripped: bool,
+}
+
+/// Submissions kept encrypted with a key that never leaves the app
+///
+/// The key sits in the app data dir (inside the encrypted volume when one
+/// is used) and is destroyed by every panic wipe, which makes the stored
+/// submissions unreadable.
+pub struct DropBox {
+    dir: PathBuf,
+    key: SecretKey,
+}
+
+impl DropBox {
+    /// open the drop box, creating its key on first use
+    pub fn open(paths: &AppPaths) -> Result<Self> {
+        let dir = paths.dropbox_dir();
+        create_private_dir(&dir)?;
+        let key_file = paths.dropbox_key();
+
+        let key = match fs::read(&key_file) {
+            Ok(raw) => {
+                let bytes: [u8; 32] = raw
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Drop box key {:?} is corrupt", key_file))?;
+                SecretKey::from_bytes(bytes)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = SecretKey::random();
+                write_private(&key_file, key.as_bytes())?;
+                info!("created a new drop box key");
+                key
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", key_file)),
+        };
+
+        Ok(Self { dir, key })
+    }
+
+    /// every complete submission, newest first
+    pub fn list(&self) -> Result<Vec<Submission>> {
+        let mut submissions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_name() == INCOMING_DIR || !entry.file_type()?.is_dir() {
+                continue;
+            }
+            match self.load(&entry.path()) {
+                Ok(submission) => submissions.push(submission),
+                Err(e) => warn!("unreadable submission {:?}: {:#}", entry.path(), e),
+            }
+        }
+        submissions.sort_by_key(|s| std::cmp::Reverse(s.received_at));
+        Ok(submissions)
+    }
+
+    /// decrypt file `index` of a submission to `destination`
+    pub fn save_file(&self, id: &str, index: usize, destination: &Path) -> Result<()> {
+        let dir = self.submission_dir(id)?;
+        let submission = self.load(&dir)?;
+        if index >= submission.files.len() {
+            bail!("Submission {} has no file {}", id, index);
+        }
+
+        let mut reader = self.open_file(&dir.join(index.to_string()))?;
+        let mut out = BufWriter::new(
+            File::create(destination)
+                .with_context(|| format!("Failed to create {:?}", destination))?,
+        );
+        io::copy(&mut reader, &mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// shred a submission
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let dir = self.submission_dir(id)?;
+        let report = wipe::shred_tree(&dir);
+        if !report.failed.is_empty() {
+            bail!("Failed to delete {}", report.failed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// start receiving a new submission
+    fn begin(self: &Arc<Self>) -> Result<Incoming> {
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = hex::encode(id);
+
+        let dir = self.dir.join(INCOMING_DIR).join(&id);
+        create_private_dir(&dir)?;
+        Ok(Incoming {
+            dropbox: self.clone(),
+            id,
+            dir,
+            files: Vec::new(),
+            finished: false,
+        })
+    }
+
+    fn submission_dir(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid submission id");
+        }
+        let dir = self.dir.join(id);
+        if !dir.is_dir() {
+            bail!("No submission {}", id);
+        }
+        Ok(dir)
+    }
+
+    fn load(&self, dir: &Path) -> Result<Submission> {
+        let sealed = fs::read(dir.join(SUBMISSION_FILE))?;
+        let raw = crypto::open(&self.key, &sealed)?;
+        Ok(serde_json::from_slice(&raw)?)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<EncryptWriter<BufWriter<File>>> {
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        EncryptWriter::new(
+            BufWriter::new(file),
+            FILE_MAGIC,
+            &self.key,
+            crypto::random_salt(),
+        )
+    }
+
+    fn open_file(&self, path: &Path) -> Result<DecryptReader<BufReader<File>>> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        DecryptReader::new(BufReader::new(file), FILE_MAGIC, |_| {
+            Ok(SecretKey::from_bytes(*self.key.as_bytes()))
+        })
+    }
+}
+
+/// a submission being received, removed again unless it is finished
+struct Incoming {
+    dropbox: Arc<DropBox>,
+    id: String,
+    dir: PathBuf,
+    files: Vec<SubmittedFile>,
+    finished: bool,
+}
+
+impl Incoming {
+    /// encrypted writer for the next file
+    fn next_file(&self) -> Result<EncryptWriter<BufWriter<File>>> {
+        self.dropbox
+            .create_file(&self.dir.join(self.files.len().to_string()))
+    }
+
+    /// Strip metadata from the received files and store the submission
+    ///
+    /// Files are cleaned in memory, one at a time, so this is bounded by
+    /// the submission size limit.
+    fn finish(mut self, message: Option<String>) -> Result<Submission> {
+        for (index, file) in self.files.iter_mut().enumerate() {
+            let Some(kind) = file.content_type.as_deref().and_then(kind_for_mime) else {
+                continue;
+            };
+            let path = self.dir.join(index.to_string());
+
+            let mut data = Vec::new();
+            self.dropbox.open_file(&path)?.read_to_end(&mut data)?;
+            let cleaned = match strip::strip_metadata(kind, &data) {
+                Ok(cleaned) => cleaned,
+                Err(e) => {
+                    warn!("could not strip metadata from a {}: {:#}", kind.mime(), e);
+                    continue;
+                }
+            };
+
+            let tmp = path.with_extension("tmp");
+            let mut writer = self.dropbox.create_file(&tmp)?;
+            writer.write_all(&cleaned)?;
+            writer.finish()?.flush()?;
+            fs::rename(&tmp, &path)?;
+
+            file.size = cleaned.len() as u64;
+            file.metadata_stripped = true;
+        }
+
+        let submission = Submission {
+            id: self.id.clone(),
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            message,
+            files: std::mem::take(&mut self.files),
+        };
+        let sealed = crypto::seal(&self.dropbox.key, &serde_json::to_vec(&submission)?)?;
+        fs::write(self.dir.join(SUBMISSION_FILE), sealed)?;
+
+        fs::rename(&self.dir, self.dropbox.dir.join(&self.id))
+            .context("Failed to store the submission")?;
+        self.finished = true;
+        Ok(submission)
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        if !self.finished {
+            wipe::shred_tree(&self.dir);
+        }
+    }
+}
+
+fn kind_for_mime(mime: &str) -> Option<Kind> {
+    [Kind::Jpeg, Kind::Png, Kind::WebP, Kind::Pdf]
+        .into_iter()
+        .find(|kind| kind.mime() == mime)
+}
+
+/// reduce a name sent by a browser to a plain file name
+fn safe_file_name(name: Option<&str>) -> String {
+    let name = name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).take(200).collect();
+    match name.trim().trim_start_matches('.') {
+        "" => "upload".to_string(),
+        name => name.to_string(),
+    }
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write {:?}", path))
+}
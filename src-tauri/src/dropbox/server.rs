@@ -0,0 +1,260 @@
+use super::strip::Kind;
+use super::{safe_file_name, DropBox, Submission, SubmittedFile};
+use crate::settings::DropBoxSettings;
+use anyhow::{bail, Result};
+use futures::TryStreamExt;
+use http_body_util::{BodyStream, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use multer::{Constraints, Multipart, SizeLimit};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, error, info, warn};
+
+/// onion port the drop box is served on
+pub const DROPBOX_PORT: u16 = 8081;
+
+/// files accepted in one submission
+const MAX_FILES: usize = 20;
+/// longest message accepted with a submission
+const MAX_MESSAGE_BYTES: u64 = 64 * 1024;
+/// submissions received at the same time
+const CONCURRENT_UPLOADS: usize = 2;
+/// bytes kept from the start of each file to detect its type
+const HEAD_SIZE: usize = 16;
+
+/// upload form, no scripts and nothing loaded from elsewhere
+const FORM_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Drop box</title>
+<style>body{font-family:sans-serif;max-width:36em;margin:3em auto;padding:0 1em}textarea{width:100%;height:8em}</style>
+</head><body>
+<h1>Send files securely</h1>
+<p>Files are encrypted on arrival and identifying metadata (EXIF, PDF properties) is removed from images and PDFs. Other formats are stored as they are.</p>
+<form method="post" enctype="multipart/form-data">
+<p><input type="file" name="file" multiple></p>
+<p><textarea name="message" placeholder="Message (optional)"></textarea></p>
+<p><button type="submit">Send</button></p>
+</form>
+</body></html>
+"#;
+
+const RECEIVED_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Received</title></head>
+<body><h1>Thank you</h1><p>Your submission was received.</p></body></html>
+"#;
+
+/// Receives submissions on the onion service, never routed to Ghost
+pub struct DropBoxServer {
+    dropbox: Arc<DropBox>,
+    max_bytes: u64,
+    max_per_hour: u32,
+    /// when recent submissions started, for the hourly limit
+    recent: Mutex<VecDeque<Instant>>,
+    uploads: Semaphore,
+    /// told about every stored submission
+    received: mpsc::UnboundedSender<Submission>,
+}
+
+impl DropBoxServer {
+    pub fn new(
+        dropbox: DropBox,
+        settings: &DropBoxSettings,
+        received: mpsc::UnboundedSender<Submission>,
+    ) -> Self {
+        Self {
+            dropbox: Arc::new(dropbox),
+            max_bytes: settings.max_submission_mb.saturating_mul(1024 * 1024),
+            max_per_hour: settings.max_per_hour,
+            recent: Mutex::new(VecDeque::new()),
+            uploads: Semaphore::new(CONCURRENT_UPLOADS),
+            received,
+        }
+    }
+
+    /// count a submission against the hourly limit, across all sources
+    fn admit(&self) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+        let now = Instant::now();
+        while recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > Duration::from_secs(3600))
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= self.max_per_hour as usize {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+
+    /// stream the multipart upload into encrypted files
+    async fn receive(&self, req: Request<Incoming>) -> Result<Submission> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let boundary = multer::parse_boundary(content_type)?;
+
+        let body = BodyStream::new(req.into_body())
+            .try_filter_map(|frame| async move { Ok(frame.into_data().ok()) });
+        let constraints = Constraints::new()
+            .allowed_fields(vec!["file", "message"])
+            .size_limit(
+                SizeLimit::new()
+                    .whole_stream(self.max_bytes)
+                    .for_field("message", MAX_MESSAGE_BYTES),
+            );
+        let mut multipart = Multipart::with_constraints(body, boundary, constraints);
+
+        let mut incoming = self.dropbox.begin()?;
+        let mut message = None;
+        while let Some(mut field) = multipart.next_field().await? {
+            let name = field.name().map(str::to_string);
+            match name.as_deref() {
+                Some("message") => {
+                    let text = field.text().await?;
+                    if !text.trim().is_empty() {
+                        message = Some(text);
+                    }
+                }
+                Some("file") => {
+                    // an empty file input still sends a part, without a name
+                    if field.file_name().unwrap_or_default().is_empty() {
+                        continue;
+                    }
+                    if incoming.files.len() >= MAX_FILES {
+                        bail!("More than {} files", MAX_FILES);
+                    }
+
+                    let file_name = safe_file_name(field.file_name());
+                    let mut writer = incoming.next_file()?;
+                    let mut head = Vec::with_capacity(HEAD_SIZE);
+                    let mut size = 0u64;
+                    while let Some(chunk) = field.chunk().await? {
+                        let missing = HEAD_SIZE.saturating_sub(head.len()).min(chunk.len());
+                        head.extend_from_slice(&chunk[..missing]);
+                        writer.write_all(&chunk)?;
+                        size += chunk.len() as u64;
+                    }
+                    writer.finish()?.flush()?;
+
+                    incoming.files.push(SubmittedFile {
+                        name: file_name,
+                        content_type: Kind::detect(&head).map(|kind| kind.mime().to_string()),
+                        size,
+                        metadata_stripped: false,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if incoming.files.is_empty() && message.is_none() {
+            bail!("Empty submission");
+        }
+        tokio::task::spawn_blocking(move || incoming.finish(message)).await?
+    }
+}
+
+/// Serve one onion stream with the drop box
+pub async fn serve_connection<S>(io: S, server: Arc<DropBoxServer>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    let service = service_fn(move |req| handle_request(req, server.clone()));
+
+    if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
+        debug!("drop box connection ended: {}", e);
+    }
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    server: Arc<DropBoxServer>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.uri().path() != "/" {
+        return Ok(page(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    match *req.method() {
+        Method::GET | Method::HEAD => Ok(page(StatusCode::OK, FORM_PAGE)),
+        Method::POST => Ok(handle_upload(req, &server).await),
+        _ => Ok(page(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")),
+    }
+}
+
+async fn handle_upload(req: Request<Incoming>, server: &DropBoxServer) -> Response<Full<Bytes>> {
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > server.max_bytes) {
+        return page(StatusCode::PAYLOAD_TOO_LARGE, "The submission is too large");
+    }
+
+    let Ok(_upload) = server.uploads.try_acquire() else {
+        return page(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Busy, please try again later",
+        );
+    };
+    if !server.admit() {
+        warn!("drop box hourly limit reached, refusing a submission");
+        return page(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many submissions, please try again later",
+        );
+    }
+
+    match server.receive(req).await {
+        Ok(submission) => {
+            info!("drop box received {} file(s)", submission.files.len());
+            let _ = server.received.send(submission);
+            page(StatusCode::OK, RECEIVED_PAGE)
+        }
+        Err(e) => match e.downcast_ref::<multer::Error>() {
+            Some(multer::Error::StreamSizeExceeded { .. })
+            | Some(multer::Error::FieldSizeExceeded { .. }) => {
+                page(StatusCode::PAYLOAD_TOO_LARGE, "The submission is too large")
+            }
+            _ => {
+                error!("Failed to receive a drop box submission: {:#}", e);
+                page(
+                    StatusCode::BAD_REQUEST,
+                    "The submission could not be received",
+                )
+            }
+        },
+    }
+}
+
+fn page(status: StatusCode, body: &'static str) -> Response<Full<Bytes>> {
+    let content_type = if body.starts_with("<!DOCTYPE") {
+        "text/html; charset=utf-8"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            "Content-Security-Policy",
+            "default-src 'none'; style-src 'unsafe-inline'; form-action 'self'",
+        )
+        .header("Referrer-Policy", "no-referrer")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
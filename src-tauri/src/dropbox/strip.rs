@@ -0,0 +1,359 @@
+use anyhow::{bail, Context, Result};
+use lopdf::{Document, Object};
+
+/// file types we know how to clean, detected from their first bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Jpeg,
+    Png,
+    WebP,
+    Pdf,
+}
+
+impl Kind {
+    /// detect the type from the start of the file, never from the name
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+            Some(Self::WebP)
+        } else if head.starts_with(b"%PDF-") {
+            Some(Self::Pdf)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+            Self::Pdf => "application/pdf",
+        }
+    }
+}
+
+/// Remove the metadata that can identify a source
+///
+/// EXIF, XMP, IPTC and comments from images, the document information
+/// dictionary, XMP streams and file IDs from PDFs.
+pub fn strip_metadata(kind: Kind, data: &[u8]) -> Result<Vec<u8>> {
+    match kind {
+        Kind::Jpeg => strip_jpeg(data),
+        Kind::Png => strip_png(data),
+        Kind::WebP => strip_webp(data),
+        Kind::Pdf => strip_pdf(data),
+    }
+}
+
+/// Drop every APPn segment but JFIF, ICC profiles and Adobe, plus comments
+///
+/// Everything after the primary image's EOI goes too: that is where MPF
+/// puts its extra images, camera previews with their own EXIF.
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+
+    loop {
+        if pos + 2 > data.len() || data[pos] != 0xFF {
+            bail!("Malformed JPEG segment at byte {}", pos);
+        }
+        let marker = data[pos + 1];
+        // fill bytes before a marker
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // markers without a length
+        if marker == 0x01 || (0xD0..=0xD9).contains(&marker) {
+            out.extend_from_slice(&data[pos..pos + 2]);
+            pos += 2;
+            if marker == 0xD9 {
+                return Ok(out);
+            }
+            continue;
+        }
+
+        let len = data
+            .get(pos + 2..pos + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .context("Truncated JPEG segment")?;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            bail!("Truncated JPEG segment");
+        }
+
+        let keep = match marker {
+            0xE0 | 0xEE => true,
+            // APP2 also carries MPF and FlashPix, only ICC is needed
+            0xE2 => data[pos + 4..end].starts_with(b"ICC_PROFILE\0"),
+            0xE1..=0xEF | 0xFE => false,
+            _ => true,
+        };
+        if keep {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+
+        // start of scan: the entropy coded data runs to the next marker
+        if marker == 0xDA {
+            let scan_end = scan_end(data, pos);
+            out.extend_from_slice(&data[pos..scan_end]);
+            pos = scan_end;
+            // no EOI, as some encoders do
+            if pos == data.len() {
+                return Ok(out);
+            }
+        }
+    }
+}
+
+/// first marker after the entropy coded data starting at `pos`
+///
+/// Inside it 0xFF is followed by a stuffed zero or a restart marker.
+fn scan_end(data: &[u8], pos: usize) -> usize {
+    (pos..data.len().saturating_sub(1))
+        .find(|&i| data[i] == 0xFF && !matches!(data[i + 1], 0x00 | 0xD0..=0xD7 | 0xFF))
+        .unwrap_or(data.len())
+}
+
+/// drop the text, EXIF and timestamp chunks
+fn strip_png(data: &[u8]) -> Result<Vec<u8>> {
+    const DROPPED: [&[u8; 4]; 5] = [b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..8]);
+    let mut pos = 8;
+
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).context("Truncated PNG chunk")?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = pos
+            .checked_add(12 + len)
+            .filter(|end| *end <= data.len())
+            .context("Truncated PNG chunk")?;
+        let chunk_type = &header[4..8];
+
+        if !DROPPED.iter().any(|t| t.as_slice() == chunk_type) {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// drop the EXIF and XMP chunks and clear their flags in the VP8X header
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>> {
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).context("Truncated WebP chunk")?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = pos
+            .checked_add(8 + len + (len & 1))
+            .context("Truncated WebP chunk")?
+            .min(data.len());
+        let fourcc = &header[..4];
+
+        if fourcc != b"EXIF" && fourcc != b"XMP " {
+            let start = out.len();
+            out.extend_from_slice(&data[pos..end]);
+            if fourcc == b"VP8X" && end > pos + 8 {
+                out[start + 8] &= !(EXIF_FLAG | XMP_FLAG);
+            }
+        }
+        pos = end;
+    }
+
+    let riff_size = u32::try_from(out.len() - 8).context("WebP file too large")?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+/// Rewrite a PDF without its metadata
+///
+/// The document is saved as a single revision, so earlier versions kept
+/// by incremental updates are dropped as well.
+fn strip_pdf(data: &[u8]) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(data).context("Failed to parse PDF")?;
+    if doc.is_encrypted() {
+        bail!("Encrypted PDFs cannot be cleaned");
+    }
+
+    doc.trailer.remove(b"Info");
+    doc.trailer.remove(b"ID");
+    for object in doc.objects.values_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        dict.remove(b"Metadata");
+        dict.remove(b"PieceInfo");
+    }
+    doc.prune_objects();
+
+    let mut out = Vec::with_capacity(data.len());
+    doc.save_to(&mut out).context("Failed to write PDF")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    /// what a camera or an editor leaves behind and must not survive
+    const SECRET: &[u8] = b"Jane Doe, 52.5200N 13.4050E";
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    /// a JPEG with EXIF, an ICC profile and an MPF preview after the EOI
+    fn jpeg() -> Vec<u8> {
+        let mut preview = vec![0xFF, 0xD8];
+        preview.extend(jpeg_segment(
+            0xE1,
+            &[b"Exif\0\0".as_slice(), SECRET].concat(),
+        ));
+        preview.extend([0xFF, 0xD9]);
+
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        data.extend(jpeg_segment(
+            0xE1,
+            &[b"Exif\0\0".as_slice(), SECRET].concat(),
+        ));
+        data.extend(jpeg_segment(0xE2, b"ICC_PROFILE\0\x01\x01colour"));
+        data.extend(jpeg_segment(0xE2, b"MPF\0II*\0"));
+        data.extend(jpeg_segment(0xFE, SECRET));
+        data.extend(jpeg_segment(0xDB, &[0; 65]));
+        data.extend(jpeg_segment(0xDA, &[1, 1, 0, 0, 63, 0]));
+        // entropy coded data with a stuffed byte and a restart marker
+        data.extend([0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+        data.extend([0xFF, 0xD9]);
+        data.extend(preview);
+        data
+    }
+
+    #[test]
+    fn jpeg_keeps_the_image_only() {
+        let clean = strip_metadata(Kind::Jpeg, &jpeg()).unwrap();
+        assert!(!contains(&clean, SECRET));
+        assert!(!contains(&clean, b"Exif"));
+        assert!(!contains(&clean, b"MPF"));
+        assert!(contains(&clean, b"JFIF"));
+        assert!(contains(&clean, b"ICC_PROFILE"));
+        assert!(clean.ends_with(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD9]));
+    }
+
+    fn png_chunk(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(payload);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn png_loses_text_exif_and_time() {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(png_chunk(b"IHDR", &[0; 13]));
+        data.extend(png_chunk(
+            b"tEXt",
+            &[b"Author\0".as_slice(), SECRET].concat(),
+        ));
+        data.extend(png_chunk(b"eXIf", SECRET));
+        data.extend(png_chunk(b"tIME", &[0; 7]));
+        data.extend(png_chunk(b"IDAT", b"pixels"));
+        data.extend(png_chunk(b"IEND", b""));
+
+        let clean = strip_metadata(Kind::Png, &data).unwrap();
+        assert!(!contains(&clean, SECRET));
+        assert!(!contains(&clean, b"tIME"));
+        assert!(contains(&clean, b"IDAT"));
+        assert!(clean.ends_with(&png_chunk(b"IEND", b"")));
+    }
+
+    fn webp_chunk(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    #[test]
+    fn webp_loses_exif_and_xmp() {
+        let mut chunks = webp_chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        chunks.extend(webp_chunk(b"VP8 ", b"pixels"));
+        chunks.extend(webp_chunk(b"EXIF", SECRET));
+        chunks.extend(webp_chunk(b"XMP ", SECRET));
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend(chunks);
+
+        let clean = strip_metadata(Kind::WebP, &data).unwrap();
+        assert!(!contains(&clean, SECRET));
+        assert_eq!(clean[20], 0, "EXIF and XMP flags are cleared");
+        let riff_size = u32::from_le_bytes(clean[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, clean.len() - 8);
+        assert!(contains(&clean, b"pixels"));
+    }
+
+    #[test]
+    fn pdf_loses_info_and_xmp() {
+        let mut doc = Document::with_version("1.7");
+        let info = doc.add_object(dictionary! {
+            "Author" => Object::string_literal(SECRET),
+        });
+        let xmp = doc.add_object(Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            SECRET.to_vec(),
+        ));
+        let pages = doc.add_object(dictionary! {
+            "Type" => "Pages",
+            "Kids" => Vec::<Object>::new(),
+            "Count" => 0,
+        });
+        let catalog = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages,
+            "Metadata" => xmp,
+        });
+        doc.trailer.set("Root", catalog);
+        doc.trailer.set("Info", info);
+        let mut data = Vec::new();
+        doc.save_to(&mut data).unwrap();
+        assert!(contains(&data, SECRET));
+
+        let clean = strip_metadata(Kind::Pdf, &data).unwrap();
+        assert!(!contains(&clean, SECRET));
+        let doc = Document::load_mem(&clean).unwrap();
+        assert!(doc.trailer.get(b"Info").is_err());
+        assert!(doc.catalog().unwrap().get(b"Metadata").is_err());
+    }
+}
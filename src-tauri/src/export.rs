@@ -0,0 +1,599 @@
+use crate::tor::identity::{self, OnionIdentity};
+use anyhow::{bail, Context, Result};
+use lol_html::html_content::Element;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Url;
+use tracing::{debug, info, warn};
+
+/// manifest listing every exported file with its hash
+const MANIFEST_NAME: &str = "MANIFEST.json";
+/// hex signature of the manifest, made with the onion identity key
+const SIGNATURE_NAME: &str = "MANIFEST.sig";
+/// version of the manifest layout
+const MANIFEST_VERSION: u32 = 1;
+
+/// pages and assets fetched before the crawl gives up
+const MAX_ITEMS: usize = 20_000;
+
+/// parts of Ghost that are not the public site or do not work offline
+const EXCLUDED_PREFIXES: &[&str] = &[
+    "/ghost/",
+    "/.ghost/",
+    "/members/",
+    "/p/",
+    "/r/",
+    "/email/",
+    "/unsubscribe/",
+    "/webmentions/",
+    "/.well-known/",
+    "/rss/",
+];
+
+/// list of exported files with their hashes
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    created_at: u64,
+    /// the signer, recipients compare it with the address they know
+    onion_address: Option<String>,
+    /// file path -> sha256
+    files: BTreeMap<String, String>,
+}
+
+/// summary of a static export
+#[derive(Serialize, Debug, Default)]
+pub struct ExportReport {
+    pub directory: String,
+    pub zip: String,
+    pub pages: usize,
+    pub assets: usize,
+    pub bytes: u64,
+    /// onion address the manifest is signed with
+    pub signed_by: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+/// result of checking an export against its manifest
+#[derive(Serialize, Debug, Default)]
+pub struct VerifyReport {
+    pub files: usize,
+    /// onion address whose key signed the manifest, when the signature holds
+    pub signed_by: Option<String>,
+    pub problems: Vec<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Write a static copy of the blog to `destination`
+///
+/// Crawls Ghost at `base_url` (the local proxy) from the home page and the
+/// sitemaps, following links that stay on the blog, and rewrites every
+/// internal link to a relative one so the tree works from a USB stick.
+/// The tree is also packed into a single ZIP next to it. With `identity`
+/// the manifest of file hashes is signed with the onion service key.
+pub async fn export_site(
+    base_url: &str,
+    destination: &Path,
+    identity: Option<&OnionIdentity>,
+) -> Result<ExportReport> {
+    let base = Url::parse(base_url)?;
+    let name = format!("blog-export-{}", now_secs());
+    let root = destination.join(&name);
+    if root.exists() {
+        bail!("{:?} already exists", root);
+    }
+    fs::create_dir_all(&root).with_context(|| format!("Failed to create {:?}", root))?;
+
+    let mut crawler = Crawler::new(base.clone(), root.clone())?;
+    crawler.enqueue(base.clone());
+    crawler.seed_from_sitemaps().await;
+    crawler.run().await?;
+
+    let signed_by = identity.map(|id| id.onion_address());
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        created_at: now_secs(),
+        onion_address: signed_by.clone(),
+        files: crawler.files.clone(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    fs::write(root.join(MANIFEST_NAME), &manifest)?;
+    if let Some(identity) = identity {
+        fs::write(root.join(SIGNATURE_NAME), identity.sign(&manifest))?;
+    }
+
+    let zip_path = destination.join(format!("{}.zip", name));
+    let (zip_root, zip_target) = (root.clone(), zip_path.clone());
+    tokio::task::spawn_blocking(move || write_zip(&zip_root, &zip_target)).await??;
+
+    info!(
+        "exported {} pages and {} assets",
+        crawler.pages, crawler.assets
+    );
+    Ok(ExportReport {
+        directory: root.to_string_lossy().into_owned(),
+        zip: zip_path.to_string_lossy().into_owned(),
+        pages: crawler.pages,
+        assets: crawler.assets,
+        bytes: crawler.bytes,
+        signed_by,
+        warnings: crawler.warnings,
+    })
+}
+
+/// Check an unpacked export against its manifest and signature
+pub fn verify_export(root: &Path) -> Result<VerifyReport> {
+    let raw = fs::read(root.join(MANIFEST_NAME))
+        .with_context(|| format!("No {} in {:?}", MANIFEST_NAME, root))?;
+    let manifest: Manifest = serde_json::from_slice(&raw).context("Invalid manifest")?;
+
+    let mut report = VerifyReport {
+        files: manifest.files.len(),
+        ..Default::default()
+    };
+
+    match (
+        &manifest.onion_address,
+        fs::read_to_string(root.join(SIGNATURE_NAME)).ok(),
+    ) {
+        (Some(address), Some(signature)) => match identity::verify(address, &raw, &signature) {
+            Ok(()) => report.signed_by = Some(address.clone()),
+            Err(e) => report.problems.push(format!("{:#}", e)),
+        },
+        (Some(_), None) => report.problems.push("The signature file is missing".to_string()),
+        (None, _) => report.problems.push("The export is not signed".to_string()),
+    }
+
+    for (path, expected) in &manifest.files {
+        match fs::read(root.join(path)) {
+            Ok(bytes) if hex::encode(Sha256::digest(&bytes)) == *expected => {}
+            Ok(_) => report.problems.push(format!("{} was modified", path)),
+            Err(_) => report.problems.push(format!("{} is missing", path)),
+        }
+    }
+
+    let mut present = Vec::new();
+    collect_files(root, root, &mut present)?;
+    for path in present {
+        if path != MANIFEST_NAME && path != SIGNATURE_NAME && !manifest.files.contains_key(&path)
+        {
+            report.problems.push(format!("{} was added", path));
+        }
+    }
+
+    Ok(report)
+}
+
+/// breadth-first crawl of the public site
+struct Crawler {
+    client: reqwest::Client,
+    base: Url,
+    root: PathBuf,
+    queue: VecDeque<Url>,
+    /// local paths already queued
+    seen: HashSet<String>,
+    /// local path -> sha256 of what was written
+    files: BTreeMap<String, String>,
+    pages: usize,
+    assets: usize,
+    bytes: u64,
+    warnings: Vec<String>,
+}
+
+impl Crawler {
+    fn new(base: Url, root: PathBuf) -> Result<Self> {
+        // Ghost is on loopback, a configured system proxy must not see the crawl
+        let client = reqwest::Client::builder().no_proxy().build()?;
+        Ok(Self {
+            client,
+            base,
+            root,
+            queue: VecDeque::new(),
+            seen: HashSet::new(),
+            files: BTreeMap::new(),
+            pages: 0,
+            assets: 0,
+            bytes: 0,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// whether `url` belongs to the exported site
+    fn is_internal(&self, url: &Url) -> bool {
+        matches!(url.host_str(), Some("localhost") | Some("127.0.0.1"))
+            && url.port_or_known_default() == self.base.port_or_known_default()
+            && !EXCLUDED_PREFIXES
+                .iter()
+                .any(|prefix| url.path().starts_with(prefix))
+    }
+
+    fn enqueue(&mut self, mut url: Url) {
+        url.set_fragment(None);
+        let Some(local) = local_path(&url) else {
+            return;
+        };
+        if self.is_internal(&url) && self.seen.insert(local) {
+            self.queue.push_back(url);
+        }
+    }
+
+    /// queue everything Ghost lists in its sitemaps
+    async fn seed_from_sitemaps(&mut self) {
+        let mut sitemaps = vec![self.base.join("/sitemap.xml").expect("valid path")];
+        while let Some(sitemap) = sitemaps.pop() {
+            let body = match self.client.get(sitemap.clone()).send().await {
+                Ok(response) if response.status().is_success() => {
+                    response.text().await.unwrap_or_default()
+                }
+                _ => {
+                    debug!("no sitemap at {}", sitemap);
+                    continue;
+                }
+            };
+            for loc in sitemap_locations(&body) {
+                let Ok(url) = sitemap.join(&loc) else {
+                    continue;
+                };
+                if url.path().ends_with(".xml") {
+                    // a sitemap index lists the per-type sitemaps
+                    if url.path().starts_with("/sitemap") && sitemaps.len() < 32 {
+                        sitemaps.push(url);
+                    }
+                } else {
+                    self.enqueue(url);
+                }
+            }
+        }
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let mut fetched = 0;
+        while let Some(url) = self.queue.pop_front() {
+            fetched += 1;
+            if fetched > MAX_ITEMS {
+                self.warnings
+                    .push(format!("Stopped after {} pages and files", MAX_ITEMS));
+                break;
+            }
+            if let Err(e) = self.fetch(url.clone()).await {
+                warn!("export: skipping {}: {:#}", url, e);
+                self.warnings.push(format!("{}: {:#}", url.path(), e));
+            }
+        }
+        Ok(())
+    }
+
+    async fn fetch(&mut self, url: Url) -> Result<()> {
+        let response = self.client.get(url.clone()).send().await?;
+        if !response.status().is_success() {
+            bail!("status {}", response.status());
+        }
+
+        // Ghost redirects to the canonical URL, store the page there
+        let final_url = response.url().clone();
+        if !self.is_internal(&final_url) {
+            bail!("redirects off the blog");
+        }
+        let local = local_path(&final_url).context("unsupported path")?;
+        self.seen.insert(local.clone());
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let body = response.bytes().await?;
+
+        let body = if content_type.starts_with("text/html") {
+            self.pages += 1;
+            let html = String::from_utf8_lossy(&body);
+            let (html, links) = rewrite_page(&html, &final_url, &local, |u| self.is_internal(u))?;
+            for link in links {
+                self.enqueue(link);
+            }
+            html.into_bytes()
+        } else if content_type.starts_with("text/css") {
+            self.assets += 1;
+            let css = String::from_utf8_lossy(&body);
+            let (css, links) = rewrite_css(&css, &final_url, &local, |u| self.is_internal(u));
+            for link in links {
+                self.enqueue(link);
+            }
+            css.into_bytes()
+        } else {
+            self.assets += 1;
+            body.to_vec()
+        };
+
+        let file = local_file(&local).context("unsupported path")?;
+        let path = self.root.join(&file);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, &body)
+            .await
+            .with_context(|| format!("Failed to write {:?}", path))?;
+
+        self.bytes += body.len() as u64;
+        self.files.insert(file, hex::encode(Sha256::digest(&body)));
+        Ok(())
+    }
+}
+
+/// `<loc>` and `<image:loc>` entries of a sitemap
+fn sitemap_locations(xml: &str) -> Vec<String> {
+    let mut locations = Vec::new();
+    for tag in ["loc", "image:loc"] {
+        let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+        let mut rest = xml;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            let Some(end) = rest.find(&close) else {
+                break;
+            };
+            locations.push(
+                rest[..end]
+                    .trim()
+                    .replace("&amp;", "&")
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">"),
+            );
+            rest = &rest[end..];
+        }
+    }
+    locations
+}
+
+/// Where `url` is stored in the export, percent-encoded as in hrefs
+///
+/// Directories (which is how Ghost addresses pages) become `index.html`,
+/// the query string is dropped (Ghost only uses it for cache busting).
+fn local_path(url: &Url) -> Option<String> {
+    let mut segments: Vec<&str> = url.path().split('/').skip(1).collect();
+    let last = segments.pop().unwrap_or_default();
+    for segment in &segments {
+        local_segment(segment)?;
+    }
+
+    let mut path = segments.join("/");
+    if !path.is_empty() {
+        path.push('/');
+    }
+    if last.is_empty() {
+        path.push_str("index.html");
+    } else if !last.contains('.') {
+        local_segment(last)?;
+        path.push_str(last);
+        path.push_str("/index.html");
+    } else {
+        local_segment(last)?;
+        path.push_str(last);
+    }
+    Some(path)
+}
+
+/// a decoded path segment that is safe as a file name
+fn local_segment(segment: &str) -> Option<String> {
+    let decoded = urlencoding::decode(segment).ok()?.into_owned();
+    let safe = !decoded.is_empty()
+        && decoded != "."
+        && decoded != ".."
+        && !decoded.contains(['/', '\\', '\0', ':']);
+    safe.then_some(decoded)
+}
+
+/// the file name (decoded) for a local path
+fn local_file(local: &str) -> Option<String> {
+    local
+        .split('/')
+        .map(local_segment)
+        .collect::<Option<Vec<_>>>()
+        .map(|segments| segments.join("/"))
+}
+
+/// relative href from the file at `from` to the file at `to`
+fn relative_href(from: &str, to: &str) -> String {
+    let mut from_dirs: Vec<&str> = from.split('/').collect();
+    from_dirs.pop();
+    let to_parts: Vec<&str> = to.split('/').collect();
+
+    let common = from_dirs
+        .iter()
+        .zip(&to_parts)
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(to_parts.len() - 1);
+    let mut href = "../".repeat(from_dirs.len() - common);
+    href.push_str(&to_parts[common..].join("/"));
+    href
+}
+
+/// rewrite one link found on the page at `page` (stored at `local`)
+fn rewrite_link(
+    value: &str,
+    page: &Url,
+    local: &str,
+    is_internal: &dyn Fn(&Url) -> bool,
+    links: &RefCell<Vec<Url>>,
+) -> Option<String> {
+    let value = value.trim();
+    if value.starts_with('#') || value.is_empty() {
+        return None;
+    }
+    let url = page.join(value).ok()?;
+    if !is_internal(&url) {
+        return None;
+    }
+    let target = local_path(&url)?;
+    links.borrow_mut().push(url.clone());
+
+    let mut href = relative_href(local, &target);
+    if let Some(fragment) = url.fragment() {
+        href.push('#');
+        href.push_str(fragment);
+    }
+    Some(href)
+}
+
+/// Make an HTML page work from disk, returns the page and the links it has
+fn rewrite_page(
+    html: &str,
+    page: &Url,
+    local: &str,
+    is_internal: impl Fn(&Url) -> bool,
+) -> Result<(String, Vec<Url>)> {
+    let links = RefCell::new(Vec::new());
+
+    let attribute = |el: &mut Element, name: &str| {
+        if let Some(value) = el.get_attribute(name) {
+            if let Some(href) = rewrite_link(&value, page, local, &is_internal, &links) {
+                let _ = el.set_attribute(name, &href);
+            }
+        }
+    };
+
+    let srcset = |el: &mut Element| {
+        let Some(value) = el.get_attribute("srcset") else {
+            return;
+        };
+        let rewritten: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(|candidate| {
+                let (url, descriptor) = candidate.split_once(' ').unwrap_or((candidate, ""));
+                match rewrite_link(url, page, local, &is_internal, &links) {
+                    Some(href) => format!("{} {}", href, descriptor).trim_end().to_string(),
+                    None => candidate.to_string(),
+                }
+            })
+            .collect();
+        let _ = el.set_attribute("srcset", &rewritten.join(", "));
+    };
+
+    // readers of a copy should not announce themselves to CDNs either
+    let is_remote = |el: &Element, name: &str| {
+        el.get_attribute(name)
+            .and_then(|value| page.join(&value).ok())
+            .is_some_and(|url| matches!(url.scheme(), "http" | "https") && !is_internal(&url))
+    };
+
+    let output = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href], area[href]", |el| {
+                    attribute(el, "href");
+                    Ok(())
+                }),
+                element!("link[href]", |el| {
+                    let rel = el.get_attribute("rel").unwrap_or_default();
+                    if rel.contains("stylesheet") && is_remote(el, "href") {
+                        el.remove();
+                    } else {
+                        attribute(el, "href");
+                    }
+                    Ok(())
+                }),
+                element!("script[src]", |el| {
+                    if is_remote(el, "src") {
+                        el.remove();
+                    } else {
+                        attribute(el, "src");
+                    }
+                    Ok(())
+                }),
+                element!("img, source, video, audio, iframe, embed, track", |el| {
+                    attribute(el, "src");
+                    attribute(el, "poster");
+                    srcset(el);
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok((output, links.into_inner()))
+}
+
+/// Make the `url(...)` references of a stylesheet relative
+fn rewrite_css(
+    css: &str,
+    stylesheet: &Url,
+    local: &str,
+    is_internal: impl Fn(&Url) -> bool,
+) -> (String, Vec<Url>) {
+    let links = RefCell::new(Vec::new());
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find("url(") {
+        output.push_str(&rest[..start + 4]);
+        rest = &rest[start + 4..];
+        let Some(end) = rest.find(')') else {
+            break;
+        };
+        let raw = &rest[..end];
+        let value = raw.trim().trim_matches(['"', '\'']);
+        match rewrite_link(value, stylesheet, local, &is_internal, &links) {
+            Some(href) => output.push_str(&format!("\"{}\"", href)),
+            None => output.push_str(raw),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+
+    (output, links.into_inner())
+}
+
+/// files below `dir` as `/`-separated paths relative to `root`
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, out)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            out.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// pack the export into a single ZIP file
+fn write_zip(root: &Path, zip_path: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    collect_files(root, root, &mut files)?;
+    files.sort();
+
+    let tmp = zip_path.with_extension("zip.tmp");
+    let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(&tmp)?));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    for file in &files {
+        zip.start_file(file.as_str(), options)?;
+        zip.write_all(&fs::read(root.join(file))?)?;
+    }
+    zip.finish()?.flush()?;
+
+    fs::rename(&tmp, zip_path).with_context(|| format!("Failed to write {:?}", zip_path))?;
+    Ok(())
+}
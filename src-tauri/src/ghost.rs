@@ -0,0 +1,346 @@
+use crate::diagnostics::SidecarExit;
+use crate::paths::AppPaths;
+use crate::tor::proxy::Upstream;
+use anyhow::{bail, Context, Result};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, error, info, warn};
+
+/// prefix of the phase lines printed by the Ghost sidecar (see phase.ts)
+const PHASE_PREFIX: &str = "@@freedom-kit:phase ";
+
+/// Ghost's historical port, kept when free so bookmarks keep working
+const DEFAULT_PORT: u16 = 2368;
+
+/// pick a free loopback port for the webview to reach Ghost on
+///
+/// The listener is dropped before the port is bound again, another process
+/// could grab it in between, which then shows up as a failed startup.
+pub fn pick_port() -> anyhow::Result<u16> {
+    if TcpListener::bind((Ipv4Addr::LOCALHOST, DEFAULT_PORT)).is_ok() {
+        return Ok(DEFAULT_PORT);
+    }
+    free_port()
+}
+
+/// any free loopback port
+fn free_port() -> anyhow::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// where Ghost itself should listen, behind the webview proxy
+///
+/// With `unix_sockets` Ghost gets a socket in a private run directory,
+/// otherwise a random loopback port.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn upstream(paths: &AppPaths, unix_sockets: bool) -> Result<Upstream> {
+    #[cfg(unix)]
+    if unix_sockets {
+        let socket = paths.ghost_socket();
+        // sun_path is 104 bytes on macOS, 108 on Linux
+        if socket.as_os_str().len() >= 100 {
+            tracing::warn!("socket path {:?} is too long, using tcp", socket);
+            return Ok(Upstream::Tcp(free_port()?));
+        }
+
+        use std::os::unix::fs::DirBuilderExt;
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(paths.run_dir())?;
+        // left behind when Ghost was killed
+        let _ = std::fs::remove_file(&socket);
+        return Ok(Upstream::Unix(socket));
+    }
+
+    Ok(Upstream::Tcp(free_port()?))
+}
+
+/// base URL of a Ghost listening on `port`
+pub fn base_url(port: u16) -> String {
+    format!("http://localhost:{}", port)
+}
+
+/// whether `url` points at the local Ghost
+pub fn is_ghost_url(url: &tauri::Url, port: u16) -> bool {
+    matches!(url.host_str(), Some("localhost") | Some("127.0.0.1")) && url.port() == Some(port)
+}
+
+/// startup phase announced by the Ghost sidecar
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "phase", content = "detail", rename_all = "snake_case")]
+pub enum GhostPhase {
+    /// sidecar spawned, nothing announced yet
+    Starting,
+    /// first run, extracting the bundled Ghost install
+    Decompressing,
+    /// creating or migrating the database
+    Migrating,
+    Booting,
+    /// Ghost says it is up, the admin API probe confirms it
+    Ready,
+    Failed(String),
+}
+
+impl GhostPhase {
+    /// parse a line of sidecar stdout, `None` for ordinary log output
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim_end().strip_prefix(PHASE_PREFIX)?;
+        let (phase, detail) = rest.split_once(' ').unwrap_or((rest, ""));
+        Some(match phase {
+            "decompressing" => Self::Decompressing,
+            "migrating" => Self::Migrating,
+            "booting" => Self::Booting,
+            "ready" => Self::Ready,
+            "failed" => Self::Failed(detail.to_string()),
+            _ => return None,
+        })
+    }
+}
+
+/// what the Ghost sidecar process did, the shell plugin's events in the app
+#[derive(Debug)]
+pub enum SidecarEvent {
+    Stdout(String),
+    Stderr(String),
+    Error(String),
+    Terminated {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+}
+
+/// Follow the sidecar's output until it exits
+///
+/// Phase lines go to `phases` and `on_phase`, everything else is logged.
+/// Exiting is reported as a `Failed` phase, so a startup in progress stops
+/// waiting. Returns how the sidecar ended, `None` when the events just stop.
+pub async fn watch_sidecar(
+    events: impl Stream<Item = SidecarEvent>,
+    phases: watch::Sender<GhostPhase>,
+    mut on_phase: impl FnMut(&GhostPhase),
+) -> Option<SidecarExit> {
+    let mut events = std::pin::pin!(events);
+    while let Some(event) = events.next().await {
+        match event {
+            SidecarEvent::Stdout(line) => {
+                if let Some(phase) = GhostPhase::parse(&line) {
+                    info!("startup phase: {:?}", phase);
+                    on_phase(&phase);
+                    phases.send_replace(phase);
+                } else {
+                    info!(stream = "stdout", "{}", line.trim_end());
+                }
+            }
+            SidecarEvent::Stderr(line) => warn!(stream = "stderr", "{}", line.trim_end()),
+            SidecarEvent::Error(e) => error!("Ghost sidecar error: {}", e),
+            SidecarEvent::Terminated { code, signal } => {
+                warn!("Ghost sidecar exited with code {:?}", code);
+                phases.send_replace(GhostPhase::Failed(format!(
+                    "sidecar exited with code {:?}",
+                    code
+                )));
+                return Some(SidecarExit::now("ghost", code, signal));
+            }
+        }
+    }
+    None
+}
+
+/// timeouts used while waiting for Ghost to come up
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReadinessSettings {
+    /// give up after this long, a first run with migrations is the slow case
+    pub startup_timeout_secs: u64,
+    /// delay between two admin API probes
+    pub probe_interval_ms: u64,
+    /// timeout of a single probe request
+    pub probe_timeout_secs: u64,
+}
+
+impl Default for ReadinessSettings {
+    fn default() -> Self {
+        Self {
+            startup_timeout_secs: 180,
+            probe_interval_ms: 250,
+            probe_timeout_secs: 5,
+        }
+    }
+}
+
+/// wait until Ghost's admin API answers with its site info
+///
+/// `phases` carries what the sidecar announces, a `Failed` phase aborts the
+/// wait right away instead of running into the timeout.
+pub async fn wait_until_ready(
+    base_url: &str,
+    mut phases: watch::Receiver<GhostPhase>,
+    settings: &ReadinessSettings,
+) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(settings.probe_timeout_secs))
+        .build()?;
+    let site_url = format!("{}/ghost/api/admin/site/", base_url.trim_end_matches('/'));
+    let deadline = Instant::now() + Duration::from_secs(settings.startup_timeout_secs);
+    let interval = Duration::from_millis(settings.probe_interval_ms);
+
+    info!("waiting for ghost at {}", site_url);
+    loop {
+        if let GhostPhase::Failed(reason) = &*phases.borrow() {
+            bail!("Ghost failed to start: {}", reason);
+        }
+
+        match probe(&client, &site_url).await {
+            Ok(version) => {
+                info!("ghost {} is ready", version);
+                return Ok(());
+            }
+            Err(e) => debug!("ghost not ready yet: {:#}", e),
+        }
+
+        // sleep, but wake up early when the sidecar reports progress
+        match timeout_at(deadline.min(Instant::now() + interval), phases.changed()).await {
+            Ok(Ok(())) | Err(_) => {}
+            // the sidecar output handler is gone, so is the sidecar
+            Ok(Err(_)) => bail!("Ghost exited during startup"),
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Ghost did not become ready within {}s (last phase: {:?})",
+                settings.startup_timeout_secs,
+                *phases.borrow()
+            );
+        }
+    }
+}
+
+/// one request to the admin site endpoint, returns the Ghost version
+async fn probe(client: &reqwest::Client, site_url: &str) -> Result<String> {
+    let response = client
+        .get(site_url)
+        .header("Accept", "application/json")
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("status {}", response.status());
+    }
+
+    // Ghost answers 200 with an HTML error page while it is still booting,
+    // only the real endpoint returns this shape
+    let body: serde_json::Value = response.json().await?;
+    let site = body
+        .get("site")
+        .ok_or_else(|| anyhow::anyhow!("no site object in response"))?;
+    if !site.get("title").is_some_and(|t| t.is_string()) {
+        bail!("site response has no title");
+    }
+    Ok(site
+        .get("version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown")
+        .to_string())
+}
+
+/// cookie Ghost keeps a signed-in staff user's session in
+const SESSION_COOKIE: &str = "ghost-admin-api-session";
+
+/// how long one admin API call may take
+const ADMIN_API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the admin's Ghost session, as the webview proxy last saw it
+///
+/// Lets the app use the admin API on the user's behalf, the same way the
+/// admin pages do, without an integration key of its own.
+#[derive(Clone, Debug, Default)]
+pub struct AdminSession(Arc<Mutex<Option<String>>>);
+
+impl AdminSession {
+    /// remember the session cookie from a request's Cookie header, if it has one
+    pub fn observe(&self, cookie_header: &str) {
+        let session = cookie_header.split(';').map(str::trim).find(|cookie| {
+            cookie
+                .strip_prefix(SESSION_COOKIE)
+                .is_some_and(|rest| rest.starts_with('='))
+        });
+        if let Some(session) = session {
+            *self.0.lock().unwrap() = Some(session.to_string());
+        }
+    }
+
+    /// admin API client for the Ghost at `base_url`, signed in as the admin
+    pub fn api(&self, base_url: &str) -> Result<AdminApi> {
+        let cookie = self
+            .0
+            .lock()
+            .unwrap()
+            .clone()
+            .context("Not signed in to Ghost, open the admin first")?;
+        Ok(AdminApi {
+            client: reqwest::Client::builder()
+                .timeout(ADMIN_API_TIMEOUT)
+                .build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cookie,
+        })
+    }
+}
+
+/// Ghost's admin API, called with the admin's session
+pub struct AdminApi {
+    client: reqwest::Client,
+    base_url: String,
+    cookie: String,
+}
+
+impl AdminApi {
+    /// GET `path` (below `/ghost/api/admin/`)
+    pub async fn get(&self, path: &str) -> Result<serde_json::Value> {
+        self.send(reqwest::Method::GET, path, None).await
+    }
+
+    /// PUT `body` to `path` (below `/ghost/api/admin/`)
+    pub async fn put(&self, path: &str, body: &serde_json::Value) -> Result<serde_json::Value> {
+        self.send(reqwest::Method::PUT, path, Some(body)).await
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value> {
+        let url = format!("{}/ghost/api/admin/{}", self.base_url, path);
+        // session auth is only accepted from the admin's own origin
+        let mut request = self
+            .client
+            .request(method, &url)
+            .header("Cookie", &self.cookie)
+            .header("Origin", &self.base_url)
+            .header("Accept", "application/json");
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await.context("Failed to reach Ghost")?;
+
+        let status = response.status();
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        if status == reqwest::StatusCode::CONFLICT {
+            bail!("It was edited in the meantime, try again");
+        }
+        if !status.is_success() {
+            let message = body
+                .pointer("/errors/0/message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no details");
+            bail!("Ghost answered {}: {}", status, message);
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,598 @@
+use crate::paths::AppPaths;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// folder names used by desktop sync clients, anything below them is uploaded
+const CLOUD_FOLDERS: &[&str] = &[
+    "dropbox",
+    "onedrive",
+    "google drive",
+    "googledrive",
+    "my drive",
+    "icloud drive",
+    "iclouddrive",
+    "mobile documents",
+    "cloudstorage",
+    "pcloud drive",
+    "megasync",
+    "nextcloud",
+    "owncloud",
+    "box sync",
+    "synologydrive",
+    "yandex.disk",
+    "tresorit",
+];
+
+/// marker files sync clients leave in the folders they manage
+const CLOUD_MARKERS: &[&str] = &[".dropbox", ".dropbox.cache", ".sync", ".owncloudsync.log"];
+
+/// a problem with one of the private directories
+#[derive(Serialize, Clone, Debug)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub problem: String,
+    /// fixed automatically, no action needed
+    pub repaired: bool,
+}
+
+/// outcome of a storage check
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HardeningReport {
+    pub findings: Vec<Finding>,
+}
+
+impl HardeningReport {
+    fn add(&mut self, path: &Path, problem: impl Into<String>, repaired: bool) {
+        let problem = problem.into();
+        if repaired {
+            info!("repaired {:?}: {}", path, problem);
+        } else {
+            warn!("unsafe storage {:?}: {}", path, problem);
+        }
+        self.findings.push(Finding {
+            path: path.to_path_buf(),
+            problem,
+            repaired,
+        });
+    }
+
+    /// problems that still need the user's attention
+    pub fn unresolved(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|f| !f.repaired)
+    }
+
+    /// nothing left that makes it unsafe to start
+    pub fn is_safe(&self) -> bool {
+        self.unresolved().next().is_none()
+    }
+
+    /// human readable list of unresolved problems
+    pub fn summary(&self) -> String {
+        let lines: Vec<String> = self
+            .unresolved()
+            .map(|f| format!("{}: {}", f.path.display(), f.problem))
+            .collect();
+        format!("Unsafe storage location:\n{}", lines.join("\n"))
+    }
+}
+
+/// a directory to check, and whether we may create it
+struct Target {
+    path: PathBuf,
+    create: bool,
+}
+
+/// directories holding the onion identity, the blog and the wallet
+fn targets(paths: &AppPaths) -> Vec<Target> {
+    let mut targets = vec![
+        Target {
+            path: paths.data_dir.clone(),
+            create: true,
+        },
+        Target {
+            path: paths.tor_dir.clone(),
+            create: true,
+        },
+        Target {
+            path: paths.wallet_dir.clone(),
+            create: true,
+        },
+        // the sidecar extracts Ghost on first run when the install dir is
+        // missing, so only its parent may be created here
+        Target {
+            path: paths
+                .ghost_dir
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| paths.ghost_dir.clone()),
+            create: true,
+        },
+        Target {
+            path: paths.ghost_dir.clone(),
+            create: false,
+        },
+        // likewise the sidecar seeds the content dir when it does not exist
+        Target {
+            path: paths.ghost_content_dir(),
+            create: false,
+        },
+    ];
+    if let Some(parent) = paths.ghost_content.as_ref().and_then(|c| c.parent()) {
+        targets.push(Target {
+            path: parent.to_path_buf(),
+            create: true,
+        });
+    }
+
+    let mut seen = Vec::new();
+    targets.retain(|t| {
+        let fresh = !seen.contains(&t.path);
+        seen.push(t.path.clone());
+        fresh
+    });
+    targets
+}
+
+/// check every private directory, repairing what can be repaired if `repair`
+///
+/// Only the app's own directories are repaired. Symlinked directories, sync
+/// folders, network mounts, directories owned by someone else and writable
+/// parents are reported instead: moving data is the user's call.
+pub fn check_storage(paths: &AppPaths, repair: bool) -> HardeningReport {
+    let mut report = HardeningReport::default();
+    for target in targets(paths) {
+        check_dir(&target.path, target.create, repair, &mut report);
+    }
+    report
+}
+
+fn check_dir(dir: &Path, create: bool, repair: bool, report: &mut HardeningReport) {
+    if fs::symlink_metadata(dir).is_err() {
+        if !create {
+            return;
+        }
+        if let Err(e) = create_private_dir(dir) {
+            report.add(dir, format!("cannot be created: {}", e), false);
+            return;
+        }
+    }
+
+    let metadata = match fs::symlink_metadata(dir) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            report.add(dir, format!("cannot be read: {}", e), false);
+            return;
+        }
+    };
+    if metadata.file_type().is_symlink() {
+        report.add(
+            dir,
+            "is a symlink, point the app at the real directory instead",
+            false,
+        );
+        return;
+    }
+    if !metadata.is_dir() {
+        report.add(dir, "is not a directory", false);
+        return;
+    }
+
+    let resolved = match dir.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            report.add(dir, format!("cannot be resolved: {}", e), false);
+            return;
+        }
+    };
+    if let Some(service) = cloud_folder(&resolved) {
+        report.add(
+            dir,
+            format!(
+                "is inside a {} folder, its contents would be uploaded",
+                service
+            ),
+            false,
+        );
+    }
+
+    platform::check_dir(dir, &resolved, repair, report);
+}
+
+/// name of the sync service owning `path`, if any
+fn cloud_folder(path: &Path) -> Option<String> {
+    for ancestor in path.ancestors() {
+        if let Some(name) = ancestor.file_name().and_then(|n| n.to_str()) {
+            let lower = name.to_lowercase();
+            // OneDrive for business uses "OneDrive - <Company>"
+            if CLOUD_FOLDERS
+                .iter()
+                .any(|c| lower == *c || lower.starts_with(&format!("{} - ", c)))
+            {
+                return Some(name.to_string());
+            }
+        }
+        if let Some(marker) = CLOUD_MARKERS.iter().find(|m| ancestor.join(m).exists()) {
+            return Some(format!("synced ({})", marker));
+        }
+    }
+    None
+}
+
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::HardeningReport;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::Path;
+
+    const STICKY: u32 = 0o1000;
+
+    pub fn check_dir(dir: &Path, resolved: &Path, repair: bool, report: &mut HardeningReport) {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        let me = unsafe { libc::geteuid() };
+
+        let Ok(metadata) = fs::metadata(resolved) else {
+            return;
+        };
+        if metadata.uid() != me {
+            report.add(
+                dir,
+                format!(
+                    "is owned by uid {} instead of the current user",
+                    metadata.uid()
+                ),
+                false,
+            );
+            return;
+        }
+
+        let mode = metadata.mode() & 0o777;
+        if mode & 0o077 != 0 {
+            if repair {
+                let restricted = fs::set_permissions(resolved, fs::Permissions::from_mode(0o700))
+                    .and_then(|_| fs::metadata(resolved));
+                match restricted {
+                    // some filesystems accept chmod and ignore it
+                    Ok(after) if after.mode() & 0o077 == 0 => report.add(
+                        dir,
+                        format!("was readable by other users ({:o}), set to 700", mode),
+                        true,
+                    ),
+                    Ok(_) => report.add(
+                        dir,
+                        format!(
+                            "is on a filesystem that ignores permissions{}, other users can read it",
+                            mount_type(resolved)
+                                .map(|t| format!(" ({})", t))
+                                .unwrap_or_default()
+                        ),
+                        false,
+                    ),
+                    Err(e) => report.add(
+                        dir,
+                        format!("is accessible by other users ({:o}): {}", mode, e),
+                        false,
+                    ),
+                }
+            } else {
+                report.add(
+                    dir,
+                    format!("is accessible by other users ({:o}), run chmod 700", mode),
+                    false,
+                );
+            }
+        }
+
+        if let Some(fs_type) = mount_type(resolved).filter(|t| is_network_fs(t)) {
+            report.add(
+                dir,
+                format!(
+                    "is on a network filesystem ({}), other machines can read it",
+                    fs_type
+                ),
+                false,
+            );
+        }
+
+        // whoever can write to a parent can swap the directory out
+        for parent in resolved.ancestors().skip(1) {
+            let Ok(metadata) = fs::metadata(parent) else {
+                continue;
+            };
+            if metadata.uid() != me && metadata.uid() != 0 {
+                report.add(
+                    dir,
+                    format!(
+                        "parent {} is owned by uid {}, who could replace it",
+                        parent.display(),
+                        metadata.uid()
+                    ),
+                    false,
+                );
+                continue;
+            }
+
+            // parents are the user's own (like $HOME), changing them is their call
+            let mode = metadata.mode();
+            if mode & 0o022 != 0 && mode & STICKY == 0 {
+                report.add(
+                    dir,
+                    format!(
+                        "parent {} is writable by other users ({:o}), run chmod go-w on it",
+                        parent.display(),
+                        mode & 0o777
+                    ),
+                    false,
+                );
+            }
+        }
+
+        // symlinks on the way are fine as long as nobody else controls them
+        for ancestor in dir.ancestors().skip(1) {
+            if let Ok(link) = fs::symlink_metadata(ancestor) {
+                if link.file_type().is_symlink() && link.uid() != me && link.uid() != 0 {
+                    report.add(
+                        dir,
+                        format!(
+                            "path goes through {}, a symlink owned by uid {}",
+                            ancestor.display(),
+                            link.uid()
+                        ),
+                        false,
+                    );
+                }
+            }
+        }
+    }
+
+    fn is_network_fs(fs_type: &str) -> bool {
+        const NETWORK: &[&str] = &[
+            "nfs",
+            "nfs4",
+            "cifs",
+            "smb3",
+            "smbfs",
+            "afpfs",
+            "webdav",
+            "9p",
+            "afs",
+            "ceph",
+            "glusterfs",
+            "fuse.sshfs",
+            "fuse.rclone",
+            "fuse.davfs",
+            "davfs",
+        ];
+        NETWORK.contains(&fs_type)
+    }
+
+    /// filesystem type of the mount holding `path`
+    #[cfg(target_os = "linux")]
+    fn mount_type(path: &Path) -> Option<String> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let mut best: Option<(usize, String)> = None;
+        for line in mountinfo.lines() {
+            // id parent dev root mountpoint options [optional...] - fstype source super
+            let Some((left, right)) = line.split_once(" - ") else {
+                continue;
+            };
+            let (Some(mount_point), Some(fs_type)) =
+                (left.split(' ').nth(4), right.split(' ').next())
+            else {
+                continue;
+            };
+            let mount_point = unescape(mount_point);
+            if path.starts_with(&mount_point)
+                && best
+                    .as_ref()
+                    .is_none_or(|(len, _)| mount_point.len() >= *len)
+            {
+                best = Some((mount_point.len(), fs_type.to_string()));
+            }
+        }
+        best.map(|(_, fs_type)| fs_type)
+    }
+
+    /// mountinfo escapes spaces and friends as octal
+    #[cfg(target_os = "linux")]
+    fn unescape(field: &str) -> String {
+        let mut out = Vec::with_capacity(field.len());
+        let bytes = field.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'\\' && i + 3 < bytes.len() {
+                if let Ok(byte) = u8::from_str_radix(&field[i + 1..i + 4], 8) {
+                    out.push(byte);
+                    i += 4;
+                    continue;
+                }
+            }
+            out.push(bytes[i]);
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    #[cfg(target_os = "macos")]
+    fn mount_type(path: &Path) -> Option<String> {
+        use std::ffi::{CStr, CString};
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: c_path is NUL terminated and stat is a valid out pointer
+        if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn mount_type(_path: &Path) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use super::HardeningReport;
+    use std::path::Path;
+
+    /// Windows has no mode bits, directories below the user profile inherit
+    /// an ACL that only grants the user, SYSTEM and administrators access.
+    pub fn check_dir(dir: &Path, resolved: &Path, _repair: bool, report: &mut HardeningReport) {
+        let Some(home) = dirs::home_dir().and_then(|h| h.canonicalize().ok()) else {
+            return;
+        };
+        if !resolved.starts_with(&home) {
+            report.add(
+                dir,
+                "is outside your user profile, other accounts may be able to read it",
+                false,
+            );
+        }
+
+        // junctions and symlinks on the way could redirect us anywhere
+        for ancestor in dir.ancestors().skip(1) {
+            if std::fs::symlink_metadata(ancestor).is_ok_and(|m| m.file_type().is_symlink()) {
+                report.add(
+                    dir,
+                    format!("path goes through the link {}", ancestor.display()),
+                    false,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// findings about `dir` itself, leaving out its parents
+    fn check(dir: &Path, repair: bool) -> Vec<Finding> {
+        let mut report = HardeningReport::default();
+        check_dir(dir, true, repair, &mut report);
+        report
+            .findings
+            .into_iter()
+            .filter(|f| !f.problem.starts_with("parent "))
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn loose_dir(root: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = root.join("blog");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    fn mode(dir: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::metadata(dir).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn missing_dirs_are_created_private() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("tor/keys");
+        assert!(check(&dir, false).is_empty());
+        assert!(dir.is_dir());
+        #[cfg(unix)]
+        assert_eq!(mode(&dir), 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn loose_permissions_are_repaired() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = loose_dir(root.path());
+
+        let findings = check(&dir, true);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].repaired);
+        assert_eq!(
+            findings[0].problem,
+            "was readable by other users (755), set to 700"
+        );
+        assert_eq!(mode(&dir), 0o700);
+        assert!(check(&dir, true).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn loose_permissions_are_only_reported_without_repair() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = loose_dir(root.path());
+
+        let findings = check(&dir, false);
+        assert_eq!(findings.len(), 1);
+        assert!(!findings[0].repaired);
+        assert_eq!(
+            findings[0].problem,
+            "is accessible by other users (755), run chmod 700"
+        );
+        assert_eq!(mode(&dir), 0o755);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_dirs_are_reported() {
+        let root = tempfile::tempdir().unwrap();
+        let real = root.path().join("real");
+        fs::create_dir(&real).unwrap();
+        let link = root.path().join("blog");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let findings = check(&link, true);
+        assert_eq!(findings.len(), 1);
+        assert!(!findings[0].repaired);
+        assert!(findings[0].problem.starts_with("is a symlink"));
+    }
+
+    #[test]
+    fn sync_folders_are_flagged() {
+        let root = tempfile::tempdir().unwrap();
+        for folder in ["Dropbox", "iCloud Drive", "OneDrive - Example Corp"] {
+            let dir = root.path().join(folder).join("blog");
+            let findings = check(&dir, true);
+            assert_eq!(findings.len(), 1, "{}", folder);
+            assert_eq!(
+                findings[0].problem,
+                format!(
+                    "is inside a {} folder, its contents would be uploaded",
+                    folder
+                )
+            );
+        }
+
+        // folders a sync client marked, whatever they are called
+        let synced = root.path().join("Documents");
+        fs::create_dir(&synced).unwrap();
+        fs::write(synced.join(".dropbox"), "").unwrap();
+        assert_eq!(
+            cloud_folder(&synced.join("blog")).as_deref(),
+            Some("synced (.dropbox)")
+        );
+        assert_eq!(cloud_folder(&root.path().join("blog")), None);
+    }
+}
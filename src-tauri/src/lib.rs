@@ -1,18 +1,85 @@
+mod backup;
+mod card;
+mod crypto;
+mod diagnostics;
+mod dropbox;
+mod export;
+mod ghost;
+mod hardening;
+mod lifecycle;
+mod localize;
+mod logging;
+mod paths;
+mod publishing;
+mod settings;
+mod share;
+mod signing;
 mod tor;
+#[cfg(desktop)]
+mod tray;
+mod vault;
+mod wipe;
 
-use std::path::PathBuf;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context;
+use futures::StreamExt;
+use lifecycle::Stage;
+use paths::AppPaths;
+use settings::Settings;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
+use tauri::webview::PageLoadEvent;
 use tauri::{Emitter, Manager};
 use tauri_plugin_shell::process::CommandChild;
 use tauri_plugin_shell::ShellExt;
 use tokio::sync::Mutex;
 use tor::{HiddenServiceConfig, HiddenServiceManager};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// Whether the webview can use the Tor SOCKS proxy, which requires a login
+///
+/// Only WebKitGTK sends SOCKS credentials, WebView2 (Chromium) has no SOCKS
+/// authentication and macOS needs the `macos-proxy` feature and macOS 14.
+const WEBVIEW_TOR: bool = cfg!(target_os = "linux");
 
 // Tauri state to hold the hidden service manager and Ghost process
 pub struct AppState {
+    paths: std::sync::RwLock<AppPaths>,
+    /// the app log, kept in memory until the storage is available
+    logs: logging::AppLog,
+    settings: Arc<Mutex<Settings>>,
+    vault: Mutex<Option<vault::UnlockedVault>>,
+    storage_report: Mutex<hardening::HardeningReport>,
+    /// loopback port the webview reaches Ghost on, picked at startup
+    ghost_port: Mutex<Option<u16>>,
+    /// bootstrapped Tor client, shared by the hidden service and the webview proxy
+    tor_client: Arc<tokio::sync::watch::Sender<Option<Arc<tor::TorClientManager>>>>,
     hidden_service: Arc<Mutex<Option<HiddenServiceManager>>>,
+    /// files offered for download on the onion service
+    shares: Arc<share::ShareManager>,
     ghost_child: Arc<Mutex<Option<CommandChild>>>,
+    /// follows Ghost's output, done once the sidecar has exited
+    ghost_watcher: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    /// the webview proxy in front of Ghost
+    proxy_handle: Mutex<Option<tokio::task::JoinHandle<()>>>,
+    /// the admin's Ghost session, for admin API calls
+    admin_session: ghost::AdminSession,
+    /// signatures of the published posts, made with the onion identity key
+    signatures: Mutex<Option<Arc<signing::ContentSigner>>>,
+    /// what is running, and the orderly way to stop it
+    lifecycle: lifecycle::Lifecycle,
+    /// how sidecars ended this run, for diagnostics bundles
+    sidecar_exits: Mutex<Vec<diagnostics::SidecarExit>>,
+}
+
+impl AppState {
+    /// Current data locations (they move into the vault once it is unlocked)
+    fn paths(&self) -> AppPaths {
+        self.paths.read().unwrap().clone()
+    }
 }
 
 // Tauri command to get the onion address
@@ -20,20 +87,1099 @@ pub struct AppState {
 async fn get_onion_address(state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
     let hs = state.hidden_service.lock().await;
     let address = hs.as_ref().and_then(|s| s.onion_url());
-    println!("📞 get_onion_address called, returning: {:?}", address);
+    debug!("get_onion_address called, returning: {:?}", address);
     Ok(address)
 }
 
-// Tauri command to check if the hidden service is running
-#[tauri::command]
-async fn is_tor_running(state: tauri::State<'_, AppState>) -> Result<bool, String> {
-    let hs = state.hidden_service.lock().await;
-    Ok(hs.as_ref().map(|s| s.is_running()).unwrap_or(false))
+// Tauri command to check if the hidden service is running
+#[tauri::command]
+async fn is_tor_running(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    let hs = state.hidden_service.lock().await;
+    Ok(hs.as_ref().map(|s| s.is_running()).unwrap_or(false))
+}
+
+// Tauri command to decrypt the proxy access log for review
+#[tauri::command]
+async fn read_access_log(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || tor::access_log::read_lines(&paths))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to shred the proxy access log
+#[tauri::command]
+async fn clear_access_log(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || tor::access_log::clear(&paths))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to read the app log for the diagnostics view
+#[tauri::command]
+async fn get_logs(
+    state: tauri::State<'_, AppState>,
+    level: Option<logging::LogLevel>,
+    subsystem: Option<logging::Subsystem>,
+    limit: Option<usize>,
+) -> Result<Vec<logging::LogEntry>, String> {
+    let logs = state.logs.clone();
+    let limit = limit.unwrap_or(logging::DEFAULT_LIMIT);
+    tauri::async_runtime::spawn_blocking(move || logs.entries(level, subsystem, limit))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to write a scrubbed diagnostics ZIP for a bug report
+#[tauri::command]
+async fn export_diagnostics(
+    state: tauri::State<'_, AppState>,
+    destination: String,
+) -> Result<diagnostics::DiagnosticsReport, String> {
+    let paths = state.paths();
+    let report = diagnostics::Report {
+        created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        versions: diagnostics::Versions::collect(&paths),
+        tor: collect_tor_metrics(&state).await,
+        storage: state.storage_report.lock().await.clone(),
+        sidecar_exits: state.sidecar_exits.lock().await.clone(),
+    };
+    let settings = state.settings.lock().await.clone();
+    let logs = state.logs.clone();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let logs = logs.entries(None, None, diagnostics::LOG_ENTRIES)?;
+        diagnostics::export(
+            Path::new(&destination),
+            &report,
+            &settings,
+            &logs,
+            &paths.ghost_config(),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to write the onion address as QR codes and a printable card
+#[tauri::command]
+async fn create_address_card(
+    state: tauri::State<'_, AppState>,
+    destination: String,
+    title: Option<String>,
+    railgun_address: Option<String>,
+) -> Result<card::CardReport, String> {
+    let onion_url = state
+        .hidden_service
+        .lock()
+        .await
+        .as_ref()
+        .and_then(|hs| hs.onion_url())
+        .ok_or_else(|| "The blog has no onion address yet".to_string())?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        card::write_card(
+            Path::new(&destination),
+            &card::Card {
+                onion_url: &onion_url,
+                title: title.as_deref(),
+                railgun_address: railgun_address.as_deref(),
+            },
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to read the Tor client and onion service counters
+#[tauri::command]
+async fn get_tor_metrics(state: tauri::State<'_, AppState>) -> Result<tor::TorMetrics, String> {
+    Ok(collect_tor_metrics(&state).await)
+}
+
+/// Aggregated counters only, nothing about single visitors or destinations
+async fn collect_tor_metrics(state: &AppState) -> tor::TorMetrics {
+    let tor_manager = state.tor_client.borrow().clone();
+    let hs = state.hidden_service.lock().await;
+    tor::TorMetrics {
+        client: tor_manager.map(|tor_manager| tor_manager.metrics()),
+        service: hs.as_ref().map(|hs| hs.metrics()),
+    }
+}
+
+/// whether the blog is published right now, and why not
+#[derive(serde::Serialize)]
+struct PublishingStatus {
+    /// the onion service is reachable
+    online: bool,
+    /// paused by hand
+    paused: bool,
+    schedule: Option<publishing::PublishSchedule>,
+}
+
+// Tauri command to report the pause switch, the schedule and the onion state
+#[tauri::command]
+async fn get_publishing_status(
+    state: tauri::State<'_, AppState>,
+) -> Result<PublishingStatus, String> {
+    let settings = state.settings.lock().await.publishing.clone();
+    let hs = state.hidden_service.lock().await;
+    Ok(PublishingStatus {
+        online: hs.as_ref().is_some_and(|hs| hs.is_running()),
+        paused: settings.paused,
+        schedule: settings.schedule,
+    })
+}
+
+// Tauri command to take the blog offline, or bring it back with the same address
+#[tauri::command]
+async fn set_publishing_paused(app_handle: tauri::AppHandle, paused: bool) -> Result<(), String> {
+    update_publishing(&app_handle, |publishing| publishing.paused = paused)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to only publish inside a daily window, `None` publishes all the time
+#[tauri::command]
+async fn set_publish_schedule(
+    app_handle: tauri::AppHandle,
+    schedule: Option<publishing::PublishSchedule>,
+) -> Result<(), String> {
+    if let Some(schedule) = &schedule {
+        schedule.validate().map_err(|e| format!("{:#}", e))?;
+    }
+    update_publishing(&app_handle, |publishing| publishing.schedule = schedule)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Save a change to the publishing settings and apply it right away
+async fn update_publishing(
+    app_handle: &tauri::AppHandle,
+    change: impl FnOnce(&mut publishing::PublishingSettings),
+) -> anyhow::Result<()> {
+    let state = app_handle.state::<AppState>();
+    {
+        let mut settings = state.settings.lock().await;
+        let mut updated = settings.clone();
+        change(&mut updated.publishing);
+        updated.save(&state.paths().settings_file())?;
+        *settings = updated;
+    }
+    let applied = apply_publishing(app_handle).await;
+    refresh_tray(app_handle).await;
+    applied
+}
+
+/// Pause or resume the onion service to match the pause switch and the schedule
+async fn apply_publishing(app_handle: &tauri::AppHandle) -> anyhow::Result<()> {
+    let state = app_handle.state::<AppState>();
+    let online = state.settings.lock().await.publishing.online_now();
+
+    let mut hs = state.hidden_service.lock().await;
+    let Some(hs) = hs.as_mut() else {
+        return Ok(());
+    };
+    if online != hs.is_paused() {
+        return Ok(());
+    }
+    if online {
+        hs.resume().await?;
+        info!("publishing resumed");
+    } else {
+        hs.pause().await?;
+        info!("publishing paused, TOR is dormant");
+    }
+    let _ = app_handle.emit("publishing-changed", online);
+    Ok(())
+}
+
+/// Show the onion address and whether the blog is online in the tray menu
+async fn refresh_tray(app_handle: &tauri::AppHandle) {
+    #[cfg(desktop)]
+    {
+        let Some(tray) = app_handle.try_state::<tray::Tray>() else {
+            return;
+        };
+        let state = app_handle.state::<AppState>();
+        let paused = state.settings.lock().await.publishing.paused;
+        let status = {
+            let hs = state.hidden_service.lock().await;
+            tray::TrayStatus {
+                onion_url: hs.as_ref().and_then(|hs| hs.onion_url()),
+                online: hs.as_ref().is_some_and(|hs| hs.is_running()),
+                paused,
+            }
+        };
+        if let Err(e) = tray.update(&status) {
+            error!("Failed to update the tray menu: {:#}", e);
+        }
+    }
+    #[cfg(not(desktop))]
+    let _ = app_handle;
+}
+
+// Tauri command to write an encrypted backup of the Tor identity, Ghost content and wallet
+#[tauri::command]
+async fn create_backup(
+    state: tauri::State<'_, AppState>,
+    destination: String,
+    passphrase: String,
+) -> Result<backup::BackupReport, String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || {
+        backup::create_backup(&paths, Path::new(&destination), &passphrase)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to verify (dry run) or restore an encrypted backup
+#[tauri::command]
+async fn restore_backup(
+    app_handle: tauri::AppHandle,
+    source: String,
+    passphrase: String,
+    options: backup::RestoreOptions,
+) -> Result<backup::RestoreReport, String> {
+    let state = app_handle.state::<AppState>();
+    // nothing may hold the Tor state, Ghost content or keys open while they are replaced
+    if !options.dry_run {
+        let shutdown = state.settings.lock().await.shutdown.clone();
+        for stage in [Stage::HiddenService, Stage::Ghost, Stage::Proxy, Stage::Tor] {
+            stop_stage(&app_handle, stage, &shutdown)
+                .await
+                .map_err(|e| format!("Failed to stop {:?}, not restoring: {:#}", stage, e))?;
+            state.lifecycle.stopped(stage);
+        }
+        refresh_tray(&app_handle).await;
+    }
+
+    let paths = state.paths();
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        backup::restore_backup(&paths, Path::new(&source), &passphrase, options)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))?;
+
+    if !report.dry_run {
+        // the backup may hold another onion identity
+        if let Some(signatures) = state.signatures.lock().await.as_ref() {
+            signatures.forget_identity();
+        }
+        info!("backup restored, restart freedom-kit to load it");
+    }
+    Ok(report)
+}
+
+// Tauri command to fetch a post's remote media over TOR and replace its embeds
+#[tauri::command]
+async fn localize_post(
+    app_handle: tauri::AppHandle,
+    post_id: String,
+) -> Result<localize::LocalizeReport, String> {
+    run_localize(&app_handle, &post_id)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Localize a post once TOR is up, then reload the editor if it shows the post
+async fn run_localize(
+    app_handle: &tauri::AppHandle,
+    post_id: &str,
+) -> anyhow::Result<localize::LocalizeReport> {
+    let state = app_handle.state::<AppState>();
+    let Some(ghost_port) = *state.ghost_port.lock().await else {
+        anyhow::bail!("Ghost is not running");
+    };
+    let admin = state.admin_session.api(&ghost::base_url(ghost_port))?;
+    let tor_manager = wait_for_localize_tor(&state).await?;
+
+    let report = localize::localize_post(&state.paths(), &tor_manager, &admin, post_id).await?;
+    if report.changed {
+        localized(app_handle, &report);
+    }
+    Ok(report)
+}
+
+/// Localize a post the admin is publishing, the proxy forwards the result
+async fn localize_publish(
+    app_handle: &tauri::AppHandle,
+    publish: tor::proxy::Publish,
+) -> anyhow::Result<()> {
+    let state = app_handle.state::<AppState>();
+    let tor_manager = wait_for_localize_tor(&state).await?;
+
+    let (report, body) = localize::localize_publish(
+        &state.paths(),
+        &tor_manager,
+        &publish.post_id,
+        publish.kind,
+        &publish.body,
+    )
+    .await?;
+    let Some(body) = body else {
+        return Ok(());
+    };
+    let _ = publish.replace.send(body.into());
+
+    if publish.saved.await.is_ok() {
+        localized(app_handle, &report);
+    }
+    Ok(())
+}
+
+async fn wait_for_localize_tor(state: &AppState) -> anyhow::Result<Arc<tor::TorClientManager>> {
+    tor::socks::wait_for_tor(state.tor_client.subscribe())
+        .await
+        .context("TOR is not available, remote media can only be fetched over TOR")
+}
+
+/// Tell the frontend, and reload the editor if it shows the post
+fn localized(app_handle: &tauri::AppHandle, report: &localize::LocalizeReport) {
+    info!(
+        "localized a post: {} media, {} embeds replaced, {} dropped",
+        report.media_localized, report.embeds_replaced, report.media_dropped
+    );
+    let _ = app_handle.emit("post-localized", report);
+
+    // the open editor holds the old version and would overwrite it
+    if let Some(window) = app_handle.get_webview_window("main") {
+        if window
+            .url()
+            .is_ok_and(|url| url.fragment().is_some_and(|f| f.contains(&report.post_id)))
+        {
+            let _ = window.eval("window.location.reload()");
+        }
+    }
+}
+
+// Tauri command to write a static copy of the blog (and a ZIP of it) for offline readers
+#[tauri::command]
+async fn export_site(
+    state: tauri::State<'_, AppState>,
+    destination: String,
+    sign: bool,
+) -> Result<export::ExportReport, String> {
+    let ghost_port = state
+        .ghost_port
+        .lock()
+        .await
+        .ok_or_else(|| "Ghost is not running".to_string())?;
+
+    let identity = if sign {
+        let tor_dir = state.paths().tor_dir;
+        Some(tor::identity::OnionIdentity::load(&tor_dir).map_err(|e| format!("{:#}", e))?)
+    } else {
+        None
+    };
+
+    let report = export::export_site(
+        &ghost::base_url(ghost_port),
+        Path::new(&destination),
+        identity.as_ref(),
+    )
+    .await
+    .map_err(|e| format!("{:#}", e))?;
+
+    info!(
+        "exported {} pages and {} files",
+        report.pages, report.assets
+    );
+    Ok(report)
+}
+
+// Tauri command to check an unpacked export against its signed manifest
+#[tauri::command]
+async fn verify_export(path: String) -> Result<export::VerifyReport, String> {
+    tauri::async_runtime::spawn_blocking(move || export::verify_export(Path::new(&path)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to check a downloaded post against the onion address it claims to be from
+#[tauri::command]
+async fn verify_signature(
+    path: String,
+    onion_address: String,
+    signature: Option<String>,
+) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        signing::verify_file(Path::new(&path), &onion_address, signature.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))
+}
+
+/// A share as shown in the UI, with its full onion URL once the service is up
+#[derive(serde::Serialize)]
+struct SharedFile {
+    #[serde(flatten)]
+    share: share::ShareInfo,
+    url: Option<String>,
+}
+
+async fn shared_file(state: &AppState, share: share::ShareInfo) -> SharedFile {
+    let host = state.hidden_service.lock().await.as_ref().and_then(|hs| {
+        hs.onion_address()
+            .map(|a| a.split(':').next().unwrap_or(a).to_string())
+    });
+    SharedFile {
+        url: host.map(|host| format!("http://{}:{}{}", host, share::SHARE_PORT, share.url_path)),
+        share,
+    }
+}
+
+// Tauri command to offer a file for download on the onion service
+#[tauri::command]
+async fn add_share(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    expires_in: Option<u64>,
+    max_downloads: Option<u32>,
+) -> Result<SharedFile, String> {
+    let share = state
+        .shares
+        .add(Path::new(&path), expires_in, max_downloads)
+        .map_err(|e| format!("{:#}", e))?;
+    info!("sharing a file of {} bytes", share.size);
+    Ok(shared_file(&state, share).await)
+}
+
+// Tauri command to list the files currently shared
+#[tauri::command]
+async fn list_shares(state: tauri::State<'_, AppState>) -> Result<Vec<SharedFile>, String> {
+    let mut shared = Vec::new();
+    for share in state.shares.list() {
+        shared.push(shared_file(&state, share).await);
+    }
+    Ok(shared)
+}
+
+// Tauri command to stop sharing a file
+#[tauri::command]
+async fn revoke_share(state: tauri::State<'_, AppState>, id: String) -> Result<bool, String> {
+    Ok(state.shares.revoke(&id))
+}
+
+// Tauri command to start a throwaway onion service in front of a local port
+#[tauri::command]
+async fn create_ephemeral_onion(
+    state: tauri::State<'_, AppState>,
+    target: String,
+    onion_port: Option<u16>,
+    ttl_secs: Option<u64>,
+) -> Result<tor::ephemeral::EphemeralInfo, String> {
+    let target: std::net::SocketAddr = target
+        .parse()
+        .map_err(|_| format!("{} is not an address like 127.0.0.1:8080", target))?;
+    let hs = state.hidden_service.lock().await;
+    let hs = hs.as_ref().ok_or("TOR is not running yet")?;
+    let info = hs
+        .launch_ephemeral(
+            target,
+            onion_port.unwrap_or(80),
+            ttl_secs.map(Duration::from_secs),
+        )
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+    info!("ephemeral onion service {} → {}", info.id, info.target);
+    Ok(info)
+}
+
+// Tauri command to list the running throwaway onion services
+#[tauri::command]
+async fn list_ephemeral_onions(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<tor::ephemeral::EphemeralInfo>, String> {
+    let hs = state.hidden_service.lock().await;
+    Ok(hs
+        .as_ref()
+        .map(|hs| hs.ephemeral_services())
+        .unwrap_or_default())
+}
+
+// Tauri command to stop a throwaway onion service and forget its keys
+#[tauri::command]
+async fn stop_ephemeral_onion(
+    state: tauri::State<'_, AppState>,
+    id: String,
+) -> Result<bool, String> {
+    let hs = state.hidden_service.lock().await;
+    Ok(hs.as_ref().is_some_and(|hs| hs.stop_ephemeral(&id)))
+}
+
+// Tauri command to change how Tor connects, live where Arti allows it
+#[tauri::command]
+async fn reconfigure_tor(
+    state: tauri::State<'_, AppState>,
+    settings: settings::TorSettings,
+) -> Result<tor::ReconfigureReport, String> {
+    if let Some(dir) = &settings.data_dir {
+        let vault_root = vault_root(&state).await;
+        tor::tor_client::check_data_dir(dir, vault_root.as_deref())
+            .map_err(|e| format!("{:#}", e))?;
+    }
+    let mut saved = state.settings.lock().await;
+    let tor_manager = state.tor_client.borrow().clone();
+    let report = match tor_manager {
+        Some(tor_manager) => tor_manager.reconfigure(&settings),
+        // not bootstrapped yet, the settings are only checked and saved
+        None => tor::tor_client::build_config(None, &settings)
+            .map(|_| tor::ReconfigureReport::pending(&saved.tor, &settings)),
+    }
+    .map_err(|e| format!("{:#}", e))?;
+
+    let mut updated = saved.clone();
+    updated.tor = settings;
+    updated
+        .save(&state.paths().settings_file())
+        .map_err(|e| format!("{:#}", e))?;
+    *saved = updated;
+    Ok(report)
+}
+
+// Tauri command to list the drop box submissions
+#[tauri::command]
+async fn list_submissions(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<dropbox::Submission>, String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || dropbox::DropBox::open(&paths)?.list())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to decrypt a submitted file to a location picked by the user
+#[tauri::command]
+async fn save_submission_file(
+    state: tauri::State<'_, AppState>,
+    id: String,
+    index: usize,
+    destination: String,
+) -> Result<(), String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || {
+        dropbox::DropBox::open(&paths)?.save_file(&id, index, Path::new(&destination))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to shred a drop box submission
+#[tauri::command]
+async fn delete_submission(state: tauri::State<'_, AppState>, id: String) -> Result<(), String> {
+    let paths = state.paths();
+    tauri::async_runtime::spawn_blocking(move || dropbox::DropBox::open(&paths)?.delete(&id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Whether the data lives in an encrypted container and if it is unlocked
+#[derive(serde::Serialize)]
+struct StorageStatus {
+    encrypted: bool,
+    locked: bool,
+    /// problems found by the last storage check
+    problems: Vec<hardening::Finding>,
+}
+
+// Tauri command to report the encrypted storage state
+#[tauri::command]
+async fn storage_status(state: tauri::State<'_, AppState>) -> Result<StorageStatus, String> {
+    let encrypted = AppPaths::vault_file().exists();
+    let unlocked = state.vault.lock().await.is_some();
+    let problems = state
+        .storage_report
+        .lock()
+        .await
+        .unresolved()
+        .cloned()
+        .collect();
+    Ok(StorageStatus {
+        encrypted,
+        locked: encrypted && !unlocked,
+        problems,
+    })
+}
+
+// Tauri command to fix what the storage check can fix, then start the services
+#[tauri::command]
+async fn repair_storage(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), String> {
+    if state.ghost_child.lock().await.is_some() {
+        return Err("Services are already running".to_string());
+    }
+
+    let paths = state.paths();
+    let report =
+        tauri::async_runtime::spawn_blocking(move || hardening::check_storage(&paths, true))
+            .await
+            .map_err(|e| e.to_string())?;
+    let safe = report.is_safe();
+    let summary = report.summary();
+    *state.storage_report.lock().await = report;
+    if !safe {
+        return Err(summary);
+    }
+
+    start_services(&app_handle)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Optional hidden volume inside a new encrypted container
+#[derive(serde::Deserialize)]
+struct HiddenVolume {
+    passphrase: String,
+    size_mb: u64,
+}
+
+// Tauri command to create the encrypted container, used from the next start
+#[tauri::command]
+async fn create_storage(
+    size_mb: u64,
+    passphrase: String,
+    hidden: Option<HiddenVolume>,
+) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        vault::create(
+            &AppPaths::vault_file(),
+            size_mb * 1024 * 1024,
+            &passphrase,
+            hidden
+                .as_ref()
+                .map(|h| (h.passphrase.as_str(), h.size_mb * 1024 * 1024)),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))?;
+
+    info!("encrypted storage created, it will be used from the next start");
+    Ok(())
+}
+
+// Tauri command to unlock the encrypted container and start the services from it
+#[tauri::command]
+async fn unlock_storage(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    passphrase: String,
+) -> Result<(), String> {
+    let mut vault_slot = state.vault.lock().await;
+    if vault_slot.is_some() {
+        return Err("Storage is already unlocked".to_string());
+    }
+
+    let unlocked = tauri::async_runtime::spawn_blocking(move || {
+        vault::unlock(&AppPaths::vault_file(), &passphrase)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("{:#}", e))?;
+
+    // every subsystem resolves its paths from here on
+    let paths = AppPaths::in_vault(unlocked.root());
+    let settings = Settings::load(&paths.settings_file());
+    if let Err(e) = state.logs.open(&paths.logs_dir(), &settings.logging) {
+        error!("Failed to open the log file: {:#}", e);
+    }
+    // earlier versions logged outside the encrypted storage
+    wipe::shred_tree(&AppPaths::resolve().logs_dir());
+    *state.settings.lock().await = settings;
+    *state.paths.write().unwrap() = paths;
+    *vault_slot = Some(unlocked);
+    drop(vault_slot);
+    // sealed again on shutdown, even when the services below fail to start
+    state.lifecycle.started(Stage::Storage);
+
+    info!("encrypted storage unlocked");
+    seal_periodically(app_handle.clone());
+
+    #[cfg(desktop)]
+    {
+        let shortcut = state.settings.lock().await.panic.shortcut.clone();
+        register_panic_shortcut(&app_handle, shortcut.as_deref());
+    }
+
+    start_services(&app_handle)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+// Tauri command to read the persisted settings
+#[tauri::command]
+async fn get_settings(state: tauri::State<'_, AppState>) -> Result<Settings, String> {
+    Ok(state.settings.lock().await.clone())
+}
+
+// Tauri command to update and persist the settings
+#[tauri::command]
+async fn save_settings(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    settings: Settings,
+) -> Result<(), String> {
+    if let Some(schedule) = &settings.publishing.schedule {
+        schedule.validate().map_err(|e| format!("{:#}", e))?;
+    }
+    settings
+        .save(&state.paths().settings_file())
+        .map_err(|e| format!("{:#}", e))?;
+
+    #[cfg(desktop)]
+    register_panic_shortcut(&app_handle, settings.panic.shortcut.as_deref());
+
+    *state.settings.lock().await = settings;
+    Ok(())
+}
+
+/// Optional encrypted backup taken right before a panic wipe
+#[derive(serde::Deserialize)]
+struct PanicBackup {
+    /// should point at removable media, not the disk being wiped
+    destination: String,
+    passphrase: String,
+}
+
+// Tauri command to stop everything and destroy keys, content and logs
+#[tauri::command]
+async fn panic_wipe(
+    app_handle: tauri::AppHandle,
+    scope: Option<wipe::WipeScope>,
+    backup: Option<PanicBackup>,
+) -> Result<wipe::WipeReport, String> {
+    let scope = match scope {
+        Some(scope) => scope,
+        None => {
+            app_handle
+                .state::<AppState>()
+                .settings
+                .lock()
+                .await
+                .panic
+                .scope
+        }
+    };
+    run_panic_wipe(&app_handle, scope, backup)
+        .await
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Stop the hidden service and sidecars, then wipe the data in `scope`
+async fn run_panic_wipe(
+    app_handle: &tauri::AppHandle,
+    scope: wipe::WipeScope,
+    backup: Option<PanicBackup>,
+) -> anyhow::Result<wipe::WipeReport> {
+    let state = app_handle.state::<AppState>();
+    let paths = state.paths();
+
+    // back up first, if that fails the user should decide what to do
+    if let Some(backup) = backup {
+        info!("writing pre-wipe backup...");
+        let paths = paths.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            backup::create_backup(&paths, Path::new(&backup.destination), &backup.passphrase)
+        })
+        .await??;
+    }
+
+    info!("panic wipe: stopping hidden service and sidecars...");
+    if let Some(mut hs) = state.hidden_service.lock().await.take() {
+        if let Err(e) = hs.stop().await {
+            error!("Failed to stop hidden service: {}", e);
+        }
+    }
+    state.shares.clear();
+    state.lifecycle.stopped(Stage::HiddenService);
+    refresh_tray(app_handle).await;
+    // no grace period here, this has to be fast
+    if let Some(child) = state.ghost_child.lock().await.take() {
+        if let Err(e) = child.kill() {
+            error!("Failed to kill Ghost process: {}", e);
+        }
+    }
+    lifecycle::forget_sidecar(&AppPaths::pid_file(), "ghost");
+    state.lifecycle.stopped(Stage::Ghost);
+    // nothing may hold the Tor state or keys open while they are shredded
+    let shutdown = state.settings.lock().await.shutdown.clone();
+    for stage in [Stage::Proxy, Stage::Tor] {
+        if let Err(e) = stop_stage(app_handle, stage, &shutdown).await {
+            error!("Failed to stop {:?}: {:#}", stage, e);
+        }
+        state.lifecycle.stopped(stage);
+    }
+
+    let vault = state.vault.lock().await.take();
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        let mut report = wipe::panic_wipe(&paths, scope);
+
+        // the container still holds the encrypted copy of what we just wiped
+        match (vault, scope) {
+            (Some(vault), wipe::WipeScope::IdentityOnly) => {
+                // the older copy in the twin slot still holds the identity
+                if let Err(e) = vault.lock_without_history() {
+                    error!("Failed to reseal encrypted storage: {:#}", e);
+                }
+            }
+            (vault, wipe::WipeScope::Everything) => {
+                if let Some(vault) = vault {
+                    vault.discard();
+                }
+                // locked or not, the container goes
+                let container = wipe::shred_tree(&AppPaths::vault_file());
+                report.wiped.extend(container.wiped);
+                report.failed.extend(container.failed);
+            }
+            (None, wipe::WipeScope::IdentityOnly) => {}
+        }
+        report
+    })
+    .await?;
+    if let Some(signatures) = state.signatures.lock().await.as_ref() {
+        signatures.forget_identity();
+    }
+
+    let _ = app_handle.emit("panic-wipe-complete", &report);
+    Ok(report)
+}
+
+// Tauri command to stop every service in order and quit
+#[tauri::command]
+async fn shutdown(app_handle: tauri::AppHandle) -> Result<(), String> {
+    shutdown_services(&app_handle).await;
+    app_handle.exit(0);
+    Ok(())
+}
+
+/// Stop whatever is running, last started first
+///
+/// Safe to call more than once, later calls wait for the first to finish.
+async fn shutdown_services(app_handle: &tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let settings = state.settings.lock().await.shutdown.clone();
+    state
+        .lifecycle
+        .shutdown(&settings, |stage| stop_stage(app_handle, stage, &settings))
+        .await;
+}
+
+/// Take one stage down, see [`lifecycle::Stage`]
+async fn stop_stage(
+    app_handle: &tauri::AppHandle,
+    stage: Stage,
+    settings: &lifecycle::ShutdownSettings,
+) -> anyhow::Result<()> {
+    let state = app_handle.state::<AppState>();
+    match stage {
+        // no wallet sidecar is started yet
+        Stage::Wallet => {}
+        Stage::HiddenService => {
+            // throwaway onion services go with it, their keys were only in memory
+            if let Some(mut hs) = state.hidden_service.lock().await.take() {
+                hs.stop().await?;
+            }
+            state.shares.clear();
+        }
+        Stage::Ghost => {
+            let Some(child) = state.ghost_child.lock().await.take() else {
+                return Ok(());
+            };
+            let watcher = state.ghost_watcher.lock().await.take();
+            let exited = async move {
+                match watcher {
+                    Some(watcher) => {
+                        let _ = watcher.await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
+            // Ghost closes its database on SIGTERM, a kill could leave it half written
+            info!("stopping Ghost sidecar...");
+            if lifecycle::terminate(child.pid(), exited, settings.ghost_grace()).await {
+                info!("Ghost exited");
+            } else {
+                warn!("Ghost did not exit in time, killing it");
+                child.kill().context("Failed to kill Ghost process")?;
+            }
+            lifecycle::forget_sidecar(&AppPaths::pid_file(), "ghost");
+        }
+        Stage::Proxy => {
+            if let Some(handle) = state.proxy_handle.lock().await.take() {
+                handle.abort();
+                let _ = handle.await;
+            }
+        }
+        Stage::Tor => {
+            // the SOCKS proxy holds new connections until a client is back
+            if state.tor_client.send_replace(None).is_some() {
+                info!("TOR client closed");
+            }
+        }
+        Stage::Storage => {
+            // Write the encrypted volume back and destroy the decrypted copy
+            if let Some(vault) = state.vault.lock().await.take() {
+                info!("sealing encrypted storage...");
+                tauri::async_runtime::spawn_blocking(move || vault.lock())
+                    .await?
+                    .context("Failed to seal encrypted storage")?;
+                info!("encrypted storage sealed");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Carry out what was picked in the tray menu
+#[cfg(desktop)]
+fn on_tray_action(app_handle: &tauri::AppHandle, action: tray::Action) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app_handle.state::<AppState>();
+        match action {
+            tray::Action::CopyAddress => {
+                use tauri_plugin_clipboard_manager::ClipboardExt;
+
+                let url = state
+                    .hidden_service
+                    .lock()
+                    .await
+                    .as_ref()
+                    .and_then(|hs| hs.onion_url());
+                if let Some(url) = url {
+                    if let Err(e) = app_handle.clipboard().write_text(url) {
+                        error!("Failed to copy the onion address: {}", e);
+                    }
+                }
+            }
+            tray::Action::TogglePublishing => {
+                let result = update_publishing(&app_handle, |publishing| {
+                    publishing.paused = !publishing.paused
+                })
+                .await;
+                if let Err(e) = result {
+                    error!("Failed to switch publishing from the tray: {:#}", e);
+                }
+            }
+            tray::Action::OpenAdmin => show_admin(&app_handle).await,
+            tray::Action::Quit => {
+                shutdown_services(&app_handle).await;
+                app_handle.exit(0);
+            }
+        }
+    });
+}
+
+/// Seal the unlocked container every `storage.seal_interval_secs`, so a
+/// crash does not lose everything since it was unlocked
+fn seal_periodically(app_handle: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let state = app_handle.state::<AppState>();
+        let secs = state.settings.lock().await.storage.seal_interval_secs;
+        if secs == 0 {
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(secs));
+        // the first tick is immediate, there is nothing to seal yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if state.lifecycle.is_shutting_down() {
+                return;
+            }
+            let mut vault = state.vault.lock().await;
+            // locked or wiped in the meantime
+            let Some(vault) = vault.as_mut() else {
+                return;
+            };
+            if let Err(e) = tokio::task::block_in_place(|| vault.seal()) {
+                error!("Failed to seal encrypted storage: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Bring the hidden window back, on Ghost admin once Ghost is up
+#[cfg(desktop)]
+async fn show_admin(app_handle: &tauri::AppHandle) {
+    let Some(window) = app_handle.get_webview_window("main") else {
+        return;
+    };
+    let _ = window.show();
+    let _ = window.unminimize();
+    let _ = window.set_focus();
+
+    // the loading screen gets there on its own, the blog's own pages do not
+    let Some(ghost_port) = *app_handle.state::<AppState>().ghost_port.lock().await else {
+        return;
+    };
+    if let Ok(url) = window.url() {
+        if ghost::is_ghost_url(&url, ghost_port) && !url.path().starts_with("/ghost") {
+            let admin_url = format!("{}/ghost", ghost::base_url(ghost_port));
+            if let Err(e) = window.navigate(tauri::Url::parse(&admin_url).unwrap()) {
+                error!("Failed to open Ghost admin: {}", e);
+            }
+        }
+    }
+}
+
+/// (Re)register the global panic shortcut, `None` disables it
+#[cfg(desktop)]
+fn register_panic_shortcut(app_handle: &tauri::AppHandle, shortcut: Option<&str>) {
+    use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+    let global_shortcut = app_handle.global_shortcut();
+    if let Err(e) = global_shortcut.unregister_all() {
+        error!("Failed to unregister shortcuts: {}", e);
+    }
+
+    let Some(shortcut) = shortcut else {
+        return;
+    };
+
+    let result = global_shortcut.on_shortcut(shortcut, |app, _shortcut, event| {
+        if event.state != ShortcutState::Pressed {
+            return;
+        }
+
+        // no confirmation on purpose, this has to work in seconds
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let scope = app.state::<AppState>().settings.lock().await.panic.scope;
+            if let Err(e) = run_panic_wipe(&app, scope, None).await {
+                error!("Panic wipe failed: {}", e);
+            }
+            app.exit(0);
+        });
+    });
+
+    match result {
+        Ok(_) => info!("panic shortcut registered: {}", shortcut),
+        Err(e) => error!("Failed to register panic shortcut {}: {}", shortcut, e),
+    }
 }
 
-/// Inject the combined TOR + Railgun banner into the current page
+/// Inject the onion address banner into the current page
 async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
-    println!("🔄 Injecting combined banner into Ghost page...");
+    info!("injecting onion banner into Ghost page...");
 
     // Get the onion address
     let state = app_handle.state::<AppState>();
@@ -42,20 +1188,9 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
         hs.as_ref().and_then(|s| s.onion_url())
     };
 
-    // Mock Railgun data - pretending the user already has a loaded wallet
-    let railgun_address =
-        Some("0zk1qyk9nn5zvjwprfv2qe6cdmzk4wwvvz7w3qjk8xrandom3example8address4mock".to_string());
-    let railgun_balance = "0".to_string();
-
-    // NOTE: Railgun sidecar is disabled - using mock data above
-    // let client = reqwest::Client::new();
-    // let railgun_address = match client.get("http://localhost:8080/wallet/address").send().await { ... };
-    // let railgun_balance = match client.get("http://localhost:8080/wallet/balance").send().await { ... };
-
-    let banner_js = if onion_address.is_some() || railgun_address.is_some() {
-        let tor_section = if let Some(addr) = &onion_address {
-            format!(
-                r#"
+    let banner_js = if let Some(addr) = &onion_address {
+        let tor_section = format!(
+            r#"
             <div style="display: flex; align-items: center; gap: 12px; flex: 1; min-width: 0;">
                 <div style="font-size: 20px;">🧅</div>
                 <div style="flex: 1; min-width: 0;">
@@ -80,55 +1215,8 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
                 ">📋 Copy</button>
             </div>
             "#,
-                addr
-            )
-        } else {
-            String::new()
-        };
-
-        let railgun_section = if let Some(addr) = &railgun_address {
-            format!(
-                r#"
-            <div style="display: flex; align-items: center; gap: 12px; flex: 1; min-width: 0;">
-                <div style="font-size: 20px;">🔒</div>
-                <div style="flex: 1; min-width: 0;">
-                    <div style="font-weight: 600; font-size: 13px; margin-bottom: 2px;">
-                        Railgun Wallet
-                    </div>
-                    <div style="display: flex; gap: 12px; align-items: center;">
-                        <div style="font-family: monospace; font-size: 11px; opacity: 0.95; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; flex: 1; min-width: 0;">
-                            {}
-                        </div>
-                        <div class="railgun-balance" style="font-weight: 600; font-size: 12px; white-space: nowrap;">
-                            {} WETH
-                        </div>
-                    </div>
-                </div>
-                <button id='copy-railgun-btn' style="
-                    background: rgba(255, 255, 255, 0.2);
-                    border: none;
-                    color: white;
-                    padding: 6px 14px;
-                    border-radius: 6px;
-                    cursor: pointer;
-                    font-size: 12px;
-                    font-weight: 500;
-                    transition: all 0.2s;
-                    white-space: nowrap;
-                ">📋 Copy</button>
-            </div>
-            "#,
-                addr, railgun_balance
-            )
-        } else {
-            String::new()
-        };
-
-        let divider = if !tor_section.is_empty() && !railgun_section.is_empty() {
-            r#"<div style="width: 1px; height: 40px; background: rgba(255, 255, 255, 0.3); margin: 0 8px;"></div>"#
-        } else {
-            ""
-        };
+            addr
+        );
 
         format!(
             r#"
@@ -158,8 +1246,6 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
     `;
     
     banner.innerHTML = `
-        {}
-        {}
         {}
         <button id='close-onion-banner' style="
             background: rgba(255, 255, 255, 0.2);
@@ -218,37 +1304,6 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
         }});
     }}
     
-    const railgunBtn = document.getElementById('copy-railgun-btn');
-    if (railgunBtn) {{
-        railgunBtn.addEventListener('click', async () => {{
-            try {{
-                await navigator.clipboard.writeText('{}');
-                railgunBtn.textContent = '✅ Copied!';
-                railgunBtn.style.background = 'rgba(76, 175, 80, 0.5)';
-                setTimeout(() => {{
-                    railgunBtn.textContent = '📋 Copy';
-                    railgunBtn.style.background = 'rgba(255, 255, 255, 0.2)';
-                }}, 2000);
-            }} catch (error) {{
-                console.error('Failed to copy:', error);
-                railgunBtn.textContent = '❌ Failed';
-                setTimeout(() => {{
-                    railgunBtn.textContent = '📋 Copy';
-                }}, 2000);
-            }}
-        }});
-        railgunBtn.addEventListener('mouseenter', () => {{
-            if (!railgunBtn.textContent.includes('Copied')) {{
-                railgunBtn.style.background = 'rgba(255, 255, 255, 0.3)';
-            }}
-        }});
-        railgunBtn.addEventListener('mouseleave', () => {{
-            if (!railgunBtn.textContent.includes('Copied')) {{
-                railgunBtn.style.background = 'rgba(255, 255, 255, 0.2)';
-            }}
-        }});
-    }}
-    
     // Close button handler
     document.getElementById('close-onion-banner').addEventListener('click', () => {{
         banner.style.transform = 'translateY(-100%)';
@@ -271,32 +1326,9 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
     setTimeout(() => {{
         banner.style.transform = 'translateY(0)';
     }}, 10);
-    
-    // NOTE: Balance refresh disabled since we're using mock Railgun data
-    // const hasRailgun = banner.querySelector('.railgun-balance');
-    // if (hasRailgun) {{
-    //     setInterval(async () => {{
-    //         try {{
-    //             const response = await fetch('http://localhost:8080/wallet/balance');
-    //             if (response.ok) {{
-    //                 const data = await response.json();
-    //                 const balanceEl = banner.querySelector('.railgun-balance');
-    //                 if (balanceEl) {{
-    //                     balanceEl.textContent = data.balance + ' WETH';
-    //                 }}
-    //             }}
-    //         }} catch (error) {{
-    //             console.error('Failed to refresh balance:', error);
-    //         }}
-    //     }}, 30000);
-    // }}
 }})();
 "#,
-            tor_section,
-            divider,
-            railgun_section,
-            onion_address.as_ref().unwrap_or(&"".to_string()),
-            railgun_address.as_ref().unwrap_or(&"".to_string())
+            tor_section, addr
         )
     } else {
         // If onion address is not ready yet, show a loading banner
@@ -342,238 +1374,598 @@ async fn inject_onion_banner(app_handle: &tauri::AppHandle) {
     // Inject the JavaScript
     if let Some(window) = app_handle.get_webview_window("main") {
         match window.eval(&banner_js) {
-            Ok(_) => println!("✅ Onion banner injected successfully"),
-            Err(e) => eprintln!("❌ Failed to inject banner: {}", e),
+            Ok(_) => info!("onion banner injected successfully"),
+            Err(e) => error!("Failed to inject banner: {}", e),
         }
     }
 }
 
-/// Check if Ghost is ready by polling the endpoint
-async fn wait_for_ghost_ready(url: &str, max_attempts: u32) -> anyhow::Result<()> {
-    println!("⏳ Waiting for Ghost to be ready at {}...", url);
-
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(5))
-        .build()?;
-
-    let mut successful_checks = 0;
-    const REQUIRED_SUCCESSFUL_CHECKS: u32 = 3;
-
-    for attempt in 1..=max_attempts {
-        match client.get(url).send().await {
-            Ok(response)
-                if response.status().is_success() || response.status().is_redirection() =>
-            {
-                // Check if we got actual HTML content
-                match response.text().await {
-                    Ok(body) if !body.is_empty() && body.len() > 100 => {
-                        successful_checks += 1;
-                        println!(
-                            "✅ Ghost responded with content (check {}/{}, attempt {}/{})",
-                            successful_checks, REQUIRED_SUCCESSFUL_CHECKS, attempt, max_attempts
-                        );
-
-                        // Require multiple successful checks to ensure Ghost is truly ready
-                        if successful_checks >= REQUIRED_SUCCESSFUL_CHECKS {
-                            println!("🎉 Ghost is fully ready!");
-                            // Add a final delay to ensure Ghost has finished initializing
-                            tokio::time::sleep(Duration::from_secs(2)).await;
-                            return Ok(());
-                        }
-                    }
-                    Ok(body) => {
-                        println!(
-                            "⏳ Ghost responded but content looks incomplete (size: {} bytes, attempt {}/{})",
-                            body.len(),
-                            attempt,
-                            max_attempts
-                        );
-                        successful_checks = 0; // Reset counter if content looks incomplete
-                    }
-                    Err(e) => {
-                        println!(
-                            "⏳ Ghost responded but failed to read body: {} (attempt {}/{})",
-                            e, attempt, max_attempts
-                        );
-                        successful_checks = 0;
-                    }
-                }
-            }
-            Ok(response) => {
-                println!(
-                    "⏳ Ghost returned status {} (attempt {}/{})",
-                    response.status(),
-                    attempt,
-                    max_attempts
-                );
-                successful_checks = 0;
-            }
-            Err(e) => {
-                println!(
-                    "⏳ Ghost not ready yet: {} (attempt {}/{})",
-                    e, attempt, max_attempts
-                );
-                successful_checks = 0;
-            }
-        }
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let paths = AppPaths::resolve();
+    let settings = Settings::load(&paths.settings_file());
 
-        if attempt < max_attempts {
-            tokio::time::sleep(Duration::from_secs(2)).await;
+    // Logs are written where the data is, inside the encrypted storage once
+    // it is unlocked when there is one
+    let logs = logging::init(&settings.logging);
+    if !AppPaths::vault_file().exists() {
+        if let Err(e) = logs.open(&paths.logs_dir(), &settings.logging) {
+            error!("Failed to open the log file: {:#}", e);
         }
     }
 
-    Err(anyhow::anyhow!(
-        "Ghost failed to start after {} attempts",
-        max_attempts
-    ))
-}
-
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+    // A crash or a kill -9 last time leaves Ghost running and holding its port
+    lifecycle::reap_orphans(&AppPaths::pid_file(), settings.shutdown.ghost_grace());
+    // the same crash leaves the unlocked container decrypted in RAM
+    vault::shred_leftovers();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_opener::init())
         .manage(AppState {
+            paths: std::sync::RwLock::new(paths),
+            logs,
+            settings: Arc::new(Mutex::new(settings)),
+            vault: Mutex::new(None),
+            storage_report: Mutex::new(Default::default()),
+            ghost_port: Mutex::new(None),
+            tor_client: Arc::new(tokio::sync::watch::channel(None).0),
             hidden_service: Arc::new(Mutex::new(None)),
+            shares: Arc::new(share::ShareManager::default()),
             ghost_child: Arc::new(Mutex::new(None)),
+            ghost_watcher: Mutex::new(None),
+            proxy_handle: Mutex::new(None),
+            admin_session: ghost::AdminSession::default(),
+            signatures: Mutex::new(None),
+            lifecycle: lifecycle::Lifecycle::default(),
+            sidecar_exits: Mutex::new(Vec::new()),
         })
         .invoke_handler(tauri::generate_handler![
             get_onion_address,
             is_tor_running,
-            // NOTE: Railgun commands disabled - using mock data instead
-            // check_railgun_wallet_exists,
-            // create_railgun_wallet,
-            // unlock_railgun_wallet,
-            // get_railgun_address,
-            // get_railgun_balance
+            create_backup,
+            restore_backup,
+            get_settings,
+            save_settings,
+            panic_wipe,
+            storage_status,
+            repair_storage,
+            localize_post,
+            export_site,
+            verify_export,
+            verify_signature,
+            add_share,
+            list_shares,
+            revoke_share,
+            list_submissions,
+            save_submission_file,
+            delete_submission,
+            create_ephemeral_onion,
+            list_ephemeral_onions,
+            stop_ephemeral_onion,
+            reconfigure_tor,
+            get_tor_metrics,
+            read_access_log,
+            clear_access_log,
+            get_logs,
+            export_diagnostics,
+            create_address_card,
+            get_publishing_status,
+            set_publishing_paused,
+            set_publish_schedule,
+            create_storage,
+            unlock_storage,
+            shutdown,
         ])
         .setup(|app| {
-            // Get the path to the ghost-sidecar binary
-            let sidecar_command = app.shell().sidecar("ghost-sidecar").unwrap();
-
-            // Spawn the sidecar process
-            let (mut rx, child) = sidecar_command
-                .spawn()
-                .expect("Failed to spawn Ghost sidecar");
-
-            // Store the child process in app state for cleanup on exit
-            let state = app.state::<AppState>();
-            tauri::async_runtime::block_on(async {
-                *state.ghost_child.lock().await = Some(child);
+            create_main_window(app)?;
+
+            // Register the panic button before anything else starts
+            #[cfg(desktop)]
+            {
+                app.handle()
+                    .plugin(tauri_plugin_global_shortcut::Builder::new().build())?;
+                let shortcut = tauri::async_runtime::block_on(async {
+                    app.state::<AppState>()
+                        .settings
+                        .lock()
+                        .await
+                        .panic
+                        .shortcut
+                        .clone()
+                });
+                register_panic_shortcut(app.handle(), shortcut.as_deref());
+            }
+
+            // With a tray icon the blog keeps publishing while the window is closed
+            #[cfg(desktop)]
+            {
+                app.handle()
+                    .plugin(tauri_plugin_clipboard_manager::init())?;
+                let enabled = tauri::async_runtime::block_on(async {
+                    app.state::<AppState>().settings.lock().await.tray.enabled
+                });
+                if enabled {
+                    match tray::Tray::create(app.handle(), on_tray_action) {
+                        Ok(tray) => {
+                            app.manage(tray);
+                        }
+                        Err(e) => error!(
+                            "Failed to create the tray icon, closing the window quits: {:#}",
+                            e
+                        ),
+                    }
+                }
+            }
+
+            // SIGTERM and friends get the same orderly shutdown as quitting from the tray
+            let signal_app = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                match lifecycle::termination_signal().await {
+                    Ok(signal) => {
+                        info!("received {}, shutting down...", signal);
+                        shutdown_services(&signal_app).await;
+                        signal_app.exit(0);
+                    }
+                    Err(e) => error!("Failed to listen for termination signals: {}", e),
+                }
             });
 
-            // Create a thread to handle output from the sidecar
+            // The dashboard gets fresh Tor metrics without polling
+            let metrics_app = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                while let Some(event) = rx.recv().await {
-                    match event {
-                        tauri_plugin_shell::process::CommandEvent::Stdout(line) => {
-                            println!("[Ghost stdout]: {}", String::from_utf8_lossy(&line));
-                        }
-                        tauri_plugin_shell::process::CommandEvent::Stderr(line) => {
-                            eprintln!("[Ghost stderr]: {}", String::from_utf8_lossy(&line));
-                        }
-                        tauri_plugin_shell::process::CommandEvent::Error(err) => {
-                            eprintln!("[Ghost error]: {}", err);
-                        }
-                        tauri_plugin_shell::process::CommandEvent::Terminated(status) => {
-                            println!("[Ghost terminated]: {:?}", status);
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+                    tor::metrics::EVENT_INTERVAL_SECS,
+                ));
+                loop {
+                    interval.tick().await;
+                    let metrics = collect_tor_metrics(&metrics_app.state::<AppState>()).await;
+                    let _ = metrics_app.emit("tor-metrics", metrics);
+                }
+            });
+
+            // The publishing schedule is followed for as long as the app runs
+            let schedule_app = app.handle().clone();
+            tauri::async_runtime::spawn(
+                async move {
+                    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+                        publishing::CHECK_INTERVAL_SECS,
+                    ));
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = apply_publishing(&schedule_app).await {
+                            error!("Failed to follow the publishing schedule: {:#}", e);
                         }
-                        _ => {}
+                        refresh_tray(&schedule_app).await;
                     }
                 }
-            });
+                .instrument(info_span!("hs")),
+            );
+
+            // Encrypted storage has to be unlocked before anything touches the data
+            if AppPaths::vault_file().exists() {
+                info!("encrypted storage found, waiting for passphrase...");
+                let _ = app.emit("storage-locked", ());
+            } else {
+                // failures are reported to the UI, keep the window up to show them
+                if let Err(e) = tauri::async_runtime::block_on(start_services(app.handle())) {
+                    error!("Failed to start services: {:#}", e);
+                }
+            }
 
-            // NOTE: Railgun sidecar disabled - using mock data in banner
-            // Spawn the railgun-sidecar binary
-            // let railgun_sidecar_command = app.shell().sidecar("railgun-sidecar").unwrap();
-            // let (mut railgun_rx, railgun_child) = railgun_sidecar_command
-            //     .spawn()
-            //     .expect("Failed to spawn Railgun sidecar");
-
-            // Store the Railgun child process in app state
-            // let state = app.state::<AppState>();
-            // tauri::async_runtime::block_on(async {
-            //     *state.railgun_child.lock().await = Some(railgun_child);
-            // });
-
-            // Create a thread to handle output from the railgun sidecar
-            // tauri::async_runtime::spawn(async move {
-            //     while let Some(event) = railgun_rx.recv().await {
-            //         match event {
-            //             tauri_plugin_shell::process::CommandEvent::Stdout(line) => {
-            //                 println!("[Railgun stdout]: {}", String::from_utf8_lossy(&line));
-            //             }
-            //             tauri_plugin_shell::process::CommandEvent::Stderr(line) => {
-            //                 eprintln!("[Railgun stderr]: {}", String::from_utf8_lossy(&line));
-            //             }
-            //             tauri_plugin_shell::process::CommandEvent::Error(err) => {
-            //                 eprintln!("[Railgun error]: {}", err);
-            //             }
-            //             tauri_plugin_shell::process::CommandEvent::Terminated(status) => {
-            //                 println!("[Railgun terminated]: {:?}", status);
-            //             }
-            //             _ => {}
-            //         }
-            //     }
-            // });
-
-            // Wait for Ghost to be ready and then navigate
-            let app_handle = app.handle().clone();
+            Ok(())
+        })
+        // every Ghost admin page gets the banner as soon as it has loaded
+        .on_page_load(|webview, payload| {
+            if webview.label() != "main" || payload.event() != PageLoadEvent::Finished {
+                return;
+            }
+            let app_handle = webview.app_handle().clone();
+            let url = payload.url().clone();
             tauri::async_runtime::spawn(async move {
-                match wait_for_ghost_ready("http://localhost:2368", 40).await {
-                    Ok(_) => {
-                        println!("🎉 Ghost is ready!");
-
-                        // Emit ghost-ready event to the frontend
-                        let _ = app_handle.emit("ghost-ready", ());
-
-                        // Wait a moment to let the loading screen show the onion address if available
-                        tokio::time::sleep(Duration::from_secs(2)).await;
-
-                        // Now navigate to Ghost
-                        if let Some(window) = app_handle.get_webview_window("main") {
-                            match window
-                                .navigate(tauri::Url::parse("http://localhost:2368/ghost").unwrap())
-                            {
-                                Ok(_) => {
-                                    println!("✅ Window navigated to Ghost");
-
-                                    // Clone app_handle for the async tasks
-                                    let app_for_banner = app_handle.clone();
-                                    // let app_for_wallet = app_handle.clone(); // Not needed - wallet popup disabled
-
-                                    // Wait a moment for the page to load, then inject the banner
-                                    tauri::async_runtime::spawn(async move {
-                                        tokio::time::sleep(Duration::from_secs(2)).await;
-                                        inject_onion_banner(&app_for_banner).await;
-                                    });
-
-                                    // NOTE: Skipping Railgun wallet setup popup - pretending user already has loaded wallet
-                                    // Show Railgun wallet setup popup after a brief delay
-                                    // tauri::async_runtime::spawn(async move {
-                                    //     tokio::time::sleep(Duration::from_secs(3)).await;
-                                    //     inject_railgun_wallet_popup(&app_for_wallet).await;
-                                    // });
-                                }
-                                Err(e) => eprintln!("❌ Failed to navigate window: {}", e),
+                let ghost_port = *app_handle.state::<AppState>().ghost_port.lock().await;
+                if ghost_port.is_some_and(|port| ghost::is_ghost_url(&url, port))
+                    && url.path().starts_with("/ghost")
+                {
+                    inject_onion_banner(&app_handle).await;
+                }
+            });
+        })
+        .on_window_event(|window, event| {
+            // the tray keeps the app (and the blog) running, the window only hides
+            #[cfg(desktop)]
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                if window.label() == "main" && window.try_state::<tray::Tray>().is_some() {
+                    api.prevent_close();
+                    match window.hide() {
+                        Ok(_) => info!("window hidden, still publishing from the tray"),
+                        Err(e) => error!("Failed to hide the window: {}", e),
+                    }
+                }
+            }
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| match event {
+            // however the app quits, everything is stopped in order first
+            tauri::RunEvent::Exit => {
+                tauri::async_runtime::block_on(shutdown_services(app_handle));
+            }
+            // clicking the dock icon brings the hidden window back
+            #[cfg(target_os = "macos")]
+            tauri::RunEvent::Reopen { .. } => {
+                let app_handle = app_handle.clone();
+                tauri::async_runtime::spawn(async move { show_admin(&app_handle).await });
+            }
+            _ => {}
+        });
+}
+
+/// Create the main window, routed through Tor where the webview supports a proxy
+fn create_main_window(app: &tauri::App) -> anyhow::Result<()> {
+    let config = app
+        .config()
+        .app
+        .windows
+        .iter()
+        .find(|w| w.label == "main")
+        .context("No main window configured")?
+        .clone();
+    let mut builder = tauri::WebviewWindowBuilder::from_config(app.handle(), &config)?;
+
+    let _proxy = info_span!("proxy").entered();
+    let mut webview_proxy = None;
+    if WEBVIEW_TOR {
+        // only the webview gets the credentials, other programs cannot use the port
+        let auth = tor::socks::SocksAuth::random();
+        let tor_client = app.state::<AppState>().tor_client.subscribe();
+        let (socks_port, _socks_handle) = tauri::async_runtime::block_on(
+            tor::socks::start_socks_proxy(tor_client, tor::Purpose::Webview, auth.clone()),
+        )?;
+        info!(
+            "webview traffic is routed through TOR (SOCKS port {})",
+            socks_port
+        );
+        // Tauri only passes host and port on, without credentials the proxy
+        // refuses every request until the webview has them
+        builder = builder.proxy_url(tauri::Url::parse(&format!(
+            "socks5://127.0.0.1:{}",
+            socks_port
+        ))?);
+        webview_proxy = Some(auth.proxy_url(socks_port));
+    } else {
+        // No proxy: Ghost pages get a lockdown CSP, and links leave nowhere
+        warn!("this webview cannot use a proxy, blocking third-party requests instead");
+        builder = builder.on_navigation(|url| {
+            let allowed = is_local_url(url);
+            if !allowed {
+                info_span!("proxy")
+                    .in_scope(|| info!("blocked navigation to {} (open it in Tor Browser)", url));
+            }
+            allowed
+        });
+    }
+
+    let window = builder.build()?;
+    if let Some(proxy) = webview_proxy {
+        set_webview_proxy(&window, proxy)?;
+    }
+    Ok(())
+}
+
+/// Point WebKit at the SOCKS proxy, credentials included
+#[cfg(target_os = "linux")]
+fn set_webview_proxy(window: &tauri::WebviewWindow, proxy: String) -> anyhow::Result<()> {
+    use webkit2gtk::{
+        NetworkProxyMode, NetworkProxySettings, WebContextExt, WebViewExt, WebsiteDataManagerExt,
+    };
+
+    window.with_webview(move |webview| {
+        let data_manager = webview
+            .inner()
+            .context()
+            .and_then(|context| context.website_data_manager());
+        match data_manager {
+            Some(data_manager) => {
+                let mut settings = NetworkProxySettings::new(Some(proxy.as_str()), &[]);
+                data_manager
+                    .set_network_proxy_settings(NetworkProxyMode::Custom, Some(&mut settings));
+            }
+            None => error!("Failed to set the webview proxy: the webview has no data manager"),
+        }
+    })?;
+    Ok(())
+}
+
+/// Only WebKitGTK takes SOCKS credentials, see [`WEBVIEW_TOR`]
+#[cfg(not(target_os = "linux"))]
+fn set_webview_proxy(_window: &tauri::WebviewWindow, _proxy: String) -> anyhow::Result<()> {
+    anyhow::bail!("This webview cannot log in to a SOCKS proxy")
+}
+
+/// Whether `url` stays on this machine (app assets, Ghost, error pages)
+fn is_local_url(url: &tauri::Url) -> bool {
+    match url.scheme() {
+        "tauri" | "asset" | "ipc" | "data" | "about" | "blob" => true,
+        _ => match url.host_str() {
+            Some(host) => {
+                host == "localhost"
+                    || host.ends_with(".localhost")
+                    || host == "127.0.0.1"
+                    || host == "[::1]"
+            }
+            None => false,
+        },
+    }
+}
+
+/// where the encrypted storage is unlocked to, `None` when it is not in use
+async fn vault_root(state: &AppState) -> Option<PathBuf> {
+    state
+        .vault
+        .lock()
+        .await
+        .as_ref()
+        .map(|vault| vault.root().to_path_buf())
+}
+
+/// Spawn Ghost and bring up the hidden service, once storage is available
+async fn start_services(app_handle: &tauri::AppHandle) -> anyhow::Result<()> {
+    let state = app_handle.state::<AppState>();
+
+    // A Tor data directory picked in the settings replaces the default one everywhere
+    let tor_dir = state.settings.lock().await.tor.data_dir.clone();
+    let mut default_tor_dir = None;
+    if let Some(tor_dir) = tor_dir {
+        let vault_root = vault_root(&state).await;
+        tor::tor_client::check_data_dir(&tor_dir, vault_root.as_deref())?;
+        let mut paths = state.paths.write().unwrap();
+        default_tor_dir = Some(std::mem::replace(&mut paths.tor_dir, tor_dir));
+    }
+    let paths = state.paths();
+
+    // Refuse to put the identity, blog or wallet anywhere others can read them
+    let repair = state.settings.lock().await.storage.auto_repair;
+    let check_paths = paths.clone();
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        hardening::check_storage(&check_paths, repair)
+    })
+    .await?;
+    for finding in report.findings.iter().filter(|f| f.repaired) {
+        info!("{}: {}", finding.path.display(), finding.problem);
+    }
+    let safe = report.is_safe();
+    let summary = report.summary();
+    *state.storage_report.lock().await = report;
+    if !safe {
+        error!("{}", summary);
+        let _ = app_handle.emit("storage-error", &summary);
+        anyhow::bail!(summary);
+    }
+    // the blog keeps its onion address in the new data directory
+    if let Some(default_tor_dir) = default_tor_dir {
+        let tor_dir = paths.tor_dir.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            tor::identity::move_keystore(&default_tor_dir, &tor_dir)
+        })
+        .await??;
+    }
+    state.lifecycle.started(Stage::Storage);
+
+    // Get the path to the ghost-sidecar binary
+    let mut sidecar_command = app_handle.shell().sidecar("ghost-sidecar")?;
+
+    // A free port, so a second instance or another local Ghost does not clash
+    let ghost_port = ghost::pick_port().context("Failed to find a free port for Ghost")?;
+    *state.ghost_port.lock().await = Some(ghost_port);
+    sidecar_command = sidecar_command.env("FREEDOM_KIT_GHOST_PORT", ghost_port.to_string());
+
+    // Ghost sits behind our proxy, off the network stack where Unix sockets are available
+    let unix_sockets = state.settings.lock().await.network.unix_sockets;
+    let upstream = ghost::upstream(&paths, unix_sockets)?;
+    let listen = match &upstream {
+        tor::proxy::Upstream::Tcp(port) => port.to_string(),
+        #[cfg(unix)]
+        tor::proxy::Upstream::Unix(socket) => socket.to_string_lossy().into_owned(),
+    };
+    info!(
+        "Ghost will listen on {}, the webview uses port {}",
+        listen, ghost_port
+    );
+    sidecar_command = sidecar_command.env("FREEDOM_KIT_GHOST_LISTEN", listen);
+
+    // Posts published from the admin get their remote media and embeds
+    // localized before Ghost publishes them
+    let localize_settings = state.settings.lock().await.localize.clone();
+    let publish = if localize_settings.on_publish {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<tor::proxy::Publish>();
+        let localize_app = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            while let Some(publish) = rx.recv().await {
+                let app_handle = localize_app.clone();
+                tauri::async_runtime::spawn(async move {
+                    let post_id = publish.post_id.clone();
+                    if let Err(e) = localize_publish(&app_handle, publish).await {
+                        error!("Failed to localize post {}: {:#}", post_id, e);
+                        let _ = app_handle.emit("localize-error", format!("{:#}", e));
+                    }
+                });
+            }
+        });
+        Some(tor::proxy::PublishHook {
+            requests: tx,
+            timeout: Duration::from_secs(localize_settings.publish_timeout_secs),
+        })
+    } else {
+        None
+    };
+
+    // Signatures of the published posts, served to the webview and the onion service
+    let signatures = Arc::new(signing::ContentSigner::new(
+        paths.tor_dir.clone(),
+        paths.ghost_database(),
+    ));
+    *state.signatures.lock().await = Some(signatures.clone());
+
+    // Nothing is logged unless the user turned the access log on
+    let access_log =
+        match tor::access_log::AccessLog::open(&paths, &state.settings.lock().await.access_log) {
+            Ok(access_log) => access_log.map(Arc::new),
+            Err(e) => {
+                error!("Failed to open the access log: {:#}", e);
+                None
+            }
+        };
+
+    // Tor bootstraps while Ghost starts, the hidden service waits for Ghost
+    let dropbox = start_dropbox(app_handle, &paths, &state.settings.lock().await.dropbox);
+    let tor_app = app_handle.clone();
+    let tor_paths = paths.clone();
+    let tor_upstream = upstream.clone();
+    let tor_signatures = signatures.clone();
+    let tor_access_log = access_log.clone();
+
+    tauri::async_runtime::spawn(
+        async move {
+            let app_handle = tor_app;
+            info!("starting TOR hidden service setup...");
+            match setup_tor_hidden_service(
+                &app_handle,
+                &tor_paths,
+                tor_upstream,
+                tor_signatures,
+                dropbox,
+                tor_access_log,
+            )
+            .await
+            {
+                Ok(onion_address) => {
+                    info!("Ghost is now available on TOR!");
+                    info!("onion address: {}", onion_address);
+
+                    // Emit event to frontend
+                    info!(
+                        "emitting tor-ready event to frontend with address: {}",
+                        onion_address
+                    );
+                    match app_handle.emit("tor-ready", onion_address.clone()) {
+                        Ok(_) => info!("tor-ready event emitted successfully"),
+                        Err(e) => error!("Failed to emit tor-ready event: {}", e),
+                    }
+
+                    // Paused or outside the publishing window, go dormant again right away
+                    if let Err(e) = apply_publishing(&app_handle).await {
+                        error!("Failed to pause publishing: {:#}", e);
+                    }
+                    refresh_tray(&app_handle).await;
+
+                    // If we're already on the Ghost page, inject/update the banner
+                    if let Some(window) = app_handle.get_webview_window("main") {
+                        if let Ok(url) = window.url() {
+                            if ghost::is_ghost_url(&url, ghost_port) {
+                                info!("updating banner with TOR address...");
+                                inject_onion_banner(&app_handle).await;
                             }
                         }
                     }
-                    Err(e) => {
-                        eprintln!("❌ Ghost failed to start: {}", e);
+                }
+                Err(e) => {
+                    error!("Failed to setup TOR hidden service: {:#}", e);
+                    let _ = app_handle.emit("tor-error", format!("{}", e));
+                }
+            }
+        }
+        .instrument(info_span!("tor")),
+    );
+
+    // Webviews that cannot use the Tor proxy get third-party requests blocked instead
+    let proxy_handle = tor::proxy::start_local_proxy(
+        ghost_port,
+        upstream.clone(),
+        !WEBVIEW_TOR,
+        publish,
+        Some(state.admin_session.clone()),
+        Some(signatures.clone()),
+        access_log.clone(),
+    )
+    .await?;
+    *state.proxy_handle.lock().await = Some(proxy_handle);
+    state.lifecycle.started(Stage::Proxy);
+
+    // Keep Ghost's content inside the encrypted volume when one is unlocked
+    if let Some(content_dir) = &paths.ghost_content {
+        sidecar_command = sidecar_command.env("FREEDOM_KIT_CONTENT_PATH", content_dir);
+    }
+
+    // Startup phases announced by the sidecar, consumed by the readiness check
+    let (phase_tx, phase_rx) = tokio::sync::watch::channel(ghost::GhostPhase::Starting);
+
+    // Spawn the sidecar process
+    let (mut rx, child) = sidecar_command
+        .spawn()
+        .context("Failed to spawn Ghost sidecar")?;
+
+    // Store the child process in app state for cleanup on exit, and on disk in case of a crash
+    if let Err(e) = lifecycle::record_sidecar(&AppPaths::pid_file(), "ghost", child.pid()) {
+        error!("Failed to record the Ghost process: {:#}", e);
+    }
+    *state.ghost_child.lock().await = Some(child);
+
+    // Create a thread to handle output from the sidecar
+    let phase_app = app_handle.clone();
+    let ghost_watcher = tauri::async_runtime::spawn(
+        async move {
+            let events = futures::stream::unfold(rx, |mut rx| async move {
+                rx.recv().await.map(|event| (event, rx))
+            })
+            .filter_map(|event| std::future::ready(sidecar_event(event)));
+            let exit = ghost::watch_sidecar(events, phase_tx, |phase| {
+                let _ = phase_app.emit("ghost-phase", phase);
+            })
+            .await;
+            if let Some(exit) = exit {
+                let state = phase_app.state::<AppState>();
+                state.sidecar_exits.lock().await.push(exit);
+                // gone on its own, there is nothing left to stop
+                state.ghost_child.lock().await.take();
+                state.lifecycle.stopped(Stage::Ghost);
+                lifecycle::forget_sidecar(&AppPaths::pid_file(), "ghost");
+            }
+        }
+        .instrument(info_span!("ghost")),
+    );
+    *state.ghost_watcher.lock().await = Some(ghost_watcher);
+    state.lifecycle.started(Stage::Ghost);
+
+    // Wait for Ghost to be ready and then navigate
+    let readiness = state.settings.lock().await.readiness.clone();
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(
+        async move {
+            match ghost::wait_until_ready(&ghost::base_url(ghost_port), phase_rx, &readiness).await
+            {
+                Ok(_) => {
+                    info!("Ghost is ready!");
+
+                    // Emit ghost-ready event to the frontend
+                    let _ = app_handle.emit("ghost-ready", ());
+
+                    // Now navigate to Ghost, the banner follows once the page has loaded
+                    if let Some(window) = app_handle.get_webview_window("main") {
+                        let admin_url = format!("{}/ghost", ghost::base_url(ghost_port));
+                        match window.navigate(tauri::Url::parse(&admin_url).unwrap()) {
+                            Ok(_) => info!("window navigated to Ghost"),
+                            Err(e) => error!("Failed to navigate window: {}", e),
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Ghost failed to start: {}", e);
 
-                        // Show error page in the window
-                        if let Some(window) = app_handle.get_webview_window("main") {
-                            let error_html = format!(
-                                r#"<!DOCTYPE html>
+                    // Show error page in the window
+                    if let Some(window) = app_handle.get_webview_window("main") {
+                        let error_html = format!(
+                            r#"<!DOCTYPE html>
 <html>
 <head>
     <meta charset="utf-8">
@@ -629,171 +2021,148 @@ pub fn run() {
         <p>The Ghost CMS server could not be started.</p>
         <div class="error-details">{}</div>
         <p><strong>Common causes:</strong></p>
-        <p>• Port 2368 is already in use<br>
+        <p>• Another program took the port Ghost was given<br>
            • Another Ghost instance is running<br>
            • Database locked by another process</p>
         <button onclick="window.location.reload()">Retry</button>
     </div>
 </body>
 </html>"#,
-                                e.to_string().replace("<", "&lt;").replace(">", "&gt;")
-                            );
-
-                            if let Ok(url) = tauri::Url::parse(&format!(
-                                "data:text/html;charset=utf-8,{}",
-                                urlencoding::encode(&error_html)
-                            )) {
-                                let _ = window.navigate(url);
-                            }
-                        }
-
-                        let _ = app_handle.emit("ghost-error", format!("{}", e));
-                    }
-                }
-            });
-
-            // Initialize TOR hidden service in the background
-            let state = app.state::<AppState>();
-            let hs_manager = state.hidden_service.clone();
-            let app_handle = app.handle().clone();
-
-            tauri::async_runtime::spawn(async move {
-                println!("🔄 Starting TOR hidden service setup...");
-                match setup_tor_hidden_service(hs_manager.clone()).await {
-                    Ok(onion_address) => {
-                        println!("🎉 Ghost is now available on TOR!");
-                        println!("🧅 Onion address: {}", onion_address);
-
-                        // Emit event to frontend
-                        println!(
-                            "📡 Emitting tor-ready event to frontend with address: {}",
-                            onion_address
+                            e.to_string().replace("<", "&lt;").replace(">", "&gt;")
                         );
-                        match app_handle.emit("tor-ready", onion_address.clone()) {
-                            Ok(_) => println!("✅ tor-ready event emitted successfully"),
-                            Err(e) => eprintln!("❌ Failed to emit tor-ready event: {}", e),
-                        }
 
-                        // If we're already on the Ghost page, inject/update the banner
-                        if let Some(window) = app_handle.get_webview_window("main") {
-                            if let Ok(url) = window.url() {
-                                let url_str = url.to_string();
-                                if url_str.contains("localhost:2368")
-                                    || url_str.contains("127.0.0.1:2368")
-                                {
-                                    println!("🔄 Updating banner with TOR address...");
-                                    inject_onion_banner(&app_handle).await;
-                                }
-                            }
+                        if let Ok(url) = tauri::Url::parse(&format!(
+                            "data:text/html;charset=utf-8,{}",
+                            urlencoding::encode(&error_html)
+                        )) {
+                            let _ = window.navigate(url);
                         }
                     }
-                    Err(e) => {
-                        eprintln!("❌ Failed to setup TOR hidden service: {}", e);
-                        eprintln!("Error details: {:?}", e);
-                        let _ = app_handle.emit("tor-error", format!("{}", e));
-                    }
-                }
-            });
 
-            Ok(())
-        })
-        .on_window_event(|window, event| {
-            if let tauri::WindowEvent::Destroyed = event {
-                println!("🛑 Window destroyed, cleaning up processes...");
-                let state = window.state::<AppState>();
-                tauri::async_runtime::block_on(async {
-                    if let Some(child) = state.ghost_child.lock().await.take() {
-                        println!("🔴 Killing Ghost sidecar process...");
-                        match child.kill() {
-                            Ok(_) => println!("✅ Ghost process killed successfully"),
-                            Err(e) => eprintln!("❌ Failed to kill Ghost process: {}", e),
-                        }
-                    }
-                    // NOTE: Railgun cleanup disabled - sidecar not spawned
-                    // if let Some(child) = state.railgun_child.lock().await.take() {
-                    //     println!("🔴 Killing Railgun sidecar process...");
-                    //     match child.kill() {
-                    //         Ok(_) => println!("✅ Railgun process killed successfully"),
-                    //         Err(e) => eprintln!("❌ Failed to kill Railgun process: {}", e),
-                    //     }
-                    // }
-                });
+                    let _ = app_handle.emit("ghost-error", format!("{}", e));
+                }
             }
-        })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
-
-/// Setup the TOR hidden service for Ghost
-async fn setup_tor_hidden_service(
-    hs_manager: Arc<Mutex<Option<HiddenServiceManager>>>,
-) -> anyhow::Result<String> {
-    // Get the app data directory for TOR storage
-    let app_data_dir = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("ghost-freedom-kit");
-
-    let tor_data_dir = app_data_dir.join("tor");
+        }
+        .instrument(info_span!("ghost")),
+    );
 
-    // TODO: fix
-    // Create directories with proper permissions (0700 - owner only)
-    // TOR requires strict permissions for security
-    std::fs::create_dir_all(&tor_data_dir)?;
+    Ok(())
+}
 
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let permissions = std::fs::Permissions::from_mode(0o700);
+/// Open the drop box when enabled, new submissions are announced to the UI
+fn start_dropbox(
+    app_handle: &tauri::AppHandle,
+    paths: &AppPaths,
+    settings: &settings::DropBoxSettings,
+) -> Option<Arc<dropbox::DropBoxServer>> {
+    if !settings.enabled {
+        return None;
+    }
+    let store = match dropbox::DropBox::open(paths) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open the drop box: {:#}", e);
+            return None;
+        }
+    };
 
-        // Set permissions on both parent and tor directory
-        std::fs::set_permissions(&app_data_dir, permissions.clone())?;
-        std::fs::set_permissions(&tor_data_dir, permissions)?;
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<dropbox::Submission>();
+    let notify_app = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(submission) = rx.recv().await {
+            info!("new drop box submission");
+            let _ = notify_app.emit("dropbox-submission", submission);
+        }
+    });
 
-        println!(
-            "📁 TOR data directory: {:?} (permissions: 0700)",
-            tor_data_dir
-        );
-    }
+    info!("drop box enabled on onion port {}", dropbox::DROPBOX_PORT);
+    Some(Arc::new(dropbox::DropBoxServer::new(store, settings, tx)))
+}
 
-    #[cfg(not(unix))]
-    {
-        println!("📁 TOR data directory: {:?}", tor_data_dir);
-    }
+/// what the app cares about in a shell plugin event
+fn sidecar_event(event: tauri_plugin_shell::process::CommandEvent) -> Option<ghost::SidecarEvent> {
+    use tauri_plugin_shell::process::CommandEvent;
+    Some(match event {
+        CommandEvent::Stdout(line) => {
+            ghost::SidecarEvent::Stdout(String::from_utf8_lossy(&line).into_owned())
+        }
+        CommandEvent::Stderr(line) => {
+            ghost::SidecarEvent::Stderr(String::from_utf8_lossy(&line).into_owned())
+        }
+        CommandEvent::Error(e) => ghost::SidecarEvent::Error(e),
+        CommandEvent::Terminated(status) => ghost::SidecarEvent::Terminated {
+            code: status.code,
+            signal: status.signal,
+        },
+        _ => return None,
+    })
+}
 
-    // Start the local HTTP proxy that forwards to Ghost
-    println!("🔄 Starting local reverse proxy...");
-    let (local_proxy_port, _proxy_handle) = tor::proxy::start_local_proxy(2368).await?;
-    println!(
-        "✅ Local proxy running on port {} (forwarding to Ghost on 2368)",
-        local_proxy_port
-    );
+/// Setup the TOR hidden service for Ghost
+async fn setup_tor_hidden_service(
+    app_handle: &tauri::AppHandle,
+    paths: &AppPaths,
+    upstream: tor::proxy::Upstream,
+    signatures: Arc<signing::ContentSigner>,
+    dropbox: Option<Arc<dropbox::DropBoxServer>>,
+    access_log: Option<Arc<tor::access_log::AccessLog>>,
+) -> anyhow::Result<String> {
+    let state = app_handle.state::<AppState>();
+    let tor_settings = state.settings.lock().await.tor.clone();
 
-    // Bootstrap the TOR client
-    println!("🔄 Bootstrapping TOR client...");
-    let tor_manager = tor::tor_client::bootstrap_tor_client(Some(tor_data_dir.clone())).await?;
-    println!("✅ TOR client ready!");
+    // The directory was created and checked by the storage hardening in start_services
+    let tor_data_dir = paths.tor_dir.clone();
+    info!("TOR data directory: {:?}", tor_data_dir);
 
-    // Create and start the hidden service
+    // Create and start the hidden service (serves port 80 on .onion straight from Ghost)
     let config = HiddenServiceConfig {
         tor_data_dir: tor_data_dir.clone(),
-        local_port: local_proxy_port, // Use the local proxy port
-        onion_port: 80,               // Standard HTTP port on the .onion address
+        upstream,       // Ghost's port or socket
+        onion_port: 80, // Standard HTTP port on the .onion address
+        access_log,
+    };
+    let routes = tor::hidden_service::Routes {
+        signatures: Some(signatures),
+        shares: Some(state.shares.clone()),
+        dropbox,
     };
 
-    println!("🔄 Creating hidden service...");
-    let mut hidden_service = HiddenServiceManager::new(config)?;
-
-    // Start the hidden service (forwards port 80 on .onion to local proxy)
-    hidden_service
-        .start(tor_manager, local_proxy_port, 80)
-        .await?;
+    let hidden_service = tor::hidden_service::publish(
+        async {
+            // Bootstrap the TOR client
+            info!("bootstrapping TOR client...");
+            let tor_manager =
+                tor::tor_client::bootstrap_tor_client(Some(tor_data_dir.clone()), &tor_settings)
+                    .await?;
+            info!("TOR client ready!");
+            anyhow::Ok(tor_manager)
+        },
+        // The webview's SOCKS proxy has been holding its connections until now
+        |tor_manager| {
+            state.tor_client.send_replace(Some(tor_manager));
+            state.lifecycle.started(Stage::Tor);
+            // nothing is published before Ghost runs behind the proxy
+            state.lifecycle.wait_for(Stage::Ghost)
+        },
+        config,
+        routes,
+    )
+    .await?;
 
     let onion_address = hidden_service
         .onion_url()
         .ok_or_else(|| anyhow::anyhow!("Failed to get onion address"))?;
 
+    // Quitting while it was being published, it would outlive the shutdown
+    if state.lifecycle.is_shutting_down() {
+        let mut hidden_service = hidden_service;
+        hidden_service.stop().await?;
+        anyhow::bail!("The app is shutting down");
+    }
+
     // Store the hidden service manager in app state
-    *hs_manager.lock().await = Some(hidden_service);
+    *state.hidden_service.lock().await = Some(hidden_service);
+    state.lifecycle.started(Stage::HiddenService);
 
     Ok(onion_address)
 }
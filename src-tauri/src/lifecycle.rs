@@ -0,0 +1,346 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+/// what the app runs, in start order, stopped the other way round
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// checked storage, and the unlocked vault when there is one
+    Storage,
+    /// the bootstrapped Tor client
+    Tor,
+    /// the webview proxy in front of Ghost
+    Proxy,
+    /// the Ghost sidecar
+    Ghost,
+    /// the blog's onion service
+    HiddenService,
+    /// the Railgun sidecar
+    Wallet,
+}
+
+/// how long shutting down may take
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// time Ghost gets to close its database after SIGTERM before it is killed
+    pub ghost_grace_secs: u64,
+    /// give up on a stage after this long and move on to the next one
+    pub stage_timeout_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            ghost_grace_secs: 10,
+            stage_timeout_secs: 10,
+        }
+    }
+}
+
+impl ShutdownSettings {
+    pub fn ghost_grace(&self) -> Duration {
+        Duration::from_secs(self.ghost_grace_secs)
+    }
+
+    /// time `stage` gets to stop, Ghost's grace period comes on top
+    ///
+    /// Storage has no limit: quitting while the vault is being sealed would
+    /// leave it half written.
+    pub fn timeout(&self, stage: Stage) -> Option<Duration> {
+        let timeout = Duration::from_secs(self.stage_timeout_secs);
+        match stage {
+            Stage::Storage => None,
+            Stage::Ghost => Some(timeout + self.ghost_grace()),
+            _ => Some(timeout),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Running,
+    Stopping,
+    Stopped,
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Phase,
+    running: BTreeSet<Stage>,
+}
+
+/// Which stages are up, and the one way of taking them down
+///
+/// Stages are started wherever their inputs are ready and report it with
+/// [`Lifecycle::started`]. [`Lifecycle::shutdown`] stops them last first,
+/// however it was triggered: the tray, the window, a signal or the `shutdown`
+/// command.
+pub struct Lifecycle {
+    state: watch::Sender<State>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            state: watch::channel(State {
+                phase: Phase::Running,
+                running: BTreeSet::new(),
+            })
+            .0,
+        }
+    }
+}
+
+impl Lifecycle {
+    /// `stage` is up, ignored once shutting down
+    pub fn started(&self, stage: Stage) {
+        self.state.send_if_modified(|state| {
+            if state.phase != Phase::Running {
+                return false;
+            }
+            debug!("{:?} started", stage);
+            state.running.insert(stage)
+        });
+    }
+
+    /// `stage` went down on its own or was stopped
+    pub fn stopped(&self, stage: Stage) {
+        self.state
+            .send_if_modified(|state| state.running.remove(&stage));
+    }
+
+    /// the stages that are up, in start order
+    pub fn running(&self) -> Vec<Stage> {
+        self.state.borrow().running.iter().copied().collect()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.borrow().phase != Phase::Running
+    }
+
+    /// Resolves once `stage` is up, fails when the app shuts down first
+    pub fn wait_for(&self, stage: Stage) -> impl Future<Output = Result<()>> + Send + 'static {
+        let mut state = self.state.subscribe();
+        async move {
+            let state = state
+                .wait_for(|state| state.phase != Phase::Running || state.running.contains(&stage))
+                .await?;
+            if state.phase != Phase::Running {
+                bail!("The app is shutting down");
+            }
+            Ok(())
+        }
+    }
+
+    /// Stop the running stages in reverse start order
+    ///
+    /// Each stage gets [`ShutdownSettings::timeout`], one that hangs or
+    /// fails is logged and the next one is stopped anyway. Storage is always
+    /// waited for, so the caller never exits while the vault is sealed. Callers that come
+    /// in while a shutdown is under way wait for it to finish.
+    pub async fn shutdown<F, Fut>(&self, settings: &ShutdownSettings, mut stop: F)
+    where
+        F: FnMut(Stage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let first = self.state.send_if_modified(|state| {
+            if state.phase != Phase::Running {
+                return false;
+            }
+            state.phase = Phase::Stopping;
+            true
+        });
+        if !first {
+            let _ = self
+                .state
+                .subscribe()
+                .wait_for(|state| state.phase == Phase::Stopped)
+                .await;
+            return;
+        }
+
+        let stages: Vec<Stage> = self.running().into_iter().rev().collect();
+        info!("shutting down {:?}", stages);
+        for stage in stages {
+            let stopping = stop(stage);
+            let result = match settings.timeout(stage) {
+                Some(timeout) => tokio::time::timeout(timeout, stopping)
+                    .await
+                    .map_err(|_| timeout),
+                None => Ok(stopping.await),
+            };
+            match result {
+                Ok(Ok(())) => debug!("{:?} stopped", stage),
+                Ok(Err(e)) => error!("Failed to stop {:?}: {:#}", stage, e),
+                Err(timeout) => warn!(
+                    "{:?} did not stop within {}s, moving on",
+                    stage,
+                    timeout.as_secs()
+                ),
+            }
+            self.stopped(stage);
+        }
+
+        self.state.send_modify(|state| state.phase = Phase::Stopped);
+        info!("shutdown complete");
+    }
+}
+
+/// Wait for the OS to ask the app to quit, returns the signal's name
+///
+/// SIGTERM, SIGINT and SIGHUP on Unix, Ctrl-C elsewhere.
+pub async fn termination_signal() -> Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        Ok(tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+            _ = hangup.recv() => "SIGHUP",
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("Ctrl-C")
+    }
+}
+
+/// Ask the process `pid` to exit and wait up to `grace` for `exited`
+///
+/// Returns whether it exited in time, the caller kills it otherwise. Without
+/// SIGTERM (Windows) there is nothing to ask, it returns `false` right away.
+pub async fn terminate(pid: u32, exited: impl Future<Output = ()>, grace: Duration) -> bool {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+            // already gone
+            return true;
+        }
+        tokio::time::timeout(grace, exited).await.is_ok()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (pid, exited, grace);
+        false
+    }
+}
+
+/// Remember that sidecar `name` runs as `pid`, for [`reap_orphans`]
+pub fn record_sidecar(pid_file: &Path, name: &str, pid: u32) -> Result<()> {
+    let mut entries = read_pid_file(pid_file);
+    entries.retain(|(entry, _)| entry != name);
+    entries.push((name.to_string(), pid));
+    write_pid_file(pid_file, &entries)
+}
+
+/// `name` was stopped, nothing to clean up for it next time
+pub fn forget_sidecar(pid_file: &Path, name: &str) {
+    let mut entries = read_pid_file(pid_file);
+    entries.retain(|(entry, _)| entry != name);
+    if let Err(e) = write_pid_file(pid_file, &entries) {
+        error!("Failed to update {:?}: {:#}", pid_file, e);
+    }
+}
+
+/// Stop sidecars a crashed or killed previous run left behind
+///
+/// Only processes that still look like the recorded sidecar are touched, a
+/// PID reused by something else is left alone. They get `grace` to exit
+/// after SIGTERM before they are killed.
+pub fn reap_orphans(pid_file: &Path, grace: Duration) {
+    for (name, pid) in read_pid_file(pid_file) {
+        if pid == std::process::id() || !is_sidecar(pid, &name) {
+            continue;
+        }
+        warn!(
+            "stopping {} sidecar (pid {}) left over from the last run",
+            name, pid
+        );
+        if let Err(e) = stop_orphan(pid, grace) {
+            error!("Failed to stop orphaned {} sidecar: {:#}", name, e);
+        }
+    }
+    let _ = fs::remove_file(pid_file);
+}
+
+fn read_pid_file(pid_file: &Path) -> Vec<(String, u32)> {
+    let Ok(raw) = fs::read_to_string(pid_file) else {
+        return Vec::new();
+    };
+    raw.lines()
+        .filter_map(|line| {
+            let (name, pid) = line.split_once(' ')?;
+            Some((name.to_string(), pid.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+fn write_pid_file(pid_file: &Path, entries: &[(String, u32)]) -> Result<()> {
+    if entries.is_empty() {
+        let _ = fs::remove_file(pid_file);
+        return Ok(());
+    }
+    if let Some(parent) = pid_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents: String = entries
+        .iter()
+        .map(|(name, pid)| format!("{} {}\n", name, pid))
+        .collect();
+    fs::write(pid_file, contents).with_context(|| format!("Failed to write {:?}", pid_file))
+}
+
+/// whether `pid` runs the `{name}-sidecar` binary
+fn is_sidecar(pid: u32, name: &str) -> bool {
+    let binary = format!("{}-sidecar", name);
+    #[cfg(unix)]
+    let output = std::process::Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "command="])
+        .output();
+    #[cfg(not(unix))]
+    let output = std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output();
+    output.is_ok_and(|output| {
+        output.status.success() && String::from_utf8_lossy(&output.stdout).contains(&binary)
+    })
+}
+
+#[cfg(unix)]
+fn stop_orphan(pid: u32, grace: Duration) -> Result<()> {
+    let pid = pid as libc::pid_t;
+    let alive = || unsafe { libc::kill(pid, 0) } == 0;
+
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let deadline = std::time::Instant::now() + grace;
+    while alive() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    if alive() && unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+        bail!("{}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn stop_orphan(pid: u32, _grace: Duration) -> Result<()> {
+    let status = std::process::Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/F"])
+        .status()?;
+    if !status.success() {
+        bail!("taskkill exited with {}", status);
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use crate::tor::{Purpose, TorClientManager};
+use anyhow::{bail, Context, Result};
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::body::Bytes;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+/// largest media file we download
+pub const MAX_MEDIA_BYTES: usize = 50 * 1024 * 1024;
+
+/// redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// a single fetch, including connecting over Tor and every redirect
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Tor Browser's user agent, so fetches do not stand out from readers
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+/// media types we store, anything else (notably SVG, which can carry
+/// scripts) is refused
+const ALLOWED_TYPES: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/avif", "avif"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/ogg", "ogv"),
+    ("audio/mpeg", "mp3"),
+    ("audio/mp4", "m4a"),
+    ("audio/ogg", "ogg"),
+    ("audio/wav", "wav"),
+];
+
+/// a downloaded media file
+pub struct Media {
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    pub body: Bytes,
+}
+
+/// Download `url` over Tor
+///
+/// The connection is made by Arti, TLS is done here against the Mozilla
+/// roots, so neither the DNS lookup nor the request leaves the Tor network.
+pub async fn fetch(tor: &TorClientManager, url: &str) -> Result<Media> {
+    tokio::time::timeout(FETCH_TIMEOUT, fetch_following(tor, url))
+        .await
+        .with_context(|| format!("Timed out fetching {}", url))?
+}
+
+async fn fetch_following(tor: &TorClientManager, url: &str) -> Result<Media> {
+    let mut url = tauri::Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = get(tor, &url).await?;
+        let status = response.status();
+
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(hyper::header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .context("Redirect without a location")?;
+            url = url.join(location)?;
+            debug!("following redirect to {}", url);
+            continue;
+        }
+        if status != StatusCode::OK {
+            bail!("{} answered {}", url, status);
+        }
+
+        let content_type = response
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let Some(&(content_type, extension)) =
+            ALLOWED_TYPES.iter().find(|(t, _)| *t == content_type)
+        else {
+            bail!("{} is {:?}, not an image, video or audio file", url, content_type);
+        };
+
+        let body = Limited::new(response.into_body(), MAX_MEDIA_BYTES)
+            .collect()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to download {}: {}", url, e))?
+            .to_bytes();
+
+        return Ok(Media {
+            content_type,
+            extension,
+            body,
+        });
+    }
+
+    bail!("Too many redirects")
+}
+
+/// one GET request on a fresh Tor stream
+async fn get(tor: &TorClientManager, url: &tauri::Url) -> Result<Response<hyper::body::Incoming>> {
+    let host = url.host_str().context("URL has no host")?.to_string();
+    let port = url.port_or_known_default().context("URL has no port")?;
+
+    let stream = tor
+        .connect(Purpose::Localize, &host, port)
+        .await
+        .with_context(|| format!("Failed to reach {} over Tor", host))?;
+
+    match url.scheme() {
+        "http" => request(stream, url).await,
+        "https" => {
+            let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+                .with_context(|| format!("Invalid server name {}", host))?;
+            let tls = TlsConnector::from(tls_config())
+                .connect(name, stream)
+                .await
+                .with_context(|| format!("TLS handshake with {} failed", host))?;
+            request(tls, url).await
+        }
+        other => bail!("Unsupported scheme {}", other),
+    }
+}
+
+async fn request<S>(io: S, url: &tauri::Url) -> Result<Response<hyper::body::Incoming>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("media connection closed: {}", e);
+        }
+    });
+
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    let request = Request::get(path)
+        .header(hyper::header::HOST, authority)
+        .header(hyper::header::USER_AGENT, USER_AGENT)
+        .header(hyper::header::ACCEPT, "image/*,video/*,audio/*;q=0.8")
+        .header(hyper::header::ACCEPT_ENCODING, "identity")
+        .body(Empty::<Bytes>::new())?;
+
+    Ok(sender.send_request(request).await?)
+}
+
+/// client TLS config, built once
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let config = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
@@ -0,0 +1,348 @@
+pub mod fetch;
+pub mod rewrite;
+
+use crate::ghost::AdminApi;
+use crate::paths::AppPaths;
+use crate::tor::TorClientManager;
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use rewrite::Resolver;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// downloads running at the same time for one post
+const PARALLEL_FETCHES: usize = 4;
+
+/// content subdirectory (below `images/` or `media/`) for localized files
+const LOCALIZED_DIR: &str = "localized";
+
+/// result of localizing a post
+#[derive(Serialize, Debug, Default)]
+pub struct LocalizeReport {
+    pub post_id: String,
+    pub title: String,
+    /// remote URLs now served from the blog
+    pub media_localized: usize,
+    /// iframes, widget scripts and plugins replaced by a static preview
+    pub embeds_replaced: usize,
+    /// remote media that could not be fetched and was replaced by a link
+    pub media_dropped: usize,
+    /// why each dropped URL could not be fetched
+    pub failures: Vec<String>,
+    /// whether the post was written back
+    pub changed: bool,
+}
+
+/// the fields of a post we read and rewrite, as the admin API has them
+struct Post {
+    title: String,
+    lexical: Option<String>,
+    html: Option<String>,
+    feature_image: Option<String>,
+    og_image: Option<String>,
+    twitter_image: Option<String>,
+}
+
+impl Post {
+    /// read the fields out of an admin API post object
+    fn from_api(post: &Map<String, Value>) -> Result<Self> {
+        if post.get("mobiledoc").is_some_and(|m| !m.is_null()) {
+            bail!("The post uses the legacy mobiledoc editor, open and save it in Ghost first");
+        }
+        let field = |name: &str| post.get(name).and_then(Value::as_str).map(str::to_string);
+        Ok(Post {
+            title: field("title").unwrap_or_default(),
+            lexical: field("lexical"),
+            html: field("html"),
+            feature_image: field("feature_image"),
+            og_image: field("og_image"),
+            twitter_image: field("twitter_image"),
+        })
+    }
+
+    /// write the fields back into `post`, only where it already has them
+    fn apply(&self, post: &mut Map<String, Value>) {
+        for (name, value) in [
+            ("lexical", &self.lexical),
+            ("html", &self.html),
+            ("feature_image", &self.feature_image),
+            ("og_image", &self.og_image),
+            ("twitter_image", &self.twitter_image),
+        ] {
+            if let Some(field) = post.get_mut(name) {
+                *field = value.clone().map_or(Value::Null, Value::String);
+            }
+        }
+    }
+}
+
+/// records every remote URL without changing anything
+#[derive(Default)]
+struct Collector(BTreeSet<String>);
+
+impl Resolver for Collector {
+    fn resolve(&mut self, url: &str) -> Option<String> {
+        self.0.insert(url.to_string());
+        Some(url.to_string())
+    }
+}
+
+/// answers from the downloads, `None` for the ones that failed
+struct Downloaded(HashMap<String, Option<String>>);
+
+impl Resolver for Downloaded {
+    fn resolve(&mut self, url: &str) -> Option<String> {
+        self.0.get(url).cloned().flatten()
+    }
+}
+
+/// Make a post load nothing from third parties
+///
+/// Every remote image, video and audio file referenced by the post is
+/// downloaded over Tor into Ghost's content directory and the post is
+/// rewritten to point at the local copy. Embeds (iframes, widget scripts)
+/// become static links. The post is saved through the admin API with the
+/// `updated_at` it was read with, so Ghost refuses the save if it was edited
+/// in the meantime.
+pub async fn localize_post(
+    paths: &AppPaths,
+    tor: &TorClientManager,
+    admin: &AdminApi,
+    post_id: &str,
+) -> Result<LocalizeReport> {
+    let path = format!("posts/{}/", post_id);
+    let response = admin
+        .get(&format!("{}?formats=lexical,html,mobiledoc", path))
+        .await
+        .with_context(|| format!("Failed to load post {}", post_id))?;
+    let original = response
+        .pointer("/posts/0")
+        .and_then(Value::as_object)
+        .with_context(|| format!("No post with id {}", post_id))?;
+    let post = Post::from_api(original)?;
+
+    let (report, rewritten) = localize(paths, tor, post_id, &post).await?;
+    if let Some(rewritten) = rewritten {
+        let mut update = Map::new();
+        for name in [
+            "lexical",
+            "html",
+            "feature_image",
+            "og_image",
+            "twitter_image",
+        ] {
+            if let Some(value) = original.get(name) {
+                update.insert(name.to_string(), value.clone());
+            }
+        }
+        rewritten.apply(&mut update);
+
+        // Ghost renders the html from the lexical document, posts without
+        // one are saved from their html instead
+        let source = if rewritten.lexical.is_some() {
+            update.remove("html");
+            ""
+        } else {
+            "?source=html"
+        };
+        update.insert(
+            "updated_at".to_string(),
+            original.get("updated_at").cloned().unwrap_or_default(),
+        );
+        admin
+            .put(
+                &format!("{}{}", path, source),
+                &serde_json::json!({ "posts": [update] }),
+            )
+            .await
+            .with_context(|| format!("Failed to save post {}", post_id))?;
+    }
+    Ok(report)
+}
+
+/// Localize a post in an admin API publish request, before Ghost gets it
+///
+/// `body` is the `PUT /ghost/api/admin/{kind}/{id}/` request, the post is
+/// rewritten in place so it goes out localized with the `updated_at` the
+/// editor sent. Returns the body to forward, `None` if nothing changed.
+pub async fn localize_publish(
+    paths: &AppPaths,
+    tor: &TorClientManager,
+    post_id: &str,
+    kind: &str,
+    body: &[u8],
+) -> Result<(LocalizeReport, Option<Vec<u8>>)> {
+    let mut body: Value = serde_json::from_slice(body).context("Invalid publish request")?;
+    let original = body
+        .pointer_mut(&format!("/{}/0", kind))
+        .and_then(Value::as_object_mut)
+        .context("Publish request carries no post")?;
+    let post = Post::from_api(original)?;
+
+    let (report, rewritten) = localize(paths, tor, post_id, &post).await?;
+    let Some(rewritten) = rewritten else {
+        return Ok((report, None));
+    };
+    rewritten.apply(original);
+    Ok((report, Some(serde_json::to_vec(&body)?)))
+}
+
+/// download the post's remote media and rewrite it, `None` if nothing changed
+async fn localize(
+    paths: &AppPaths,
+    tor: &TorClientManager,
+    post_id: &str,
+    post: &Post,
+) -> Result<(LocalizeReport, Option<Post>)> {
+    let content_dir = paths.ghost_content_dir();
+    let mut report = LocalizeReport {
+        post_id: post_id.to_string(),
+        title: post.title.clone(),
+        ..Default::default()
+    };
+
+    // first pass: which remote URLs does the post use
+    let mut collector = Collector::default();
+    rewrite_post(post, &mut collector)?;
+    if collector.0.is_empty() {
+        info!("post {} has no remote media", post_id);
+        return Ok((report, None));
+    }
+
+    let mut downloads = HashMap::new();
+    let mut results = futures::stream::iter(collector.0)
+        .map(|url| {
+            let content_dir = &content_dir;
+            async move {
+                let result = download(tor, &url, content_dir).await;
+                (url, result)
+            }
+        })
+        .buffer_unordered(PARALLEL_FETCHES);
+    while let Some((url, result)) = results.next().await {
+        match result {
+            Ok(local) => {
+                downloads.insert(url, Some(local));
+            }
+            Err(e) => {
+                warn!("could not localize {}: {:#}", url, e);
+                report.failures.push(format!("{}: {:#}", url, e));
+                downloads.insert(url, None);
+            }
+        }
+    }
+    drop(results);
+
+    // second pass: rewrite with what we got
+    let (rewritten, changes) = rewrite_post(post, &mut Downloaded(downloads))?;
+    report.media_localized = changes.media;
+    report.embeds_replaced = changes.embeds;
+    report.media_dropped = changes.dropped;
+    if !changes.any() {
+        return Ok((report, None));
+    }
+
+    report.changed = true;
+    info!(
+        "localized post {}: {} media, {} embeds, {} dropped",
+        post_id, changes.media, changes.embeds, changes.dropped
+    );
+    Ok((report, Some(rewritten)))
+}
+
+/// rewrite every URL-bearing column of `post`
+fn rewrite_post(post: &Post, resolver: &mut dyn Resolver) -> Result<(Post, rewrite::Changes)> {
+    let (lexical, lexical_changes) = match &post.lexical {
+        Some(raw) => {
+            let mut document: serde_json::Value =
+                serde_json::from_str(raw).context("Post has an invalid lexical document")?;
+            let changes = rewrite::rewrite_lexical(&mut document, resolver)?;
+            (Some(serde_json::to_string(&document)?), Some(changes))
+        }
+        None => (None, None),
+    };
+
+    let (html, html_changes) = match &post.html {
+        Some(raw) => {
+            let (html, changes) = rewrite::rewrite_html(raw, resolver)?;
+            (Some(html), changes)
+        }
+        None => (None, rewrite::Changes::default()),
+    };
+
+    // the html column is rendered from the lexical cards, count them once
+    let mut changes = match lexical_changes {
+        Some(changes) if changes.any() || !html_changes.any() => changes,
+        _ => html_changes,
+    };
+
+    let mut image = |value: &Option<String>| match value {
+        Some(url) if rewrite::is_remote(url) => {
+            let local = resolver.resolve(&rewrite::absolute(url));
+            match &local {
+                Some(_) => changes.media += 1,
+                None => changes.dropped += 1,
+            }
+            local
+        }
+        other => other.clone(),
+    };
+    let feature_image = image(&post.feature_image);
+    let og_image = image(&post.og_image);
+    let twitter_image = image(&post.twitter_image);
+
+    Ok((
+        Post {
+            title: post.title.clone(),
+            lexical,
+            html,
+            feature_image,
+            og_image,
+            twitter_image,
+        },
+        changes,
+    ))
+}
+
+/// download one URL into the content dir, returns the URL Ghost stores for it
+async fn download(tor: &TorClientManager, url: &str, content_dir: &Path) -> Result<String> {
+    let media = fetch::fetch(tor, url).await?;
+
+    // images are served from content/images, video and audio from content/media
+    let section = if media.content_type.starts_with("image/") {
+        "images"
+    } else {
+        "media"
+    };
+    let name = format!(
+        "{}.{}",
+        &hex::encode(Sha256::digest(&media.body))[..32],
+        media.extension
+    );
+    let dir = content_dir.join(section).join(LOCALIZED_DIR);
+    let path = dir.join(&name);
+
+    if !path.exists() {
+        let body = media.body;
+        let target = path.clone();
+        tokio::task::spawn_blocking(move || write_file(&dir, &target, &body)).await??;
+    }
+
+    Ok(format!(
+        "__GHOST_URL__/content/{}/{}/{}",
+        section, LOCALIZED_DIR, name
+    ))
+}
+
+fn write_file(dir: &Path, path: &Path, body: &[u8]) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let tmp = path.with_extension("part");
+    fs::write(&tmp, body).with_context(|| format!("Failed to write {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to save {:?}", path))?;
+    Ok(())
+}
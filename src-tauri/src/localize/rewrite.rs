@@ -0,0 +1,440 @@
+use anyhow::Result;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::net::IpAddr;
+
+/// keys of Lexical card nodes whose string value is a media URL
+const MEDIA_KEYS: &[&str] = &[
+    "src",
+    "thumbnailSrc",
+    "customThumbnailSrc",
+    "backgroundImageSrc",
+    "productImageSrc",
+    "thumbnail",
+    "thumbnail_url",
+    "icon",
+];
+
+/// card types that are nothing without their `src`
+const MEDIA_CARDS: &[&str] = &["image", "video", "audio", "file"];
+
+/// what to do with a remote media URL
+pub trait Resolver {
+    /// the local URL replacing `url`, `None` when it could not be fetched
+    fn resolve(&mut self, url: &str) -> Option<String>;
+}
+
+/// counts of what a rewrite changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes {
+    pub media: usize,
+    /// iframes, scripts and plugins replaced by a static preview
+    pub embeds: usize,
+    /// remote media that had to be dropped
+    pub dropped: usize,
+}
+
+impl Changes {
+    pub fn any(&self) -> bool {
+        self.media + self.embeds + self.dropped > 0
+    }
+
+    fn add(&mut self, other: Changes) {
+        self.media += other.media;
+        self.embeds += other.embeds;
+        self.dropped += other.dropped;
+    }
+}
+
+/// whether a URL makes the reader's browser contact a third party
+pub fn is_remote(url: &str) -> bool {
+    // relative, data:, blob: and __GHOST_URL__ URLs stay on the blog
+    let Ok(url) = tauri::Url::parse(&absolute(url)) else {
+        return false;
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => !ip.is_loopback(),
+        Err(_) => !(host.is_empty() || host == "localhost" || host.ends_with(".localhost")),
+    }
+}
+
+/// absolute form of a remote URL, protocol-relative ones become https
+pub fn absolute(url: &str) -> String {
+    let url = url.trim();
+    match url.strip_prefix("//") {
+        Some(rest) => format!("https://{}", rest),
+        None => url.to_string(),
+    }
+}
+
+/// Rewrite a post's Lexical document
+///
+/// Remote media in cards is resolved, cards whose media could not be
+/// fetched become an HTML card with a plain link, and HTML inside embed
+/// and HTML cards goes through [`rewrite_html`].
+pub fn rewrite_lexical(lexical: &mut Value, resolver: &mut dyn Resolver) -> Result<Changes> {
+    let mut changes = Changes::default();
+    rewrite_node(lexical, resolver, &mut changes)?;
+    Ok(changes)
+}
+
+fn rewrite_node(node: &mut Value, resolver: &mut dyn Resolver, changes: &mut Changes) -> Result<()> {
+    match node {
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                rewrite_node(item, resolver, changes)?;
+            }
+        }
+        Value::Object(map) => {
+            let card = map.get("type").and_then(|t| t.as_str()).map(str::to_string);
+
+            // a media card without its media is replaced by a link to it
+            if let Some(card) = card.as_deref().filter(|c| MEDIA_CARDS.contains(c)) {
+                let src = map.get("src").and_then(|s| s.as_str()).unwrap_or_default();
+                if is_remote(src) && resolver.resolve(&absolute(src)).is_none() {
+                    let label = map
+                        .get("alt")
+                        .or_else(|| map.get("title"))
+                        .or_else(|| map.get("fileName"))
+                        .and_then(|s| s.as_str())
+                        .filter(|s| !s.is_empty())
+                        .unwrap_or(card);
+                    let preview = preview_link(&absolute(src), label);
+                    *node = serde_json::json!({ "type": "html", "version": 1, "html": preview });
+                    changes.dropped += 1;
+                    return Ok(());
+                }
+            }
+
+            // gallery images that could not be fetched are left out
+            if card.as_deref() == Some("gallery") {
+                if let Some(Value::Array(images)) = map.get_mut("images") {
+                    let before = images.len();
+                    images.retain(|image| {
+                        let src = image.get("src").and_then(|s| s.as_str()).unwrap_or_default();
+                        !is_remote(src) || resolver.resolve(&absolute(src)).is_some()
+                    });
+                    changes.dropped += before - images.len();
+                }
+            }
+
+            let html_card = matches!(card.as_deref(), Some("html") | Some("embed"));
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(s) if MEDIA_KEYS.contains(&key.as_str()) && is_remote(s) => {
+                        match resolver.resolve(&absolute(s)) {
+                            Some(local) => {
+                                *value = Value::String(local);
+                                changes.media += 1;
+                            }
+                            None => {
+                                *value = Value::Null;
+                                changes.dropped += 1;
+                            }
+                        }
+                    }
+                    Value::String(s) if key == "html" && html_card => {
+                        let (html, html_changes) = rewrite_html(s, resolver)?;
+                        *value = Value::String(html);
+                        changes.add(html_changes);
+                    }
+                    Value::Array(_) | Value::Object(_) => rewrite_node(value, resolver, changes)?,
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rewrite rendered post HTML
+///
+/// Remote images, video and audio are resolved (or replaced by a link),
+/// iframes, plugins and remote scripts and stylesheets are replaced by a
+/// static preview. Links stay: following one is the reader's choice.
+pub fn rewrite_html(html: &str, resolver: &mut dyn Resolver) -> Result<(String, Changes)> {
+    let resolver = RefCell::new(resolver);
+    let changes = RefCell::new(Changes::default());
+
+    let resolve = |url: &str| resolver.borrow_mut().resolve(&absolute(url));
+
+    let media = |el: &mut Element, attr: &str| {
+        let Some(src) = el.get_attribute(attr).filter(|s| is_remote(s)) else {
+            return;
+        };
+        match resolve(&src) {
+            Some(local) => {
+                let _ = el.set_attribute(attr, &local);
+                changes.borrow_mut().media += 1;
+            }
+            None if attr == "src" => {
+                let label = el
+                    .get_attribute("alt")
+                    .or_else(|| el.get_attribute("title"))
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or_else(|| match el.tag_name().as_str() {
+                        "video" => "Video".to_string(),
+                        "audio" => "Audio".to_string(),
+                        _ => "Image".to_string(),
+                    });
+                el.replace(&preview_link(&absolute(&src), &label), ContentType::Html);
+                changes.borrow_mut().dropped += 1;
+            }
+            None => {
+                el.remove_attribute(attr);
+                changes.borrow_mut().dropped += 1;
+            }
+        }
+    };
+
+    let srcset = |el: &mut Element| {
+        let Some(srcset) = el.get_attribute("srcset") else {
+            return;
+        };
+        let mut kept = Vec::new();
+        for candidate in srcset.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let (url, descriptor) = candidate.split_once(' ').unwrap_or((candidate, ""));
+            if !is_remote(url) {
+                kept.push(candidate.to_string());
+                continue;
+            }
+            match resolve(url) {
+                Some(local) => {
+                    kept.push(format!("{} {}", local, descriptor).trim_end().to_string());
+                    changes.borrow_mut().media += 1;
+                }
+                None => changes.borrow_mut().dropped += 1,
+            }
+        }
+        if kept.is_empty() {
+            el.remove_attribute("srcset");
+        } else {
+            let _ = el.set_attribute("srcset", &kept.join(", "));
+        }
+    };
+
+    let embed = |el: &mut Element, attr: &str| {
+        let target = el.get_attribute(attr).unwrap_or_default();
+        if !is_remote(&target) {
+            return;
+        }
+        let label = el
+            .get_attribute("title")
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "Embedded content".to_string());
+        el.replace(&preview_link(&absolute(&target), &label), ContentType::Html);
+        changes.borrow_mut().embeds += 1;
+    };
+
+    let output = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("img, source, video, audio, input[type=image]", |el| {
+                    srcset(el);
+                    if el.tag_name() == "video" {
+                        media(el, "poster");
+                    }
+                    media(el, "src");
+                    Ok(())
+                }),
+                element!("iframe, frame, embed", |el| {
+                    embed(el, "src");
+                    Ok(())
+                }),
+                element!("object", |el| {
+                    embed(el, "data");
+                    Ok(())
+                }),
+                // widget loaders (twitter, instagram...), the blockquote they
+                // would have turned into an embed stays readable without them
+                element!("script[src]", |el| {
+                    if is_remote(&el.get_attribute("src").unwrap_or_default()) {
+                        el.remove();
+                        changes.borrow_mut().embeds += 1;
+                    }
+                    Ok(())
+                }),
+                element!("link[href]", |el| {
+                    if is_remote(&el.get_attribute("href").unwrap_or_default()) {
+                        el.remove();
+                        changes.borrow_mut().embeds += 1;
+                    }
+                    Ok(())
+                }),
+                element!("[style]", |el| {
+                    let style = el.get_attribute("style").unwrap_or_default();
+                    if has_remote_css_url(&style) {
+                        el.remove_attribute("style");
+                        changes.borrow_mut().dropped += 1;
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    let changes = changes.into_inner();
+    Ok((output, changes))
+}
+
+/// whether inline CSS loads something from another host
+fn has_remote_css_url(style: &str) -> bool {
+    style.split("url(").skip(1).any(|rest| {
+        let url = rest.trim_start().trim_start_matches(['"', '\'']);
+        is_remote(url.split([')', '"', '\'']).next().unwrap_or_default())
+    })
+}
+
+fn host_of(url: &str) -> String {
+    tauri::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| "external content".to_string())
+}
+
+/// static stand-in for remote content, nothing loads until it is clicked
+fn preview_link(url: &str, label: &str) -> String {
+    format!(
+        r#"<p class="freedom-kit-preview"><a href="{}" rel="noopener noreferrer nofollow">{} (external: {})</a></p>"#,
+        escape(url),
+        escape(label),
+        escape(&host_of(url))
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// fetches everything but URLs mentioning "missing", records what it saw
+    #[derive(Default)]
+    struct Fake(Vec<String>);
+
+    impl Resolver for Fake {
+        fn resolve(&mut self, url: &str) -> Option<String> {
+            self.0.push(url.to_string());
+            let name = url.rsplit('/').next().unwrap_or_default();
+            (!url.contains("missing"))
+                .then(|| format!("__GHOST_URL__/content/images/localized/{}", name))
+        }
+    }
+
+    #[test]
+    fn remote_urls() {
+        assert!(is_remote("https://example.com/a.jpg"));
+        assert!(is_remote("//cdn.example.com/a.jpg"));
+        assert!(is_remote("http://93.184.216.34/a.jpg"));
+        assert!(is_remote("http://[2001:db8::1]/a.jpg"));
+
+        assert!(!is_remote("/content/images/a.jpg"));
+        assert!(!is_remote("__GHOST_URL__/content/images/a.jpg"));
+        assert!(!is_remote("data:image/png;base64,AAAA"));
+        assert!(!is_remote("http://localhost:2368/a.jpg"));
+        assert!(!is_remote("http://blog.localhost/a.jpg"));
+        assert!(!is_remote("http://127.0.0.1/a.jpg"));
+        assert!(!is_remote("http://[::1]/a.jpg"));
+        assert!(!is_remote("mailto:someone@example.com"));
+    }
+
+    #[test]
+    fn html_media_and_embeds() {
+        let html = concat!(
+            r#"<p><img src="https://example.com/a.jpg" srcset="//example.com/a.jpg 600w, /content/images/b.jpg 1000w"></p>"#,
+            r#"<img src="https://example.com/missing.jpg" alt="Chart">"#,
+            r#"<iframe src="https://www.youtube.com/embed/x" title="Talk"></iframe>"#,
+            r#"<script src="https://platform.twitter.com/widgets.js"></script>"#,
+            r#"<div style="background: url('https://example.com/bg.jpg')">x</div>"#,
+            r#"<a href="https://example.com/">link</a>"#,
+        );
+        let mut resolver = Fake::default();
+        let (output, changes) = rewrite_html(html, &mut resolver).unwrap();
+
+        assert_eq!(
+            changes,
+            Changes {
+                media: 2,
+                embeds: 2,
+                dropped: 2
+            }
+        );
+        assert!(output.contains(r#"src="__GHOST_URL__/content/images/localized/a.jpg""#));
+        assert!(output.contains(
+            r#"srcset="__GHOST_URL__/content/images/localized/a.jpg 600w, /content/images/b.jpg 1000w""#
+        ));
+        assert!(output.contains("Chart (external: example.com)"));
+        assert!(output.contains("Talk (external: www.youtube.com)"));
+        assert!(!output.contains("<iframe"));
+        assert!(!output.contains("<script"));
+        assert!(!output.contains("style="));
+        assert!(output.contains(r#"<a href="https://example.com/">link</a>"#));
+    }
+
+    #[test]
+    fn html_without_remote_content_is_unchanged() {
+        let html =
+            r#"<p><img src="/content/images/a.jpg"><a href="https://example.com/">x</a></p>"#;
+        let mut resolver = Fake::default();
+        let (output, changes) = rewrite_html(html, &mut resolver).unwrap();
+        assert_eq!(output, html);
+        assert!(!changes.any());
+        assert!(resolver.0.is_empty());
+    }
+
+    #[test]
+    fn lexical_cards() {
+        let mut lexical = serde_json::json!({
+            "root": { "children": [
+                { "type": "image", "src": "https://example.com/a.jpg", "alt": "" },
+                { "type": "video", "src": "https://example.com/missing.mp4", "title": "Clip" },
+                { "type": "gallery", "images": [
+                    { "src": "https://example.com/b.jpg" },
+                    { "src": "https://example.com/missing.jpg" },
+                ] },
+                { "type": "embed", "html": "<iframe src=\"https://player.example.com/1\"></iframe>" },
+                { "type": "paragraph", "children": [
+                    { "type": "link", "url": "https://example.com/" },
+                ] },
+            ] }
+        });
+        let mut resolver = Fake::default();
+        let changes = rewrite_lexical(&mut lexical, &mut resolver).unwrap();
+
+        assert_eq!(
+            changes,
+            Changes {
+                media: 2,
+                embeds: 1,
+                dropped: 2
+            }
+        );
+        let cards = &lexical["root"]["children"];
+        assert_eq!(
+            cards[0]["src"],
+            "__GHOST_URL__/content/images/localized/a.jpg"
+        );
+        assert_eq!(cards[1]["type"], "html");
+        assert!(cards[1]["html"]
+            .as_str()
+            .unwrap()
+            .contains("Clip (external: example.com)"));
+        assert_eq!(cards[2]["images"].as_array().unwrap().len(), 1);
+        assert!(!cards[3]["html"].as_str().unwrap().contains("<iframe"));
+        assert_eq!(cards[4]["children"][0]["url"], "https://example.com/");
+    }
+}
@@ -0,0 +1,641 @@
+use anyhow::{Context as _, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// entries returned by [`read_entries`] when no limit is given
+pub const DEFAULT_LIMIT: usize = 500;
+/// entries held in memory until the log has a directory, the oldest go first
+const BUFFER_LIMIT: usize = 1000;
+/// stands in for anything that could identify the user or unlock something
+const REDACTED: &str = "[redacted]";
+/// field names whose values are never written out
+const SECRET_NAMES: &[&str] = &[
+    "password",
+    "passphrase",
+    "secret",
+    "token",
+    "mnemonic",
+    "seed",
+    "cookie",
+    "session",
+    "api_key",
+    "apikey",
+];
+
+/// how much is written to the log file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<&tracing::Level> for LogLevel {
+    fn from(level: &tracing::Level) -> Self {
+        match *level {
+            tracing::Level::ERROR => Self::Error,
+            tracing::Level::WARN => Self::Warn,
+            tracing::Level::INFO => Self::Info,
+            tracing::Level::DEBUG => Self::Debug,
+            tracing::Level::TRACE => Self::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+/// the part of the app an entry comes from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    App,
+    Tor,
+    /// the blog's and the throwaway onion services
+    Hs,
+    /// the webview and onion proxies in front of Ghost
+    Proxy,
+    Ghost,
+    Railgun,
+}
+
+impl Subsystem {
+    /// the subsystem a span of that name stands for
+    fn from_span(name: &str) -> Option<Self> {
+        match name {
+            "tor" => Some(Self::Tor),
+            "hs" => Some(Self::Hs),
+            "proxy" => Some(Self::Proxy),
+            "ghost" => Some(Self::Ghost),
+            "railgun" => Some(Self::Railgun),
+            _ => None,
+        }
+    }
+
+    /// guess from the module or crate that logged, for events outside any span
+    fn from_target(target: &str) -> Self {
+        let module = target.split_once("::").map_or("", |(_, module)| module);
+        if module.starts_with("tor::hidden_service")
+            || module.starts_with("tor::ephemeral")
+            || target.starts_with("tor_hsservice")
+        {
+            Self::Hs
+        } else if module.starts_with("tor::proxy")
+            || module.starts_with("tor::socks")
+            || module.starts_with("tor::access_log")
+        {
+            Self::Proxy
+        } else if module.starts_with("tor::")
+            || target.starts_with("arti")
+            || target.starts_with("tor_")
+        {
+            Self::Tor
+        } else if module.starts_with("ghost") {
+            Self::Ghost
+        } else {
+            Self::App
+        }
+    }
+}
+
+/// app logging, applied on the next start
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogSettings {
+    /// least severe level written to the log file
+    pub level: LogLevel,
+    /// size a log file grows to before it is rotated, in KiB
+    pub max_file_kb: u64,
+    /// rotated files kept next to the current one
+    pub keep_files: u32,
+    /// rotated files older than this are deleted, in days
+    pub keep_days: u32,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            max_file_kb: 1024,
+            keep_files: 5,
+            keep_days: 7,
+        }
+    }
+}
+
+/// one line of the log file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    /// RFC 3339, UTC
+    pub time: String,
+    pub level: LogLevel,
+    pub subsystem: Subsystem,
+    pub target: String,
+    pub message: String,
+}
+
+/// Route every `tracing` event to stderr and to the app log
+///
+/// Stderr follows `RUST_LOG` (info by default) and is meant for development.
+/// The app log gets `settings.level` and up with secrets redacted, it is what
+/// [`read_entries`] and the diagnostics view show. It stays in memory until
+/// [`AppLog::open`] gives it a directory, so nothing is written before the
+/// encrypted storage is unlocked.
+pub fn init(settings: &LogSettings) -> AppLog {
+    let log = AppLog::default();
+    let console = tracing_subscriber::fmt::layer()
+        .with_filter(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()));
+    let file = log
+        .clone()
+        .with_filter(tracing_subscriber::filter::LevelFilter::from_level(
+            settings.level.into(),
+        ));
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+    log
+}
+
+/// where the app log goes
+enum Sink {
+    /// the newest [`BUFFER_LIMIT`] entries, until there is a directory
+    Memory(VecDeque<LogEntry>),
+    File(LogFile),
+}
+
+/// The app log, see [`init`]
+#[derive(Clone)]
+pub struct AppLog(Arc<Mutex<Sink>>);
+
+impl Default for AppLog {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Sink::Memory(VecDeque::new()))))
+    }
+}
+
+impl AppLog {
+    /// Write to rotating files in `dir` from now on, the entries so far first
+    pub fn open(&self, dir: &Path, settings: &LogSettings) -> Result<()> {
+        let file = LogFile::open(dir, settings)?;
+        let mut sink = self.0.lock().unwrap();
+        if let Sink::Memory(entries) = &mut *sink {
+            for entry in entries.drain(..) {
+                file.write(&entry)
+                    .with_context(|| format!("Failed to write {:?}", dir))?;
+            }
+        }
+        *sink = Sink::File(file);
+        Ok(())
+    }
+
+    /// The newest entries, like [`read_entries`] wherever the log is kept
+    pub fn entries(
+        &self,
+        level: Option<LogLevel>,
+        subsystem: Option<Subsystem>,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>> {
+        let dir = match &*self.0.lock().unwrap() {
+            Sink::Memory(entries) => {
+                let mut newest: Vec<LogEntry> = entries
+                    .iter()
+                    .rev()
+                    .filter(|entry| wanted(entry, level, subsystem))
+                    .take(limit)
+                    .cloned()
+                    .collect();
+                newest.reverse();
+                return Ok(newest);
+            }
+            Sink::File(file) => file.dir.clone(),
+        };
+        read_entries(&dir, level, subsystem, limit)
+    }
+
+    fn write(&self, entry: LogEntry) -> io::Result<()> {
+        match &mut *self.0.lock().unwrap() {
+            Sink::Memory(entries) => {
+                if entries.len() == BUFFER_LIMIT {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+                Ok(())
+            }
+            Sink::File(file) => file.write(&entry),
+        }
+    }
+}
+
+/// Rotating log file, one JSON [`LogEntry`] per line
+struct LogFile {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep_files: u32,
+    keep_days: u32,
+    /// the current file and how much has been written to it
+    current: Mutex<(File, u64)>,
+}
+
+impl LogFile {
+    fn open(dir: &Path, settings: &LogSettings) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let log = Self {
+            dir: dir.to_path_buf(),
+            max_bytes: settings.max_file_kb.max(1).saturating_mul(1024),
+            keep_files: settings.keep_files,
+            keep_days: settings.keep_days,
+            current: Mutex::new(open_append(&log_file(dir, 0))?),
+        };
+        log.prune();
+        Ok(log)
+    }
+
+    fn write(&self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut current = self.current.lock().unwrap();
+        if current.1 > 0 && current.1 + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            *current = open_append(&log_file(&self.dir, 0))?;
+        }
+        current.0.write_all(&line)?;
+        current.1 += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let _ = fs::remove_file(log_file(&self.dir, self.keep_files));
+        for index in (0..self.keep_files).rev() {
+            let from = log_file(&self.dir, index);
+            if from.exists() {
+                fs::rename(&from, log_file(&self.dir, index + 1))?;
+            }
+        }
+        if self.keep_files == 0 {
+            let _ = fs::remove_file(log_file(&self.dir, 0));
+        }
+        self.prune();
+        Ok(())
+    }
+
+    /// delete rotated files past the retention period
+    fn prune(&self) {
+        let max_age = Duration::from_secs(u64::from(self.keep_days) * 24 * 60 * 60);
+        for index in 1..=self.keep_files {
+            let path = log_file(&self.dir, index);
+            let expired = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > max_age);
+            if expired {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for AppLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let subsystem = ctx
+            .event_scope(event)
+            .and_then(|mut scope| scope.find_map(|span| Subsystem::from_span(span.name())))
+            .unwrap_or_else(|| Subsystem::from_target(metadata.target()));
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let entry = LogEntry {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            level: metadata.level().into(),
+            subsystem,
+            target: metadata.target().to_string(),
+            message: redact(&fields.0),
+        };
+        // logging from here would come straight back to this layer
+        if let Err(e) = self.write(entry) {
+            eprintln!("Failed to write the log file: {}", e);
+        }
+    }
+}
+
+/// the message of an event followed by its other fields as `name=value`
+#[derive(Default)]
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let message = format!("{:?}", value);
+            self.0.insert_str(0, &message);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.insert_str(0, value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+}
+
+/// Entries of the log files, oldest first, at most `limit` of the newest
+///
+/// Only entries at `level` or more severe and from `subsystem`, when given,
+/// are kept.
+pub fn read_entries(
+    dir: &Path,
+    level: Option<LogLevel>,
+    subsystem: Option<Subsystem>,
+    limit: usize,
+) -> Result<Vec<LogEntry>> {
+    let mut files: Vec<(u32, PathBuf)> = match fs::read_dir(dir) {
+        Ok(read) => read
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let index = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix("app.")?
+                    .strip_suffix(".log")?
+                    .parse()
+                    .ok()?;
+                Some((index, path))
+            })
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+    };
+    files.sort_by_key(|(index, _)| *index);
+
+    // newest file first, put back in order at the end
+    let mut entries = Vec::new();
+    for (_, path) in files {
+        let file = File::open(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        let mut lines: Vec<LogEntry> = BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .filter(|entry: &LogEntry| wanted(entry, level, subsystem))
+            .collect();
+        lines.reverse();
+        entries.extend(lines);
+        if entries.len() >= limit {
+            break;
+        }
+    }
+    entries.truncate(limit);
+    entries.reverse();
+    Ok(entries)
+}
+
+/// `entry` is at `level` or more severe and from `subsystem`, when given
+fn wanted(entry: &LogEntry, level: Option<LogLevel>, subsystem: Option<Subsystem>) -> bool {
+    level.is_none_or(|level| entry.level <= level)
+        && subsystem.is_none_or(|subsystem| entry.subsystem == subsystem)
+}
+
+/// Replace secrets and identifying details in a log message
+///
+/// The home directory becomes `~`, onion addresses become `[onion]`, and
+/// values of secret-looking fields, long hex strings and tokens become
+/// `[redacted]`.
+pub fn redact(message: &str) -> String {
+    static HOME: OnceLock<Option<String>> = OnceLock::new();
+    let home = HOME.get_or_init(|| {
+        dirs::home_dir()
+            .map(|home| home.to_string_lossy().into_owned())
+            .filter(|home| home.len() > 1)
+    });
+    let message = match home {
+        Some(home) => message.replace(home.as_str(), "~"),
+        None => message.to_string(),
+    };
+
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message.as_str();
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+
+        if after.starts_with(".onion") && word.len() >= 16 {
+            out.push_str("[onion]");
+            rest = &after[".onion".len()..];
+        } else if is_secret_name(word) && starts_value(after) {
+            // keep the separator, drop the value
+            let separator = after.len() - after.trim_start_matches([' ', '=', ':', '"']).len();
+            out.push_str(word);
+            out.push_str(&after[..separator]);
+            out.push_str(REDACTED);
+            let value = &after[separator..];
+            // a quoted value runs to the closing quote, spaces and all
+            let value_end = if after[..separator].ends_with('"') {
+                value.find('"')
+            } else {
+                value.find(|c: char| c.is_whitespace() || matches!(c, ',' | '&' | ')' | '}'))
+            }
+            .unwrap_or(value.len());
+            rest = &value[value_end..];
+        } else {
+            if looks_like_secret(word) {
+                out.push_str(REDACTED);
+            } else {
+                out.push_str(word);
+            }
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// a field or parameter name that holds a password, token or similar
+pub fn is_secret_name(word: &str) -> bool {
+    let word = word.to_ascii_lowercase();
+    SECRET_NAMES.iter().any(|name| word.ends_with(name))
+}
+
+/// `=value`, `: value` or `":"value` follows
+fn starts_value(after: &str) -> bool {
+    let after = after.trim_start_matches('"');
+    after.starts_with('=') || after.starts_with(": ") || after.starts_with(':')
+}
+
+/// long hex strings (keys, hashes) and long runs mixing letters and digits
+fn looks_like_secret(word: &str) -> bool {
+    if word.len() >= 32 && word.chars().all(|c| c.is_ascii_hexdigit()) {
+        return true;
+    }
+    word.len() >= 24
+        && word.chars().any(|c| c.is_ascii_digit())
+        && word.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+fn log_file(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("app.{}.log", index))
+}
+
+/// open `path` for appending, private to the user
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: LogLevel, subsystem: Subsystem, message: &str) -> LogEntry {
+        LogEntry {
+            time: "2026-01-01T00:00:00Z".to_string(),
+            level,
+            subsystem,
+            target: "freedom_kit_lib".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(entries: &[LogEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.message.as_str()).collect()
+    }
+
+    #[test]
+    fn redacts_secrets_and_addresses() {
+        let onion = "duskgytldkxiuqc6.onion";
+        assert_eq!(
+            redact(&format!("published at http://{}/about", onion)),
+            "published at http://[onion]/about"
+        );
+        assert_eq!(
+            redact("login with password=hunter2 and retry"),
+            "login with password=[redacted] and retry"
+        );
+        assert_eq!(
+            redact(r#"{"admin_api_key":"secret with spaces","ok":true}"#),
+            r#"{"admin_api_key":"[redacted]","ok":true}"#
+        );
+        assert_eq!(
+            redact(&format!("key {}", "ab".repeat(32))),
+            "key [redacted]"
+        );
+        assert_eq!(
+            redact("session token: a1b2c3d4e5f6g7h8i9j0k1l2m3"),
+            "session token: [redacted]"
+        );
+        assert_eq!(redact("ghost 5.0 is ready"), "ghost 5.0 is ready");
+
+        if let Some(home) = dirs::home_dir().filter(|home| home.as_os_str().len() > 1) {
+            let path = home.join(".ghost-freedom-kit");
+            assert_eq!(
+                redact(&format!("extracting to {}", path.display())),
+                "extracting to ~/.ghost-freedom-kit"
+            );
+        }
+    }
+
+    fn write_lines(path: &Path, entries: &[LogEntry]) {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry).unwrap());
+            lines.push('\n');
+        }
+        fs::write(path, lines).unwrap();
+    }
+
+    #[test]
+    fn reads_rotated_files_oldest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        write_lines(
+            &log_file(dir.path(), 1),
+            &[
+                entry(LogLevel::Info, Subsystem::App, "first"),
+                entry(LogLevel::Error, Subsystem::Tor, "second"),
+            ],
+        );
+        write_lines(
+            &log_file(dir.path(), 0),
+            &[
+                entry(LogLevel::Debug, Subsystem::Ghost, "third"),
+                entry(LogLevel::Warn, Subsystem::Tor, "fourth"),
+            ],
+        );
+        fs::write(dir.path().join("access.0.log"), "not ours\n").unwrap();
+
+        let all = read_entries(dir.path(), None, None, 10).unwrap();
+        assert_eq!(messages(&all), ["first", "second", "third", "fourth"]);
+        let newest = read_entries(dir.path(), None, None, 3).unwrap();
+        assert_eq!(messages(&newest), ["second", "third", "fourth"]);
+        let warnings = read_entries(dir.path(), Some(LogLevel::Warn), None, 10).unwrap();
+        assert_eq!(messages(&warnings), ["second", "fourth"]);
+        let tor = read_entries(dir.path(), None, Some(Subsystem::Tor), 10).unwrap();
+        assert_eq!(messages(&tor), ["second", "fourth"]);
+
+        let missing = read_entries(&dir.path().join("missing"), None, None, 10).unwrap();
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn memory_log_moves_to_its_directory() {
+        let log = AppLog::default();
+        log.write(entry(LogLevel::Info, Subsystem::App, "locked"))
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        log.open(&dir.path().join("logs"), &LogSettings::default())
+            .unwrap();
+        log.write(entry(LogLevel::Info, Subsystem::App, "unlocked"))
+            .unwrap();
+        let entries = log.entries(None, None, 10).unwrap();
+        assert_eq!(messages(&entries), ["locked", "unlocked"]);
+        let on_disk = read_entries(&dir.path().join("logs"), None, None, 10).unwrap();
+        assert_eq!(messages(&on_disk), ["locked", "unlocked"]);
+    }
+}
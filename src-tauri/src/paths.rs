@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+/// locations of everything freedom-kit keeps on disk
+#[derive(Clone, Debug)]
+pub struct AppPaths {
+    /// app data directory (Tor state lives below it)
+    pub data_dir: PathBuf,
+    /// Tor state and keystore, including the onion identity
+    pub tor_dir: PathBuf,
+    /// extracted Ghost installation used by the sidecar
+    pub ghost_dir: PathBuf,
+    /// Railgun wallet directory
+    pub wallet_dir: PathBuf,
+    /// Ghost content kept apart from the Ghost install (encrypted storage)
+    pub ghost_content: Option<PathBuf>,
+}
+
+impl AppPaths {
+    /// resolve the default locations used by the app and its sidecars
+    pub fn resolve() -> Self {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        let data_dir = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("ghost-freedom-kit");
+
+        Self {
+            tor_dir: data_dir.join("tor"),
+            data_dir,
+            // these match the paths hardcoded in the sidecars
+            ghost_dir: home.join(".ghost-freedom-kit").join("original"),
+            wallet_dir: home.join(".railgun-freedom-kit"),
+            ghost_content: None,
+        }
+    }
+
+    /// locations inside an unlocked encrypted volume mounted at `root`
+    ///
+    /// The Ghost install itself stays outside, it holds no user data once
+    /// its content directory is redirected into the volume.
+    pub fn in_vault(root: &Path) -> Self {
+        let data_dir = root.join("data");
+        Self {
+            tor_dir: data_dir.join("tor"),
+            data_dir,
+            ghost_dir: Self::resolve().ghost_dir,
+            wallet_dir: root.join("wallet"),
+            ghost_content: Some(root.join("ghost-content")),
+        }
+    }
+
+    /// encrypted container holding the data, when the user set one up
+    pub fn vault_file() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("freedom-kit.vault")
+    }
+
+    /// sidecars of the running app, found there by the next launch if it crashes
+    ///
+    /// Kept with the default data, it has to be read before storage is unlocked.
+    pub fn pid_file() -> PathBuf {
+        Self::resolve().run_dir().join("sidecars.pid")
+    }
+
+    /// runtime files such as sockets, private to the user
+    pub fn run_dir(&self) -> PathBuf {
+        self.data_dir.join("run")
+    }
+
+    /// Unix socket Ghost listens on instead of a TCP port
+    pub fn ghost_socket(&self) -> PathBuf {
+        self.run_dir().join("ghost.sock")
+    }
+
+    /// persisted user settings
+    pub fn settings_file(&self) -> PathBuf {
+        self.data_dir.join("settings.json")
+    }
+
+    /// Ghost's `config.production.json`
+    pub fn ghost_config(&self) -> PathBuf {
+        self.ghost_dir.join("config.production.json")
+    }
+
+    /// Ghost content directory (images, themes and the SQLite database)
+    ///
+    /// Honours `paths.contentPath` from the Ghost config when present.
+    pub fn ghost_content_dir(&self) -> PathBuf {
+        if let Some(content) = &self.ghost_content {
+            return content.clone();
+        }
+
+        std::fs::read_to_string(self.ghost_config())
+            .ok()
+            .and_then(|raw| serde_json::from_str::<serde_json::Value>(&raw).ok())
+            .and_then(|config| {
+                config
+                    .pointer("/paths/contentPath")
+                    .and_then(|v| v.as_str())
+                    .map(PathBuf::from)
+            })
+            .filter(|path| path.is_absolute())
+            .unwrap_or_else(|| self.ghost_dir.join("content"))
+    }
+
+    /// Ghost's SQLite database inside the content directory
+    pub fn ghost_database(&self) -> PathBuf {
+        self.ghost_content_dir().join("data").join("ghost-local.db")
+    }
+
+    /// encrypted drop box submissions
+    pub fn dropbox_dir(&self) -> PathBuf {
+        self.data_dir.join("dropbox")
+    }
+
+    /// key the drop box submissions are encrypted with
+    pub fn dropbox_key(&self) -> PathBuf {
+        self.data_dir.join("dropbox.key")
+    }
+
+    /// our own logs, such as the encrypted proxy access log
+    pub fn logs_dir(&self) -> PathBuf {
+        self.data_dir.join("logs")
+    }
+
+    /// key the access log is encrypted with
+    pub fn access_log_key(&self) -> PathBuf {
+        self.data_dir.join("access-log.key")
+    }
+
+    /// encrypted Railgun wallet file
+    pub fn wallet_file(&self) -> PathBuf {
+        self.wallet_dir.join("wallet.enc")
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Result};
+use chrono::{Local, Timelike};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// how often the schedule is checked
+pub const CHECK_INTERVAL_SECS: u64 = 30;
+
+/// when the blog is reachable on its onion address
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PublishingSettings {
+    /// publishing paused by hand, wins over the schedule
+    pub paused: bool,
+    /// only publish inside this daily window, `None` publishes all the time
+    pub schedule: Option<PublishSchedule>,
+}
+
+impl PublishingSettings {
+    /// whether the onion service should be online right now
+    pub fn online_now(&self) -> bool {
+        if self.paused {
+            return false;
+        }
+        let Some(schedule) = &self.schedule else {
+            return true;
+        };
+        let now = Local::now();
+        match schedule.is_open_at(now.hour() * 60 + now.minute()) {
+            Ok(open) => open,
+            Err(e) => {
+                warn!("ignoring invalid publishing schedule: {:#}", e);
+                true
+            }
+        }
+    }
+}
+
+/// daily publishing window in local time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PublishSchedule {
+    /// "HH:MM" the onion service comes online
+    pub start: String,
+    /// "HH:MM" it goes offline, earlier than `start` for a window past midnight
+    pub end: String,
+}
+
+impl PublishSchedule {
+    pub fn validate(&self) -> Result<()> {
+        if parse_time(&self.start)? == parse_time(&self.end)? {
+            bail!("The publishing window starts and ends at the same time");
+        }
+        Ok(())
+    }
+
+    /// whether `minute` (minutes after midnight) falls inside the window
+    pub fn is_open_at(&self, minute: u32) -> Result<bool> {
+        let start = parse_time(&self.start)?;
+        let end = parse_time(&self.end)?;
+        Ok(if start <= end {
+            (start..end).contains(&minute)
+        } else {
+            minute >= start || minute < end
+        })
+    }
+}
+
+/// minutes after midnight for "HH:MM"
+fn parse_time(time: &str) -> Result<u32> {
+    let parsed = time.trim().split_once(':').and_then(|(hours, minutes)| {
+        let hours: u32 = hours.parse().ok()?;
+        let minutes: u32 = minutes.parse().ok()?;
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    });
+    parsed.with_context(|| format!("Invalid time {:?}, expected HH:MM", time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(start: &str, end: &str) -> PublishSchedule {
+        PublishSchedule {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+
+    #[test]
+    fn times_parse_to_minutes() {
+        assert_eq!(parse_time("00:00").unwrap(), 0);
+        assert_eq!(parse_time(" 9:05 ").unwrap(), 545);
+        assert_eq!(parse_time("23:59").unwrap(), 1439);
+        for bad in ["24:00", "12:60", "12", "noon", "-1:30", ""] {
+            let err = parse_time(bad).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid time {:?}, expected HH:MM", bad)
+            );
+        }
+    }
+
+    #[test]
+    fn daytime_window() {
+        let window = schedule("09:00", "17:30");
+        assert!(!window.is_open_at(8 * 60 + 59).unwrap());
+        assert!(window.is_open_at(9 * 60).unwrap());
+        assert!(window.is_open_at(17 * 60 + 29).unwrap());
+        assert!(!window.is_open_at(17 * 60 + 30).unwrap());
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let window = schedule("22:00", "02:00");
+        assert!(window.is_open_at(22 * 60).unwrap());
+        assert!(window.is_open_at(23 * 60 + 59).unwrap());
+        assert!(window.is_open_at(0).unwrap());
+        assert!(window.is_open_at(119).unwrap());
+        assert!(!window.is_open_at(120).unwrap());
+        assert!(!window.is_open_at(12 * 60).unwrap());
+        assert!(!window.is_open_at(21 * 60 + 59).unwrap());
+    }
+
+    #[test]
+    fn invalid_windows_are_refused() {
+        assert!(schedule("22:00", "02:00").validate().is_ok());
+        let err = schedule("08:00", "8:00").validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The publishing window starts and ends at the same time"
+        );
+        assert!(schedule("08:00", "25:00").validate().is_err());
+        assert!(schedule("08:00", "25:00").is_open_at(0).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::ghost::ReadinessSettings;
+use crate::lifecycle::ShutdownSettings;
+use crate::logging::LogSettings;
+use crate::publishing::PublishingSettings;
+use crate::tor::access_log::AccessLogSettings;
+use crate::wipe::WipeScope;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// user settings, persisted as JSON in the app data dir
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub panic: PanicSettings,
+    pub storage: StorageSettings,
+    pub readiness: ReadinessSettings,
+    pub network: NetworkSettings,
+    pub localize: LocalizeSettings,
+    pub dropbox: DropBoxSettings,
+    pub tor: TorSettings,
+    pub publishing: PublishingSettings,
+    pub access_log: AccessLogSettings,
+    pub logging: LogSettings,
+    pub shutdown: ShutdownSettings,
+    pub tray: TraySettings,
+}
+
+/// panic button behaviour
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PanicSettings {
+    /// global shortcut that triggers the wipe, `None` disables it
+    pub shortcut: Option<String>,
+    /// what the shortcut destroys
+    pub scope: WipeScope,
+}
+
+impl Default for PanicSettings {
+    fn default() -> Self {
+        Self {
+            shortcut: Some("CommandOrControl+Shift+Alt+X".to_string()),
+            scope: WipeScope::IdentityOnly,
+        }
+    }
+}
+
+/// tray / menu bar icon
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TraySettings {
+    /// show the tray icon and keep publishing when the window is closed,
+    /// without it closing the window quits (read at launch)
+    pub enabled: bool,
+}
+
+impl Default for TraySettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// startup checks of the private directories
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StorageSettings {
+    /// tighten loose permissions instead of refusing to start
+    pub auto_repair: bool,
+    /// seconds between seals of the unlocked container, 0 seals only on quit
+    ///
+    /// A crash loses what changed since the last seal.
+    pub seal_interval_secs: u64,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            auto_repair: true,
+            seal_interval_secs: 300,
+        }
+    }
+}
+
+/// how the webview and the onion service reach Ghost
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// run Ghost on a Unix socket instead of a loopback port (Unix only)
+    pub unix_sockets: bool,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self { unix_sockets: true }
+    }
+}
+
+/// keeping published posts free of third-party requests
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LocalizeSettings {
+    /// localize remote media and embeds whenever a post is published
+    pub on_publish: bool,
+    /// how long publishing waits for the media, then the post goes out as it is
+    pub publish_timeout_secs: u64,
+}
+
+impl Default for LocalizeSettings {
+    fn default() -> Self {
+        Self {
+            on_publish: true,
+            publish_timeout_secs: 300,
+        }
+    }
+}
+
+/// anonymous drop box on the onion service
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DropBoxSettings {
+    /// accept submissions from sources
+    pub enabled: bool,
+    /// largest submission accepted, in MiB
+    pub max_submission_mb: u64,
+    /// submissions accepted per hour, across all sources
+    pub max_per_hour: u32,
+}
+
+impl Default for DropBoxSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_submission_mb: 100,
+            max_per_hour: 20,
+        }
+    }
+}
+
+/// how the Tor client connects, changed at runtime with `reconfigure_tor`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TorSettings {
+    /// let the webview and fetches reach .onion addresses
+    pub onion_client: bool,
+    /// connect through the bridges below instead of public guards
+    pub use_bridges: bool,
+    /// bridge lines as handed out by bridges.torproject.org
+    pub bridges: Vec<String>,
+    /// pluggable transport binary (lyrebird, obfs4proxy) for obfs4 bridges
+    pub transport_path: Option<PathBuf>,
+    /// Tor state and keystore somewhere else, `None` keeps it in the app data dir
+    pub data_dir: Option<PathBuf>,
+}
+
+impl Default for TorSettings {
+    fn default() -> Self {
+        Self {
+            onion_client: true,
+            use_bridges: false,
+            bridges: Vec::new(),
+            transport_path: None,
+            data_dir: None,
+        }
+    }
+}
+
+impl Settings {
+    /// load settings, falling back to defaults if missing or unreadable
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                warn!("invalid settings file {:?}, using defaults: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// write settings atomically with owner-only permissions
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+
+        fs::rename(&tmp, path).with_context(|| format!("Failed to save {:?}", path))?;
+        Ok(())
+    }
+}
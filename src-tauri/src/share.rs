@@ -0,0 +1,431 @@
+use anyhow::{bail, Context, Result};
+use futures::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tracing::{debug, error, info};
+
+/// onion port the shared files are served on, next to the blog on 80
+pub const SHARE_PORT: u16 = 8080;
+
+/// size of the chunks streamed from disk, the only per-download buffer
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type ShareBody = BoxBody<Bytes, std::io::Error>;
+
+/// a file offered for download
+#[derive(Clone, Debug)]
+struct Share {
+    path: PathBuf,
+    name: String,
+    created_at: u64,
+    expires_at: Option<u64>,
+    max_downloads: Option<u32>,
+    downloads: u32,
+    /// downloads that got the file's last byte
+    finished: u32,
+}
+
+impl Share {
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+}
+
+/// what the UI is told about a share
+#[derive(Serialize, Clone, Debug)]
+pub struct ShareInfo {
+    /// random token, also the first path segment of the URL
+    pub id: String,
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub downloads: u32,
+    /// path below the onion service, `/<token>/<file name>`
+    pub url_path: String,
+}
+
+/// Files shared over the onion service
+///
+/// Shares only live in memory, they all end when the app quits.
+#[derive(Default)]
+pub struct ShareManager {
+    shares: Mutex<HashMap<String, Share>>,
+}
+
+impl ShareManager {
+    /// offer `path` under a new random URL
+    pub fn add(
+        &self,
+        path: &Path,
+        expires_in: Option<u64>,
+        max_downloads: Option<u32>,
+    ) -> Result<ShareInfo> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        if !path.is_file() {
+            bail!("{:?} is not a file", path);
+        }
+        if max_downloads == Some(0) {
+            bail!("A share needs to allow at least one download");
+        }
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "download".to_string());
+
+        let mut token = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut token);
+        let token = hex::encode(token);
+
+        let now = now();
+        let share = Share {
+            path,
+            name,
+            created_at: now,
+            expires_at: expires_in.map(|secs| now.saturating_add(secs)),
+            max_downloads,
+            downloads: 0,
+            finished: 0,
+        };
+        let info = share_info(&token, &share);
+        self.shares.lock().unwrap().insert(token, share);
+        info!("new share until revoked or expired");
+        Ok(info)
+    }
+
+    /// the shares that are still open
+    pub fn list(&self) -> Vec<ShareInfo> {
+        let mut shares = self.shares.lock().unwrap();
+        let now = now();
+        shares.retain(|_, share| !share.expired(now));
+        let mut list: Vec<_> = shares
+            .iter()
+            .map(|(token, share)| share_info(token, share))
+            .collect();
+        list.sort_by_key(|share| share.created_at);
+        list
+    }
+
+    /// stop sharing, returns whether the share existed
+    pub fn revoke(&self, id: &str) -> bool {
+        self.shares.lock().unwrap().remove(id).is_some()
+    }
+
+    /// stop sharing everything
+    pub fn clear(&self) {
+        self.shares.lock().unwrap().clear();
+    }
+
+    fn lookup(&self, token: &str) -> Option<Share> {
+        let mut shares = self.shares.lock().unwrap();
+        let share = shares.get(token)?;
+        if share.expired(now()) {
+            shares.remove(token);
+            return None;
+        }
+        Some(share.clone())
+    }
+
+    /// count a download that starts at the first byte, `false` once the
+    /// limit is reached
+    ///
+    /// Ranges further in are not counted, they resume a counted download.
+    fn start_download(&self, token: &str) -> bool {
+        let mut shares = self.shares.lock().unwrap();
+        let Some(share) = shares.get_mut(token) else {
+            return false;
+        };
+        if share
+            .max_downloads
+            .is_some_and(|max| share.downloads >= max)
+        {
+            return false;
+        }
+        share.downloads += 1;
+        true
+    }
+
+    /// note that a response delivered the file's last byte
+    ///
+    /// The share closes once as many downloads finished as it allows, so
+    /// the last one can still be resumed until then.
+    fn finish_download(&self, token: &str) {
+        let mut shares = self.shares.lock().unwrap();
+        let Some(share) = shares.get_mut(token) else {
+            return;
+        };
+        share.finished = (share.finished + 1).min(share.downloads);
+        if share.max_downloads.is_some_and(|max| share.finished >= max) {
+            info!("a share reached its download limit");
+            shares.remove(token);
+        }
+    }
+}
+
+fn share_info(token: &str, share: &Share) -> ShareInfo {
+    ShareInfo {
+        id: token.to_string(),
+        name: share.name.clone(),
+        path: share.path.display().to_string(),
+        size: std::fs::metadata(&share.path).map(|m| m.len()).unwrap_or(0),
+        created_at: share.created_at,
+        expires_at: share.expires_at,
+        max_downloads: share.max_downloads,
+        downloads: share.downloads,
+        url_path: format!("/{}/{}", token, urlencoding::encode(&share.name)),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Serve one onion stream with the shared files
+///
+/// Nothing here reaches Ghost, unknown paths get a plain 404.
+pub async fn serve_connection<S>(io: S, shares: Arc<ShareManager>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    let service = service_fn(move |req| handle_request(req, shares.clone()));
+
+    if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
+        debug!("share connection ended: {}", e);
+    }
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    shares: Arc<ShareManager>,
+) -> Result<Response<ShareBody>, Infallible> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(plain_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method not allowed",
+        ));
+    }
+
+    let token = req
+        .uri()
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let Some(share) = shares.lookup(&token) else {
+        return Ok(plain_response(StatusCode::NOT_FOUND, "Not found"));
+    };
+
+    match send_file(&req, &shares, &token, &share).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            error!("Failed to serve shared file: {:#}", e);
+            Ok(plain_response(StatusCode::NOT_FOUND, "Not found"))
+        }
+    }
+}
+
+async fn send_file(
+    req: &Request<Incoming>,
+    shares: &Arc<ShareManager>,
+    token: &str,
+    share: &Share,
+) -> Result<Response<ShareBody>> {
+    let mut file = tokio::fs::File::open(&share.path)
+        .await
+        .with_context(|| format!("Failed to open {:?}", share.path))?;
+    let metadata = file.metadata().await?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", size, modified);
+
+    // a resumed download only continues the same version of the file
+    let header_str = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let range = match header_str(header::IF_RANGE) {
+        Some(if_range) if if_range != etag => ByteRange::Full,
+        _ => ByteRange::parse(header_str(header::RANGE), size),
+    };
+
+    let (status, start, end) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end + 1),
+        ByteRange::Unsatisfiable => {
+            let mut response =
+                plain_response(StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable");
+            response
+                .headers_mut()
+                .insert(header::CONTENT_RANGE, format!("bytes */{}", size).parse()?);
+            return Ok(response);
+        }
+    };
+
+    if req.method() == Method::GET && start == 0 && !shares.start_download(token) {
+        return Ok(plain_response(StatusCode::NOT_FOUND, "Not found"));
+    }
+    // the download is done once the last byte is on its way
+    let mut finish =
+        (req.method() == Method::GET && end == size).then(|| (shares.clone(), token.to_string()));
+    if start == end {
+        if let Some((shares, token)) = finish.take() {
+            shares.finish_download(&token);
+        }
+    }
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, end - start)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename*=UTF-8''{}",
+                urlencoding::encode(&share.name)
+            ),
+        )
+        .header(header::CACHE_CONTROL, "no-store")
+        .header("Referrer-Policy", "no-referrer");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, size),
+        );
+    }
+
+    if req.method() == Method::HEAD {
+        return Ok(response.body(empty_body())?);
+    }
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let reader = file.take(end - start);
+    let chunks =
+        futures::stream::try_unfold((reader, finish), |(mut reader, mut finish)| async move {
+            let mut chunk = vec![0u8; CHUNK_SIZE];
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            chunk.truncate(read);
+            if reader.limit() == 0 {
+                if let Some((shares, token)) = finish.take() {
+                    shares.finish_download(&token);
+                }
+            }
+            Ok(Some((Bytes::from(chunk), (reader, finish))))
+        });
+    let body = StreamBody::new(chunks.map_ok(Frame::data));
+    Ok(response.body(BodyExt::boxed(body))?)
+}
+
+/// the part of the file a request asks for
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    /// first and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// parse a `Range` header, anything but a single byte range sends the whole file
+    fn parse(header: Option<&str>, size: u64) -> Self {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return Self::Full;
+        };
+        if spec.contains(',') {
+            return Self::Full;
+        }
+        let Some((first, last)) = spec.split_once('-') else {
+            return Self::Full;
+        };
+
+        let (first, last) = match (first.trim(), last.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return Self::Unsatisfiable,
+                Ok(len) => (size.saturating_sub(len), u64::MAX),
+                Err(_) => return Self::Full,
+            },
+            (first, "") => match first.parse::<u64>() {
+                Ok(first) => (first, u64::MAX),
+                Err(_) => return Self::Full,
+            },
+            (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(first), Ok(last)) if first <= last => (first, last),
+                _ => return Self::Full,
+            },
+        };
+
+        if first >= size {
+            return Self::Unsatisfiable;
+        }
+        Self::Partial(first, last.min(size - 1))
+    }
+}
+
+fn empty_body() -> ShareBody {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
+
+fn plain_response(status: StatusCode, message: &'static str) -> Response<ShareBody> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(
+            Full::new(Bytes::from(message))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn byte_ranges() {
+        let parse = |header| ByteRange::parse(header, 1000);
+        assert_eq!(parse(None), ByteRange::Full);
+        assert_eq!(parse(Some("bytes=0-499")), ByteRange::Partial(0, 499));
+        assert_eq!(parse(Some("bytes=500-")), ByteRange::Partial(500, 999));
+        assert_eq!(parse(Some("bytes=900-5000")), ByteRange::Partial(900, 999));
+        assert_eq!(parse(Some("bytes=-100")), ByteRange::Partial(900, 999));
+        assert_eq!(parse(Some("bytes=-5000")), ByteRange::Partial(0, 999));
+        assert_eq!(parse(Some("bytes=1000-")), ByteRange::Unsatisfiable);
+        assert_eq!(parse(Some("bytes=-0")), ByteRange::Unsatisfiable);
+        // anything we do not serve as one range gets the whole file
+        assert_eq!(parse(Some("bytes=0-1,5-9")), ByteRange::Full);
+        assert_eq!(parse(Some("bytes=9-5")), ByteRange::Full);
+        assert_eq!(parse(Some("items=0-5")), ByteRange::Full);
+        assert_eq!(parse(Some("bytes=a-b")), ByteRange::Full);
+    }
+}
@@ -0,0 +1,358 @@
+use crate::tor::identity::{self, OnionIdentity};
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// where the signatures are published on the blog
+pub const WELL_KNOWN_PREFIX: &str = "/.well-known/freedom-kit/";
+
+/// version of the canonical post document and the index
+const FORMAT_VERSION: u32 = 1;
+
+/// a post as it is signed
+#[derive(Serialize, Clone, Debug)]
+struct SignedPost {
+    slug: String,
+    title: String,
+    #[serde(rename = "type")]
+    kind: String,
+    published_at: Option<String>,
+    updated_at: Option<String>,
+    /// path of the canonical document the signature covers
+    content: String,
+    sha256: String,
+    signature: String,
+    #[serde(skip)]
+    document: Arc<String>,
+}
+
+/// the signature index served at `/.well-known/freedom-kit/signatures`
+#[derive(Serialize)]
+struct SignatureIndex<'a> {
+    version: u32,
+    onion_address: String,
+    algorithm: &'static str,
+    /// prepended to every document before signing
+    context: &'static str,
+    posts: &'a [SignedPost],
+}
+
+/// Detached signatures over the published posts, made with the onion key
+///
+/// The identity key is only read once the onion service has created it,
+/// and signatures are recomputed when a post is published or changed.
+pub struct ContentSigner {
+    tor_dir: PathBuf,
+    database: PathBuf,
+    identity: Mutex<Option<Arc<OnionIdentity>>>,
+    /// (fingerprint of the published posts, their signatures)
+    cache: Mutex<Option<(String, Arc<Vec<SignedPost>>)>>,
+}
+
+impl ContentSigner {
+    pub fn new(tor_dir: PathBuf, database: PathBuf) -> Self {
+        Self {
+            tor_dir,
+            database,
+            identity: Mutex::new(None),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Answer a request below [`WELL_KNOWN_PREFIX`]
+    ///
+    /// Returns the content type and body, `None` for unknown paths:
+    /// - `signatures`: JSON index of every published post
+    /// - `posts/<slug>`: the canonical document signed for a post
+    /// - `posts/<slug>.sig`: its detached signature
+    pub async fn serve(self: &Arc<Self>, path: &str) -> Result<Option<(&'static str, Vec<u8>)>> {
+        let Some(rest) = path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .strip_prefix(WELL_KNOWN_PREFIX)
+        else {
+            return Ok(None);
+        };
+        let rest = rest.to_string();
+        let signer = self.clone();
+        tokio::task::spawn_blocking(move || signer.serve_blocking(&rest)).await?
+    }
+
+    fn serve_blocking(&self, rest: &str) -> Result<Option<(&'static str, Vec<u8>)>> {
+        let identity = self.identity()?;
+        let posts = self.signed_posts(&identity)?;
+
+        if rest == "signatures" {
+            let index = SignatureIndex {
+                version: FORMAT_VERSION,
+                onion_address: identity.onion_address(),
+                algorithm: "ed25519",
+                context: identity::SIGNATURE_CONTEXT,
+                posts: &posts,
+            };
+            return Ok(Some((
+                "application/json",
+                serde_json::to_vec_pretty(&index)?,
+            )));
+        }
+
+        let Some(name) = rest.strip_prefix("posts/") else {
+            return Ok(None);
+        };
+        let (slug, detached) = match name.strip_suffix(".sig") {
+            Some(slug) => (slug, true),
+            None => (name, false),
+        };
+        Ok(posts.iter().find(|p| p.slug == slug).map(|post| {
+            if detached {
+                (
+                    "text/plain; charset=utf-8",
+                    post.signature.clone().into_bytes(),
+                )
+            } else {
+                (
+                    "text/plain; charset=utf-8",
+                    post.document.as_bytes().to_vec(),
+                )
+            }
+        }))
+    }
+
+    fn identity(&self) -> Result<Arc<OnionIdentity>> {
+        let mut slot = self.identity.lock().unwrap();
+        if let Some(identity) = slot.as_ref() {
+            return Ok(identity.clone());
+        }
+        let identity = Arc::new(OnionIdentity::load(&self.tor_dir)?);
+        *slot = Some(identity.clone());
+        Ok(identity)
+    }
+
+    /// drop the cached key and signatures, the keystore was wiped or replaced
+    pub fn forget_identity(&self) {
+        *self.identity.lock().unwrap() = None;
+        *self.cache.lock().unwrap() = None;
+    }
+
+    /// signatures of the published posts, reused until one of them changes
+    fn signed_posts(&self, identity: &OnionIdentity) -> Result<Arc<Vec<SignedPost>>> {
+        let conn = open_database(&self.database)?;
+        let fingerprint: String = conn.query_row(
+            "SELECT COUNT(*) || '/' || IFNULL(MAX(updated_at), '') FROM posts \
+             WHERE status = 'published'",
+            [],
+            |row| row.get(0),
+        )?;
+
+        if let Some((cached, posts)) = self.cache.lock().unwrap().as_ref() {
+            if *cached == fingerprint {
+                return Ok(posts.clone());
+            }
+        }
+
+        let mut statement = conn.prepare(
+            "SELECT id, slug, title, type, published_at, updated_at, html FROM posts \
+             WHERE status = 'published' ORDER BY published_at DESC",
+        )?;
+        let posts = statement
+            .query_map([], |row| {
+                Ok(PostRow {
+                    id: row.get(0)?,
+                    slug: row.get(1)?,
+                    title: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    kind: row.get(3)?,
+                    published_at: row.get(4)?,
+                    updated_at: row.get(5)?,
+                    html: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .map(|row| sign_post(identity, row))
+            .collect::<Vec<_>>();
+
+        let posts = Arc::new(posts);
+        *self.cache.lock().unwrap() = Some((fingerprint, posts.clone()));
+        Ok(posts)
+    }
+}
+
+struct PostRow {
+    id: String,
+    slug: String,
+    title: String,
+    kind: String,
+    published_at: Option<String>,
+    updated_at: Option<String>,
+    html: String,
+}
+
+/// The canonical document of a post: a few header lines, a blank line,
+/// then the post HTML exactly as Ghost stores it
+fn canonical_document(row: &PostRow) -> String {
+    let line = |s: &str| s.replace(['\r', '\n'], " ");
+    format!(
+        "freedom-kit-post: {}\nid: {}\nslug: {}\ntitle: {}\ntype: {}\npublished_at: {}\nupdated_at: {}\n\n{}",
+        FORMAT_VERSION,
+        line(&row.id),
+        line(&row.slug),
+        line(&row.title),
+        line(&row.kind),
+        line(row.published_at.as_deref().unwrap_or_default()),
+        line(row.updated_at.as_deref().unwrap_or_default()),
+        row.html
+    )
+}
+
+fn sign_post(identity: &OnionIdentity, row: PostRow) -> SignedPost {
+    let document = canonical_document(&row);
+    SignedPost {
+        content: format!("{}posts/{}", WELL_KNOWN_PREFIX, row.slug),
+        sha256: hex::encode(Sha256::digest(document.as_bytes())),
+        signature: identity.sign(document.as_bytes()),
+        document: Arc::new(document),
+        slug: row.slug,
+        title: row.title,
+        kind: row.kind,
+        published_at: row.published_at,
+        updated_at: row.updated_at,
+    }
+}
+
+fn open_database(database: &Path) -> Result<Connection> {
+    let conn = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open Ghost database {:?}", database))?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+/// Check a downloaded document against the onion address it claims to be from
+///
+/// `signature` is the hex signature from the index, without it the
+/// detached `<file>.sig` next to the document is used.
+pub fn verify_file(path: &Path, onion_address: &str, signature: Option<&str>) -> Result<()> {
+    let document = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let signature = match signature {
+        Some(signature) => signature.to_string(),
+        None => {
+            let mut sig_path = path.as_os_str().to_owned();
+            sig_path.push(".sig");
+            fs::read_to_string(&sig_path)
+                .with_context(|| format!("No signature given and none found at {:?}", sig_path))?
+        }
+    };
+    identity::verify(onion_address, &document, &signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(slug: &str, title: &str) -> PostRow {
+        PostRow {
+            id: "64f0c0ffee".into(),
+            slug: slug.into(),
+            title: title.into(),
+            kind: "post".into(),
+            published_at: Some("2024-05-01 10:00:00".into()),
+            updated_at: None,
+            html: "<p>first\nsecond</p>".into(),
+        }
+    }
+
+    /// a signer over a Ghost database with one published post
+    fn signer(dir: &Path, identity: OnionIdentity) -> ContentSigner {
+        let database = dir.join("ghost.db");
+        let conn = Connection::open(&database).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE posts (id TEXT, slug TEXT, title TEXT, type TEXT, status TEXT, \
+             published_at TEXT, updated_at TEXT, html TEXT);
+             INSERT INTO posts VALUES ('1', 'hello', 'Hello', 'post', 'published', \
+             '2024-05-01', '2024-05-02', '<p>hi</p>');
+             INSERT INTO posts VALUES ('2', 'draft', 'Draft', 'post', 'draft', \
+             NULL, '2024-05-03', '<p>not yet</p>');",
+        )
+        .unwrap();
+        let signer = ContentSigner::new(dir.join("tor"), database);
+        *signer.identity.lock().unwrap() = Some(Arc::new(identity));
+        signer
+    }
+
+    #[test]
+    fn header_lines_cannot_be_forged() {
+        let document = canonical_document(&row("a\nslug", "Title\r\nupdated_at: later"));
+        let (header, body) = document.split_once("\n\n").unwrap();
+        assert_eq!(
+            header,
+            "freedom-kit-post: 1\nid: 64f0c0ffee\nslug: a slug\n\
+             title: Title  updated_at: later\ntype: post\n\
+             published_at: 2024-05-01 10:00:00\nupdated_at: "
+        );
+        // the HTML is signed exactly as stored
+        assert_eq!(body, "<p>first\nsecond</p>");
+    }
+
+    #[test]
+    fn signed_posts_verify_against_the_onion_address() {
+        let identity = OnionIdentity::generate();
+        let address = identity.onion_address();
+        let post = sign_post(&identity, row("hello", "Hello"));
+        identity::verify(&address, post.document.as_bytes(), &post.signature).unwrap();
+        assert_eq!(
+            post.sha256,
+            hex::encode(Sha256::digest(post.document.as_bytes()))
+        );
+
+        let tampered = post.document.replace("first", "altered");
+        assert!(identity::verify(&address, tampered.as_bytes(), &post.signature).is_err());
+        let other = OnionIdentity::generate().onion_address();
+        assert!(identity::verify(&other, post.document.as_bytes(), &post.signature).is_err());
+    }
+
+    #[test]
+    fn serves_documents_and_detached_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = OnionIdentity::generate();
+        let address = identity.onion_address();
+        let signer = signer(dir.path(), identity);
+
+        let (kind, document) = signer.serve_blocking("posts/hello").unwrap().unwrap();
+        assert_eq!(kind, "text/plain; charset=utf-8");
+        assert!(document.ends_with(b"<p>hi</p>"));
+        let (_, signature) = signer.serve_blocking("posts/hello.sig").unwrap().unwrap();
+        identity::verify(&address, &document, &String::from_utf8(signature).unwrap()).unwrap();
+
+        let (kind, index) = signer.serve_blocking("signatures").unwrap().unwrap();
+        assert_eq!(kind, "application/json");
+        let index: serde_json::Value = serde_json::from_slice(&index).unwrap();
+        assert_eq!(index["onion_address"], address.as_str());
+        assert_eq!(index["posts"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            index["posts"][0]["content"],
+            "/.well-known/freedom-kit/posts/hello"
+        );
+
+        // drafts, unknown posts and other paths are not served
+        for path in ["posts/draft", "posts/missing.sig", "posts/", "other"] {
+            assert!(signer.serve_blocking(path).unwrap().is_none(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn forgetting_the_identity_reloads_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let signer = signer(dir.path(), OnionIdentity::generate());
+        assert!(signer.serve_blocking("signatures").unwrap().is_some());
+
+        // the keystore was wiped, there is nothing left to sign with
+        signer.forget_identity();
+        let err = signer.serve_blocking("signatures").err().unwrap();
+        assert!(err.to_string().starts_with("No onion service keys yet"));
+    }
+}
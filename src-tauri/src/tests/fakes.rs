@@ -0,0 +1,284 @@
+//! Offline stand-ins for Ghost and Tor
+
+use crate::tor::backend::{OnionBackend, OnionStreamRequest};
+use crate::tor::metrics::ServiceCounters;
+use crate::tor::proxy::Upstream;
+use anyhow::{anyhow, Context, Result};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// the address every fake onion service gets
+pub const ONION_NAME: &str = "fakefakefakefakefakefakefakefakefakefakefakefakefakefake.onion";
+
+/// a free loopback port, nothing listens on it
+pub fn free_port() -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// served at `/image.bin`: every byte value, including the invalid UTF-8 ones
+pub fn binary_body() -> Vec<u8> {
+    (0..=255u8).cycle().take(64 * 1024 + 7).collect()
+}
+
+/// a local hyper server answering like Ghost
+pub struct FakeGhost {
+    pub upstream: Upstream,
+    handle: JoinHandle<()>,
+}
+
+impl FakeGhost {
+    /// Ghost on a random loopback port
+    pub async fn start() -> Self {
+        Self::on_port(0).await
+    }
+
+    /// Ghost on `port`, for when the proxy was pointed there first
+    pub async fn on_port(port: u16) -> Self {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream));
+            }
+        });
+        Self {
+            upstream: Upstream::Tcp(port),
+            handle,
+        }
+    }
+
+    /// Ghost on a Unix domain socket at `socket`
+    #[cfg(unix)]
+    pub async fn on_socket(socket: &std::path::Path) -> Self {
+        let listener = tokio::net::UnixListener::bind(socket).unwrap();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream));
+            }
+        });
+        Self {
+            upstream: Upstream::Unix(socket.to_path_buf()),
+            handle,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match &self.upstream {
+            Upstream::Tcp(port) => *port,
+            #[cfg(unix)]
+            Upstream::Unix(_) => panic!("Ghost is on a socket"),
+        }
+    }
+}
+
+impl Drop for FakeGhost {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve<S>(io: S)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let _ = http1::Builder::new()
+        .serve_connection(TokioIo::new(io), service_fn(ghost))
+        .await;
+}
+
+async fn ghost(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let response = Response::builder();
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/image.bin") => response
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(Full::new(Bytes::from(binary_body()))),
+        (&Method::GET, "/ghost") => response
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(LOCATION, "/ghost/#/signin")
+            .body(Full::default()),
+        (&Method::GET, "/ghost/api/admin/site/") => response
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from_static(
+                br#"{"site":{"title":"Fake Ghost","version":"5.0"}}"#,
+            ))),
+        (&Method::POST, "/echo") => {
+            let content_type = req
+                .headers()
+                .get(CONTENT_TYPE)
+                .cloned()
+                .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            response
+                .header(CONTENT_TYPE, content_type)
+                .body(Full::new(body))
+        }
+        _ => response
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from_static(b"Not found"))),
+    };
+    Ok(response.unwrap())
+}
+
+/// An in-process Tor: services are published nowhere
+///
+/// Visitors are simulated with [`FakeTor::visit`], which hands the service
+/// one end of an in-memory pipe, the way a rendezvous stream would arrive.
+#[derive(Default)]
+pub struct FakeTor {
+    /// where streams for the service launched last go
+    visitors: Mutex<Option<mpsc::UnboundedSender<FakeRequest>>>,
+    pub launches: AtomicUsize,
+    pub dormant: AtomicBool,
+    /// services launched and not dropped yet
+    pub live: Arc<AtomicUsize>,
+    /// what happened, in order, for tests about startup ordering
+    pub events: Mutex<Vec<&'static str>>,
+}
+
+/// keeps the fake service counted as published
+pub struct FakeService(Arc<AtomicUsize>);
+
+impl Drop for FakeService {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct FakeRequest {
+    port: u16,
+    stream: DuplexStream,
+}
+
+impl OnionStreamRequest for FakeRequest {
+    type Stream = DuplexStream;
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    async fn open(self) -> Result<Self::Stream> {
+        Ok(self.stream)
+    }
+
+    fn reject(self) {}
+}
+
+impl OnionBackend for FakeTor {
+    type Service = FakeService;
+    type Request = FakeRequest;
+
+    fn launch(
+        &self,
+        _nickname: &str,
+        counters: &Arc<ServiceCounters>,
+    ) -> Result<(Self::Service, String, BoxStream<'static, Self::Request>)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.visitors.lock().unwrap() = Some(tx);
+        self.launches.fetch_add(1, Ordering::SeqCst);
+        self.live.fetch_add(1, Ordering::SeqCst);
+        self.events.lock().unwrap().push("launch");
+
+        let counters = counters.clone();
+        let requests = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|request| (request, rx))
+        })
+        .inspect(move |_| counters.count_rend_request())
+        .boxed();
+        Ok((
+            FakeService(self.live.clone()),
+            ONION_NAME.to_string(),
+            requests,
+        ))
+    }
+
+    fn state(_service: &Self::Service) -> String {
+        "running".to_string()
+    }
+
+    fn set_dormant(&self, dormant: bool) {
+        self.dormant.store(dormant, Ordering::SeqCst);
+    }
+}
+
+impl FakeTor {
+    /// open a stream to `port` on the service, like a visitor would
+    pub fn visit(&self, port: u16) -> Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let visitors = self
+            .visitors
+            .lock()
+            .unwrap()
+            .clone()
+            .context("No service was launched")?;
+        visitors
+            .send(FakeRequest {
+                port,
+                stream: server,
+            })
+            .map_err(|_| anyhow!("The service is not serving"))?;
+        Ok(client)
+    }
+
+    pub fn is_dormant(&self) -> bool {
+        self.dormant.load(Ordering::SeqCst)
+    }
+
+    pub fn live_services(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+}
+
+/// a response as the test sees it
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: hyper::HeaderMap,
+    pub body: Bytes,
+}
+
+/// send one HTTP request over `io`, e.g. a stream from [`FakeTor::visit`]
+pub async fn request<S>(io: S, req: Request<Full<Bytes>>) -> Result<Reply>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+    tokio::spawn(connection);
+    let (parts, body) = sender.send_request(req).await?.into_parts();
+    Ok(Reply {
+        status: parts.status,
+        headers: parts.headers,
+        body: body.collect().await?.to_bytes(),
+    })
+}
+
+/// `GET path` with the Host a Tor Browser would send
+pub fn onion_get(path: &str) -> Request<Full<Bytes>> {
+    Request::get(path)
+        .header(hyper::header::HOST, ONION_NAME)
+        .body(Full::default())
+        .unwrap()
+}
+
+/// a client for the webview proxy, handing redirects back like a browser sees them
+pub fn webview_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,200 @@
+//! Start order, orderly shutdown and left over sidecars
+
+use crate::lifecycle::{self, Lifecycle, ShutdownSettings, Stage};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn settings() -> ShutdownSettings {
+    ShutdownSettings {
+        ghost_grace_secs: 0,
+        stage_timeout_secs: 1,
+    }
+}
+
+#[tokio::test]
+async fn stops_in_reverse_start_order() {
+    let lifecycle = Lifecycle::default();
+    for stage in [Stage::Ghost, Stage::Storage, Stage::Proxy, Stage::Tor] {
+        lifecycle.started(stage);
+    }
+
+    let stopped = Mutex::new(Vec::new());
+    lifecycle
+        .shutdown(&settings(), |stage| {
+            stopped.lock().unwrap().push(stage);
+            async { Ok(()) }
+        })
+        .await;
+
+    assert_eq!(
+        *stopped.lock().unwrap(),
+        [Stage::Ghost, Stage::Proxy, Stage::Tor, Stage::Storage]
+    );
+    assert!(lifecycle.running().is_empty());
+}
+
+#[tokio::test]
+async fn a_hung_or_failing_stage_does_not_stop_the_rest() {
+    let lifecycle = Lifecycle::default();
+    for stage in [
+        Stage::Storage,
+        Stage::Tor,
+        Stage::Proxy,
+        Stage::HiddenService,
+    ] {
+        lifecycle.started(stage);
+    }
+
+    let stopped = Mutex::new(Vec::new());
+    lifecycle
+        .shutdown(&settings(), |stage| {
+            let stopped = &stopped;
+            async move {
+                match stage {
+                    Stage::HiddenService => anyhow::bail!("descriptor upload stuck"),
+                    Stage::Proxy => std::future::pending().await,
+                    _ => {}
+                }
+                stopped.lock().unwrap().push(stage);
+                Ok(())
+            }
+        })
+        .await;
+
+    assert_eq!(*stopped.lock().unwrap(), [Stage::Tor, Stage::Storage]);
+    assert!(lifecycle.running().is_empty());
+}
+
+#[tokio::test]
+async fn sealing_the_storage_is_never_cut_short() {
+    let lifecycle = Lifecycle::default();
+    lifecycle.started(Stage::Storage);
+    lifecycle.started(Stage::Proxy);
+
+    let sealed = Mutex::new(false);
+    let start = Instant::now();
+    lifecycle
+        .shutdown(&settings(), |stage| {
+            let sealed = &sealed;
+            async move {
+                if stage == Stage::Storage {
+                    // longer than `stage_timeout_secs`
+                    tokio::time::sleep(Duration::from_millis(1500)).await;
+                    *sealed.lock().unwrap() = true;
+                }
+                Ok(())
+            }
+        })
+        .await;
+
+    assert!(*sealed.lock().unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+    assert!(lifecycle.running().is_empty());
+}
+
+#[tokio::test]
+async fn shutting_down_twice_waits_for_the_first() {
+    let lifecycle = Arc::new(Lifecycle::default());
+    lifecycle.started(Stage::Ghost);
+    let waiting = lifecycle.wait_for(Stage::HiddenService);
+
+    let done = Arc::new(Mutex::new(false));
+    let first = {
+        let (lifecycle, done) = (lifecycle.clone(), done.clone());
+        tokio::spawn(async move {
+            lifecycle
+                .shutdown(&settings(), |_| async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(())
+                })
+                .await;
+            *done.lock().unwrap() = true;
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(lifecycle.is_shutting_down());
+
+    lifecycle
+        .shutdown(&settings(), |_| async { panic!("stopped twice") })
+        .await;
+    assert!(lifecycle.running().is_empty());
+    first.await.unwrap();
+    assert!(*done.lock().unwrap());
+
+    // nothing starts any more, and nothing waiting for a stage hangs
+    lifecycle.started(Stage::HiddenService);
+    assert!(lifecycle.running().is_empty());
+    assert!(waiting.await.is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sigterm_comes_before_a_kill() {
+    let mut polite = tokio::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    let pid = polite.id().unwrap();
+    let exited = async {
+        let _ = polite.wait().await;
+    };
+    assert!(lifecycle::terminate(pid, exited, Duration::from_secs(5)).await);
+
+    let mut stubborn = tokio::process::Command::new("sh")
+        .args(["-c", "trap '' TERM; sleep 30"])
+        .spawn()
+        .unwrap();
+    // let the shell install its trap
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let pid = stubborn.id().unwrap();
+    let start = Instant::now();
+    let exited = async {
+        let _ = stubborn.wait().await;
+    };
+    assert!(!lifecycle::terminate(pid, exited, Duration::from_millis(300)).await);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    stubborn.kill().await.unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn orphans_are_recorded_and_only_sidecars_reaped() {
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("run").join("sidecars.pid");
+
+    lifecycle::record_sidecar(&pid_file, "ghost", 4242).unwrap();
+    lifecycle::record_sidecar(&pid_file, "railgun", 4343).unwrap();
+    lifecycle::forget_sidecar(&pid_file, "ghost");
+    assert_eq!(
+        std::fs::read_to_string(&pid_file).unwrap(),
+        "railgun 4343\n"
+    );
+    lifecycle::forget_sidecar(&pid_file, "railgun");
+    assert!(!pid_file.exists());
+
+    // the PID was reused by something that is not our sidecar
+    let mut bystander = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    lifecycle::record_sidecar(&pid_file, "ghost", bystander.id()).unwrap();
+    lifecycle::reap_orphans(&pid_file, Duration::from_millis(100));
+    assert!(bystander.try_wait().unwrap().is_none());
+    assert!(!pid_file.exists());
+    bystander.kill().unwrap();
+
+    // a Ghost the last run left behind
+    let sidecar = dir.path().join("ghost-sidecar");
+    std::fs::copy("/bin/sleep", &sidecar).unwrap();
+    let mut orphan = std::process::Command::new(&sidecar)
+        .arg("30")
+        .spawn()
+        .unwrap();
+    lifecycle::record_sidecar(&pid_file, "ghost", orphan.id()).unwrap();
+    lifecycle::reap_orphans(&pid_file, Duration::from_millis(300));
+    let start = Instant::now();
+    while orphan.try_wait().unwrap().is_none() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,12 @@
+//! Offline integration tests
+//!
+//! A local hyper server plays Ghost and [`fakes::FakeTor`] stands in for
+//! Arti, so the proxy, the onion service and the Ghost launcher run without
+//! a network or a sidecar.
+
+mod fakes;
+mod lifecycle;
+mod onion;
+mod proxy;
+mod share;
+mod startup;
@@ -0,0 +1,157 @@
+//! The blog's onion service, fed by fake rendezvous streams
+
+use super::fakes::{binary_body, free_port, onion_get, request, FakeGhost, FakeTor, ONION_NAME};
+use crate::tor::hidden_service::{self, Routes};
+use crate::tor::proxy::Upstream;
+use crate::tor::{HiddenServiceConfig, HiddenServiceManager};
+use hyper::body::Bytes;
+use hyper::StatusCode;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+async fn publish(upstream: Upstream, dir: &Path) -> (Arc<FakeTor>, HiddenServiceManager<FakeTor>) {
+    let tor = Arc::new(FakeTor::default());
+    let config = HiddenServiceConfig {
+        tor_data_dir: dir.to_path_buf(),
+        upstream,
+        onion_port: 80,
+        access_log: None,
+    };
+    let service = hidden_service::publish(
+        async { anyhow::Ok(tor.clone()) },
+        |_| async { Ok(()) },
+        config,
+        Routes::default(),
+    )
+    .await
+    .unwrap();
+    (tor, service)
+}
+
+#[tokio::test]
+async fn binary_body_passes_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, service) = publish(ghost.upstream.clone(), dir.path()).await;
+    assert_eq!(
+        service.onion_url().unwrap(),
+        format!("http://{}:80", ONION_NAME)
+    );
+
+    let reply = request(tor.visit(80).unwrap(), onion_get("/image.bin"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body, binary_body());
+
+    let upload = hyper::Request::post("/echo")
+        .header(hyper::header::HOST, ONION_NAME)
+        .body(http_body_util::Full::new(Bytes::from(binary_body())))
+        .unwrap();
+    let reply = request(tor.visit(80).unwrap(), upload).await.unwrap();
+    assert_eq!(reply.body, binary_body());
+
+    let stats = service.metrics();
+    assert_eq!(stats.state, "running");
+    assert_eq!(stats.rend_requests, 2);
+    assert_eq!(stats.blog.opened, 2);
+    assert!(stats.blog.bytes_out >= 2 * binary_body().len() as u64);
+}
+
+#[tokio::test]
+async fn redirects_go_back_to_the_visitor() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, _service) = publish(ghost.upstream.clone(), dir.path()).await;
+
+    let reply = request(tor.visit(80).unwrap(), onion_get("/ghost"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(reply.headers["location"], "/ghost/#/signin");
+}
+
+#[tokio::test]
+async fn ghost_down_is_a_bad_gateway() {
+    let dir = tempfile::tempdir().unwrap();
+    let (tor, _service) = publish(Upstream::Tcp(free_port()), dir.path()).await;
+
+    let reply = request(tor.visit(80).unwrap(), onion_get("/"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn other_ports_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, service) = publish(ghost.upstream.clone(), dir.path()).await;
+
+    let mut stream = tor.visit(22).unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    assert!(received.is_empty());
+
+    let stats = service.metrics();
+    assert_eq!(stats.rejected_streams, 1);
+    assert_eq!(stats.blog.opened, 0);
+}
+
+#[tokio::test]
+async fn stop_takes_the_service_down() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, mut service) = publish(ghost.upstream.clone(), dir.path()).await;
+    assert!(service.is_running());
+    assert_eq!(tor.live_services(), 1);
+
+    service.stop().await.unwrap();
+
+    assert!(!service.is_running());
+    assert_eq!(service.onion_address(), None);
+    assert_eq!(tor.live_services(), 0);
+    assert!(tor.visit(80).is_err());
+    assert_eq!(service.metrics().state, "stopped");
+}
+
+#[tokio::test]
+async fn pause_and_resume_keep_the_address() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, mut service) = publish(ghost.upstream.clone(), dir.path()).await;
+    let address = service.onion_address().unwrap().to_string();
+
+    service.pause().await.unwrap();
+    assert!(service.is_paused());
+    assert!(tor.is_dormant());
+    assert_eq!(tor.live_services(), 0);
+    assert!(tor.visit(80).is_err());
+    assert_eq!(service.metrics().state, "paused");
+
+    service.resume().await.unwrap();
+    assert!(!service.is_paused());
+    assert!(!tor.is_dormant());
+    assert_eq!(service.onion_address(), Some(address.as_str()));
+    assert_eq!(tor.live_services(), 1);
+    assert_eq!(tor.launches.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+    let reply = request(tor.visit(80).unwrap(), onion_get("/image.bin"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn stopping_a_paused_service_wakes_tor() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let (tor, mut service) = publish(ghost.upstream.clone(), dir.path()).await;
+
+    service.pause().await.unwrap();
+    service.stop().await.unwrap();
+
+    assert!(!tor.is_dormant());
+    assert!(!service.is_paused());
+}
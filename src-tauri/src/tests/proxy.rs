@@ -0,0 +1,129 @@
+//! The webview proxy in front of Ghost
+
+use super::fakes::{binary_body, free_port, webview_client, FakeGhost};
+use crate::tor::proxy::{start_local_proxy, Upstream};
+use hyper::StatusCode;
+
+async fn proxy(upstream: Upstream) -> (u16, tokio::task::JoinHandle<()>) {
+    let port = free_port();
+    let handle = start_local_proxy(port, upstream, false, None, None, None, None)
+        .await
+        .unwrap();
+    (port, handle)
+}
+
+#[tokio::test]
+async fn binary_body_passes_unchanged() {
+    let ghost = FakeGhost::start().await;
+    let (port, _handle) = proxy(ghost.upstream.clone()).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/image.bin", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/octet-stream"
+    );
+    assert_eq!(response.bytes().await.unwrap(), binary_body());
+}
+
+#[tokio::test]
+async fn binary_upload_reaches_ghost() {
+    let ghost = FakeGhost::start().await;
+    let (port, _handle) = proxy(ghost.upstream.clone()).await;
+
+    let response = webview_client()
+        .post(format!("http://127.0.0.1:{}/echo", port))
+        .header("content-type", "image/png")
+        .body(binary_body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(response.bytes().await.unwrap(), binary_body());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn binary_body_over_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::on_socket(&dir.path().join("ghost.sock")).await;
+    let (port, _handle) = proxy(ghost.upstream.clone()).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/image.bin", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap(), binary_body());
+}
+
+#[tokio::test]
+async fn redirects_go_back_to_the_browser() {
+    let ghost = FakeGhost::start().await;
+    let (port, _handle) = proxy(ghost.upstream.clone()).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/ghost", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["location"], "/ghost/#/signin");
+}
+
+#[tokio::test]
+async fn ghost_down_is_a_bad_gateway() {
+    let (port, _handle) = proxy(Upstream::Tcp(free_port())).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn missing_socket_is_a_bad_gateway() {
+    let dir = tempfile::tempdir().unwrap();
+    let (port, _handle) = proxy(Upstream::Unix(dir.path().join("ghost.sock"))).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn other_hosts_are_refused() {
+    let ghost = FakeGhost::start().await;
+    let (port, _handle) = proxy(ghost.upstream.clone()).await;
+
+    let response = webview_client()
+        .get(format!("http://127.0.0.1:{}/image.bin", port))
+        .header("host", "attacker.example")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+}
+
+#[tokio::test]
+async fn stopping_the_proxy_frees_its_port() {
+    let ghost = FakeGhost::start().await;
+    let (port, handle) = proxy(ghost.upstream.clone()).await;
+
+    handle.abort();
+    let _ = handle.await;
+
+    assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_ok());
+}
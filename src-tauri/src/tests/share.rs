@@ -0,0 +1,76 @@
+//! Files shared over the onion service
+
+use super::fakes::{request, Reply};
+use crate::share::{serve_connection, ShareManager};
+use http_body_util::Full;
+use hyper::{header, Request, StatusCode};
+use std::sync::Arc;
+
+/// bigger than one streamed chunk
+const FILE_SIZE: usize = 200 * 1024;
+
+fn shared_file() -> (tempfile::TempDir, std::path::PathBuf, Vec<u8>) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("leak.bin");
+    let contents: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &contents).unwrap();
+    (dir, path, contents)
+}
+
+async fn get(shares: &Arc<ShareManager>, path: &str, range: Option<&str>) -> Reply {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(serve_connection(server, shares.clone()));
+    let mut req = Request::get(path);
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    request(client, req.body(Full::default()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn download_limit_closes_the_share() {
+    let (_dir, path, contents) = shared_file();
+    let shares = Arc::new(ShareManager::default());
+    let share = shares.add(&path, None, Some(2)).unwrap();
+
+    for _ in 0..2 {
+        let reply = get(&shares, &share.url_path, None).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        assert_eq!(reply.body, contents);
+    }
+    assert!(shares.list().is_empty());
+    let reply = get(&shares, &share.url_path, None).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn last_download_can_be_resumed() {
+    let (_dir, path, contents) = shared_file();
+    let shares = Arc::new(ShareManager::default());
+    let share = shares.add(&path, None, Some(1)).unwrap();
+
+    // the connection drops half way
+    let half = FILE_SIZE / 2;
+    let reply = get(
+        &shares,
+        &share.url_path,
+        Some(&format!("bytes=0-{}", half - 1)),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(reply.body, contents[..half]);
+    assert_eq!(shares.list()[0].downloads, 1);
+
+    // nobody else gets a copy, but the download goes on
+    let reply = get(&shares, &share.url_path, None).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = get(&shares, &share.url_path, Some(&format!("bytes={}-", half))).await;
+    assert_eq!(reply.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(reply.body, contents[half..]);
+
+    assert!(shares.list().is_empty());
+    let reply = get(&shares, &share.url_path, Some(&format!("bytes={}-", half))).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
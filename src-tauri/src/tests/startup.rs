@@ -0,0 +1,195 @@
+//! What happens in which order while the app comes up
+
+use super::fakes::{free_port, onion_get, request, webview_client, FakeGhost, FakeTor};
+use crate::ghost::{self, GhostPhase, ReadinessSettings, SidecarEvent};
+use crate::tor::hidden_service::{self, Routes};
+use crate::tor::proxy::{start_local_proxy, Upstream};
+use crate::tor::HiddenServiceConfig;
+use hyper::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+
+fn config(dir: &std::path::Path, upstream: Upstream) -> HiddenServiceConfig {
+    HiddenServiceConfig {
+        tor_data_dir: dir.to_path_buf(),
+        upstream,
+        onion_port: 80,
+        access_log: None,
+    }
+}
+
+fn readiness() -> ReadinessSettings {
+    ReadinessSettings {
+        startup_timeout_secs: 10,
+        probe_interval_ms: 20,
+        probe_timeout_secs: 1,
+    }
+}
+
+/// a sidecar whose output the test writes
+fn sidecar() -> (
+    mpsc::UnboundedSender<SidecarEvent>,
+    impl futures::Stream<Item = SidecarEvent>,
+) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (event, rx))
+    });
+    (tx, events)
+}
+
+fn stdout(line: &str) -> SidecarEvent {
+    SidecarEvent::Stdout(format!("{}\n", line))
+}
+
+#[tokio::test]
+async fn webview_gets_tor_before_the_service_launches() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost = FakeGhost::start().await;
+    let tor = Arc::new(FakeTor::default());
+
+    let service = hidden_service::publish(
+        async { anyhow::Ok(tor.clone()) },
+        |client: Arc<FakeTor>| async move {
+            client.events.lock().unwrap().push("client");
+            Ok(())
+        },
+        config(dir.path(), ghost.upstream.clone()),
+        Routes::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(*tor.events.lock().unwrap(), ["client", "launch"]);
+    assert!(service.is_running());
+}
+
+#[tokio::test]
+async fn failed_bootstrap_publishes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let handed_out = AtomicBool::new(false);
+
+    let result = hidden_service::publish::<FakeTor, _>(
+        async { anyhow::bail!("no network") },
+        |_| async {
+            handed_out.store(true, Ordering::SeqCst);
+            Ok(())
+        },
+        config(dir.path(), Upstream::Tcp(free_port())),
+        Routes::default(),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(!handed_out.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn proxies_answer_once_ghost_is_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let ghost_port = free_port();
+    let upstream = Upstream::Tcp(ghost_port);
+
+    let proxy_port = free_port();
+    let _proxy = start_local_proxy(proxy_port, upstream.clone(), false, None, None, None, None)
+        .await
+        .unwrap();
+    let tor = Arc::new(FakeTor::default());
+    let _service = hidden_service::publish(
+        async { anyhow::Ok(tor.clone()) },
+        |_| async { Ok(()) },
+        config(dir.path(), upstream),
+        Routes::default(),
+    )
+    .await
+    .unwrap();
+    let url = format!("http://127.0.0.1:{}/image.bin", proxy_port);
+
+    // both are up before Ghost is
+    let response = webview_client().get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let reply = request(tor.visit(80).unwrap(), onion_get("/image.bin"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::BAD_GATEWAY);
+
+    let _ghost = FakeGhost::on_port(ghost_port).await;
+
+    let response = webview_client().get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let reply = request(tor.visit(80).unwrap(), onion_get("/image.bin"))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn ghost_is_ready_after_its_phases() {
+    let ghost = FakeGhost::start().await;
+    let (tx, events) = sidecar();
+    let (phase_tx, phase_rx) = watch::channel(GhostPhase::Starting);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+
+    let watcher = {
+        let seen = seen.clone();
+        tokio::spawn(ghost::watch_sidecar(events, phase_tx, move |phase| {
+            seen.lock().unwrap().push(phase.clone())
+        }))
+    };
+    tx.send(stdout("Ghost boot log line")).unwrap();
+    tx.send(stdout("@@freedom-kit:phase migrating")).unwrap();
+    tx.send(SidecarEvent::Stderr("a warning".to_string()))
+        .unwrap();
+    tx.send(stdout("@@freedom-kit:phase ready")).unwrap();
+
+    ghost::wait_until_ready(&ghost::base_url(ghost.port()), phase_rx, &readiness())
+        .await
+        .unwrap();
+
+    drop(tx);
+    assert!(watcher.await.unwrap().is_none());
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [GhostPhase::Migrating, GhostPhase::Ready]
+    );
+}
+
+#[tokio::test]
+async fn ghost_exiting_ends_the_wait() {
+    let (tx, events) = sidecar();
+    let (phase_tx, phase_rx) = watch::channel(GhostPhase::Starting);
+    let watcher = tokio::spawn(ghost::watch_sidecar(events, phase_tx, |_| {}));
+
+    tx.send(stdout("@@freedom-kit:phase booting")).unwrap();
+    tx.send(SidecarEvent::Terminated {
+        code: Some(1),
+        signal: None,
+    })
+    .unwrap();
+
+    let error = ghost::wait_until_ready(&ghost::base_url(free_port()), phase_rx, &readiness())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("Ghost failed to start"));
+
+    let exit = watcher.await.unwrap().unwrap();
+    assert_eq!(exit.sidecar, "ghost");
+    assert_eq!(exit.code, Some(1));
+}
+
+#[tokio::test]
+async fn ghost_not_answering_times_out() {
+    let (_tx, events) = sidecar();
+    let (phase_tx, phase_rx) = watch::channel(GhostPhase::Starting);
+    tokio::spawn(ghost::watch_sidecar(events, phase_tx, |_| {}));
+
+    let settings = ReadinessSettings {
+        startup_timeout_secs: 1,
+        ..readiness()
+    };
+    let error = ghost::wait_until_ready(&ghost::base_url(free_port()), phase_rx, &settings)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("did not become ready"));
+}
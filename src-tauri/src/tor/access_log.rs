@@ -0,0 +1,481 @@
+use crate::crypto::{self, SecretKey};
+use crate::paths::AppPaths;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{error, info};
+
+/// routes seen fewer times in an hour are counted as "other"
+const MIN_ROUTE_COUNT: u64 = 3;
+/// stands in for anything that could identify a visitor
+const REDACTED: &str = "[redacted]";
+
+/// what the proxy records about the requests it forwards
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogMode {
+    /// nothing is recorded
+    #[default]
+    Off,
+    /// request counts per route, written once an hour
+    Aggregate,
+    /// every request, with tokens and query values redacted
+    Debug,
+}
+
+/// access logging of the webview and onion proxies, applied on the next start
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AccessLogSettings {
+    pub mode: AccessLogMode,
+    /// size a log file grows to before it is rotated, in KiB
+    pub max_file_kb: u64,
+    /// rotated files kept next to the current one
+    pub keep_files: u32,
+}
+
+impl Default for AccessLogSettings {
+    fn default() -> Self {
+        Self {
+            mode: AccessLogMode::Off,
+            max_file_kb: 1024,
+            keep_files: 3,
+        }
+    }
+}
+
+/// where a request reached the proxy
+#[derive(Clone, Copy, Debug)]
+pub enum Source {
+    Onion,
+    Webview,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Onion => "onion",
+            Self::Webview => "webview",
+        }
+    }
+}
+
+/// Encrypted, rotating access log
+///
+/// Every line is sealed on its own with a key kept in the app data dir, so
+/// a crash loses at most the line being written. Panic wipes destroy the
+/// key, leaving the logs unreadable. Never records addresses or headers.
+pub struct AccessLog {
+    mode: AccessLogMode,
+    dir: PathBuf,
+    key: SecretKey,
+    max_bytes: u64,
+    keep_files: u32,
+    /// route counts of the current hour, for [`AccessLogMode::Aggregate`]
+    hour: Mutex<(i64, BTreeMap<String, u64>)>,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl AccessLog {
+    /// open the log for `settings`, `None` when logging is off
+    pub fn open(paths: &AppPaths, settings: &AccessLogSettings) -> Result<Option<Self>> {
+        if settings.mode == AccessLogMode::Off {
+            return Ok(None);
+        }
+        let dir = paths.logs_dir();
+        create_private_dir(&dir)?;
+
+        info!("proxy access log enabled ({:?})", settings.mode);
+        Ok(Some(Self {
+            mode: settings.mode,
+            dir,
+            key: load_key(&paths.access_log_key(), true)?,
+            max_bytes: settings.max_file_kb.max(1).saturating_mul(1024),
+            keep_files: settings.keep_files,
+            hour: Mutex::new((current_hour(), BTreeMap::new())),
+        }))
+    }
+
+    /// note a forwarded request, `path` with its query as the client sent it
+    pub fn record(&self, source: Source, method: &hyper::Method, path: &str, status: u16) {
+        let result = match self.mode {
+            AccessLogMode::Off => Ok(()),
+            AccessLogMode::Aggregate => {
+                let mut hour = self.hour.lock().unwrap();
+                let now = current_hour();
+                let flushed = if hour.0 != now {
+                    let counts = std::mem::take(&mut hour.1);
+                    let flushed = self.write_hour(hour.0, counts);
+                    hour.0 = now;
+                    flushed
+                } else {
+                    Ok(())
+                };
+                *hour
+                    .1
+                    .entry(format!("{} {} {}", source.as_str(), method, route(path)))
+                    .or_default() += 1;
+                flushed
+            }
+            AccessLogMode::Debug => self.append(&format!(
+                "{} {} {} {} {}",
+                Utc::now().format("%Y-%m-%dT%H:%MZ"),
+                source.as_str(),
+                method,
+                redact(path),
+                status
+            )),
+        };
+        if let Err(e) = result {
+            error!("Failed to write the access log: {:#}", e);
+        }
+    }
+
+    /// one line with the counts of an hour, rare routes folded into "other"
+    fn write_hour(&self, hour: i64, counts: BTreeMap<String, u64>) -> Result<()> {
+        if counts.is_empty() {
+            return Ok(());
+        }
+        let mut requests = BTreeMap::new();
+        for (route, count) in counts {
+            let key = if count < MIN_ROUTE_COUNT {
+                "other".to_string()
+            } else {
+                route
+            };
+            *requests.entry(key).or_insert(0u64) += count;
+        }
+        let start = DateTime::from_timestamp(hour * 3600, 0).unwrap_or_default();
+        self.append(&serde_json::to_string(&serde_json::json!({
+            "hour": start.format("%Y-%m-%dT%H:00Z").to_string(),
+            "requests": requests,
+        }))?)
+    }
+
+    /// seal a line onto the current file, rotating it when full
+    fn append(&self, line: &str) -> Result<()> {
+        let sealed = crypto::seal(&self.key, line.as_bytes())?;
+        let current = log_file(&self.dir, 0);
+        let size = fs::metadata(&current).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + sealed.len() as u64 + 4 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&current)
+            .with_context(|| format!("Failed to open {:?}", current))?;
+        let mut record = (sealed.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(&sealed);
+        file.write_all(&record)?;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let oldest = log_file(&self.dir, self.keep_files);
+        if oldest.exists() {
+            crate::wipe::shred_tree(&oldest);
+        }
+        for index in (0..self.keep_files).rev() {
+            let from = log_file(&self.dir, index);
+            if from.exists() {
+                fs::rename(&from, log_file(&self.dir, index + 1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        // the hour in progress is written out when the proxies stop
+        if self.mode == AccessLogMode::Aggregate {
+            let (hour, counts) = std::mem::take(&mut *self.hour.lock().unwrap());
+            if let Err(e) = self.write_hour(hour, counts) {
+                error!("Failed to write the access log: {:#}", e);
+            }
+        }
+    }
+}
+
+/// decrypt every log file, oldest line first
+pub fn read_lines(paths: &AppPaths) -> Result<Vec<String>> {
+    let dir = paths.logs_dir();
+    let mut files: Vec<(u32, PathBuf)> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let index = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix("access.")?
+                    .strip_suffix(".log")?
+                    .parse()
+                    .ok()?;
+                Some((index, path))
+            })
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+    };
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    files.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+
+    let key = load_key(&paths.access_log_key(), false)?;
+    let mut lines = Vec::new();
+    for (_, path) in files {
+        let mut raw = Vec::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut raw))
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let mut rest = raw.as_slice();
+        while rest.len() >= 4 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            // a line cut short by a crash ends the file
+            let Some(sealed) = rest.get(4..4 + len) else {
+                break;
+            };
+            lines.push(String::from_utf8_lossy(&crypto::open(&key, sealed)?).into_owned());
+            rest = &rest[4 + len..];
+        }
+    }
+    Ok(lines)
+}
+
+/// shred every log file
+pub fn clear(paths: &AppPaths) -> Result<()> {
+    let dir = paths.logs_dir();
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let is_log = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("access.") && name.ends_with(".log"));
+        if is_log {
+            let report = crate::wipe::shred_tree(&path);
+            if !report.failed.is_empty() {
+                bail!("Failed to delete {}", report.failed.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The path without its query, token-like segments replaced
+///
+/// `/p/1b2c…/` and `/p/4f5e…/` count as the same route.
+fn route(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    path.split('/')
+        .map(|segment| {
+            if looks_like_token(segment) {
+                ":token"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The path with token-like segments and every query value replaced
+///
+/// Search terms, member and magic-link tokens and unsubscribe keys all
+/// travel in the query.
+fn redact(path: &str) -> String {
+    let path = path.split('#').next().unwrap_or_default();
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let mut redacted = path
+        .split('/')
+        .map(|segment| {
+            if looks_like_token(segment) {
+                REDACTED
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if let Some(query) = query {
+        let params: Vec<String> = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| match param.split_once('=') {
+                Some((name, _)) => format!("{}={}", name, REDACTED),
+                None => REDACTED.to_string(),
+            })
+            .collect();
+        redacted.push('?');
+        redacted.push_str(&params.join("&"));
+    }
+    redacted
+}
+
+/// UUIDs, and long keys that a slug or a file name would not look like
+///
+/// Ghost slugs are lowercase words, base64url keys mix cases and hex or
+/// base32 runs mix letters with digits inside one word.
+fn looks_like_token(segment: &str) -> bool {
+    let is_uuid = segment.len() == 36
+        && segment.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    if is_uuid {
+        return true;
+    }
+    if segment.len() < 16
+        || !segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return false;
+    }
+
+    let mixed_case = segment.chars().any(|c| c.is_ascii_uppercase())
+        && segment.chars().any(|c| c.is_ascii_lowercase());
+    let mixed_word = segment.split(['-', '_']).any(|word| {
+        word.len() >= 8
+            && word.chars().any(|c| c.is_ascii_digit())
+            && word.chars().any(|c| c.is_ascii_alphabetic())
+    });
+    mixed_case || mixed_word
+}
+
+fn current_hour() -> i64 {
+    Utc::now().timestamp().div_euclid(3600)
+}
+
+fn log_file(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("access.{}.log", index))
+}
+
+/// read the log key, creating it first when `create` is set
+fn load_key(path: &Path, create: bool) -> Result<SecretKey> {
+    match fs::read(path) {
+        Ok(raw) => {
+            let bytes: [u8; 32] = raw
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Access log key {:?} is corrupt", path))?;
+            Ok(SecretKey::from_bytes(bytes))
+        }
+        Err(e) if create && e.kind() == io::ErrorKind::NotFound => {
+            let key = SecretKey::random();
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            options
+                .open(path)
+                .and_then(|mut file| file.write_all(key.as_bytes()))
+                .with_context(|| format!("Failed to write {:?}", path))?;
+            Ok(key)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            bail!("The access log key is gone, the logs can no longer be read")
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_told_from_slugs() {
+        for token in [
+            "3f2504e0-4f89-11d3-9a0c-0305e82c3301",
+            "1b2c3d4e5f6a7b8c9d0e",
+            "Xk9-aB3dEf_Gh12-kLmN",
+            "kq8-ZtV2_wXr-0yPs",
+            "a1b2c3d4-e5f6a7b8",
+        ] {
+            assert!(looks_like_token(token), "{}", token);
+        }
+        for slug in [
+            "welcome-to-my-blog",
+            "top-10-tips-for-web3-developers",
+            "2024-05-01-monthly-update",
+            "abcdefghijklmnopqrstuvwxyz",
+            "MyHolidayPhoto2024.jpg",
+            "signatures",
+            "",
+        ] {
+            assert!(!looks_like_token(slug), "{}", slug);
+        }
+    }
+
+    #[test]
+    fn routes_group_tokens_and_drop_the_query() {
+        assert_eq!(
+            route("/p/3f2504e0-4f89-11d3-9a0c-0305e82c3301/?member=1#top"),
+            "/p/:token/"
+        );
+        assert_eq!(
+            route("/unsubscribe/Xk9-aB3dEf_Gh12-kLmN"),
+            "/unsubscribe/:token"
+        );
+        assert_eq!(
+            route("/welcome-to-my-blog/?ref=home"),
+            "/welcome-to-my-blog/"
+        );
+    }
+
+    #[test]
+    fn redact_keeps_query_names_only() {
+        assert_eq!(
+            redact("/search/?q=secret+plans&page=2#results"),
+            "/search/?q=[redacted]&page=[redacted]"
+        );
+        assert_eq!(
+            redact("/members/?token=Xk9-aB3dEf_Gh12-kLmN&flag&"),
+            "/members/?token=[redacted]&[redacted]"
+        );
+        assert_eq!(
+            redact("/r/1b2c3d4e5f6a7b8c9d0e/welcome-to-my-blog/"),
+            "/r/[redacted]/welcome-to-my-blog/"
+        );
+        assert_eq!(redact("/about/"), "/about/");
+    }
+}
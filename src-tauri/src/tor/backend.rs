@@ -0,0 +1,118 @@
+use crate::tor::metrics::ServiceCounters;
+use crate::tor::TorClientManager;
+use anyhow::{Context, Result};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tor_cell::relaycell::msg::Connected;
+use tor_hsservice::config::OnionServiceConfigBuilder;
+use tor_hsservice::{HsNickname, RunningOnionService, StreamRequest};
+use tor_proto::stream::{DataStream, IncomingStreamRequest};
+
+/// What the blog's onion service needs from Tor
+///
+/// [`TorClientManager`] is the real thing, tests put an in-process stand-in
+/// in its place so the proxy and the service can run offline.
+pub trait OnionBackend: Send + Sync + 'static {
+    /// keeps the service published for as long as it is held
+    type Service: Send + Sync + 'static;
+    type Request: OnionStreamRequest;
+
+    /// Publish the service stored under `nickname`
+    ///
+    /// Returns the service, its `.onion` name and the streams visitors open,
+    /// counting each introduction in `counters`.
+    #[allow(clippy::type_complexity)]
+    fn launch(
+        &self,
+        nickname: &str,
+        counters: &Arc<ServiceCounters>,
+    ) -> Result<(Self::Service, String, BoxStream<'static, Self::Request>)>;
+
+    /// what the service reports about itself: running, degraded, ...
+    fn state(service: &Self::Service) -> String;
+
+    /// stop or resume background Tor activity
+    fn set_dormant(&self, dormant: bool);
+}
+
+/// a stream a visitor wants to open to the service
+pub trait OnionStreamRequest: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// port on the `.onion` address the stream is for
+    fn port(&self) -> u16;
+
+    /// accept the stream
+    fn open(self) -> impl Future<Output = Result<Self::Stream>> + Send;
+
+    /// refuse the stream by tearing its circuit down
+    fn reject(self);
+}
+
+impl OnionBackend for TorClientManager {
+    type Service = Arc<RunningOnionService>;
+    type Request = StreamRequest;
+
+    fn launch(
+        &self,
+        nickname: &str,
+        counters: &Arc<ServiceCounters>,
+    ) -> Result<(Self::Service, String, BoxStream<'static, Self::Request>)> {
+        let nickname: HsNickname = nickname
+            .to_string()
+            .try_into()
+            .context("Invalid hidden service nickname")?;
+
+        // configure the hidden service
+        let hs_config = OnionServiceConfigBuilder::default()
+            .nickname(nickname)
+            .build()
+            .context("Failed to build hidden service config")?;
+
+        // launch the onion service (this is NOT async, returns immediately)
+        let (onion_service, rend_requests) = self
+            .client()
+            .launch_onion_service(hs_config)
+            .context("Failed to launch onion service")?;
+
+        let onion_name = onion_service
+            .onion_name()
+            .context("Failed to get onion name")?
+            .to_string();
+
+        let counters = counters.clone();
+        let rend_requests = rend_requests.inspect(move |_| counters.count_rend_request());
+        let requests = tor_hsservice::handle_rend_requests(rend_requests).boxed();
+        Ok((onion_service, onion_name, requests))
+    }
+
+    fn state(service: &Self::Service) -> String {
+        format!("{:?}", service.status().state()).to_lowercase()
+    }
+
+    fn set_dormant(&self, dormant: bool) {
+        TorClientManager::set_dormant(self, dormant)
+    }
+}
+
+impl OnionStreamRequest for StreamRequest {
+    type Stream = DataStream;
+
+    fn port(&self) -> u16 {
+        match self.request() {
+            IncomingStreamRequest::Begin(begin) => begin.port(),
+            _ => 0,
+        }
+    }
+
+    async fn open(self) -> Result<Self::Stream> {
+        Ok(self.accept(Connected::new_empty()).await?)
+    }
+
+    fn reject(self) {
+        let _ = self.shutdown_circuit();
+    }
+}
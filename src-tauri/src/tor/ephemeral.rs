@@ -0,0 +1,186 @@
+use crate::tor::TorClientManager;
+use crate::wipe;
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use rand::RngCore;
+use serde::Serialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+use tor_cell::relaycell::msg::Connected;
+use tor_hsservice::config::OnionServiceConfigBuilder;
+use tor_hsservice::{HsNickname, OnionService, RunningOnionService};
+use tor_keymgr::{ArtiEphemeralKeystore, KeyMgrBuilder};
+use tor_netdir::NetDirProvider;
+use tor_persist::state_dir::StateDirectory;
+use tor_proto::stream::IncomingStreamRequest;
+use tracing::{debug, error, info};
+
+/// what the UI is told about a throwaway onion service
+#[derive(Serialize, Clone, Debug)]
+pub struct EphemeralInfo {
+    pub id: String,
+    /// full `http://<address>.onion[:port]` URL
+    pub onion_url: String,
+    /// local address the onion streams are forwarded to
+    pub target: String,
+    pub onion_port: u16,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+/// A throwaway onion service
+///
+/// Its identity key only ever lives in an in-memory keystore, so once the
+/// service is dropped the address can never be published again. Arti's
+/// per-service state (introduction points, replay logs) is shredded too.
+pub struct EphemeralService {
+    info: EphemeralInfo,
+    _service: Arc<RunningOnionService>,
+    /// accepts streams, and owns the tasks copying them
+    forwarder: tokio::task::JoinHandle<()>,
+    state_dir: PathBuf,
+}
+
+impl EphemeralService {
+    /// Launch a service with fresh keys that forwards `onion_port` to `target`
+    ///
+    /// Streams are forwarded as raw TCP, so anything from a web server to a
+    /// chat client can sit behind the address.
+    pub async fn launch(
+        tor_manager: &TorClientManager,
+        state_root: &Path,
+        target: SocketAddr,
+        onion_port: u16,
+        ttl: Option<Duration>,
+    ) -> Result<Self> {
+        if !target.ip().is_loopback() {
+            bail!("Ephemeral services can only point at this computer (loopback addresses)");
+        }
+
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = hex::encode(id);
+        let nickname: HsNickname = format!("ephemeral_{}", id)
+            .try_into()
+            .context("Invalid hidden service nickname")?;
+
+        // keys are generated into this store on launch and never touch the disk
+        let keymgr = KeyMgrBuilder::default()
+            .primary_store(Box::new(ArtiEphemeralKeystore::new(
+                "ephemeral".to_string(),
+            )))
+            .build()
+            .context("Failed to create the in-memory keystore")?;
+
+        let state_dir = state_root.join(&id);
+        fs::create_dir_all(&state_dir)
+            .with_context(|| format!("Failed to create {:?}", state_dir))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(state_root, fs::Permissions::from_mode(0o700))?;
+            fs::set_permissions(&state_dir, fs::Permissions::from_mode(0o700))?;
+        }
+        let state = StateDirectory::new(&state_dir, &Default::default())
+            .context("Failed to open the ephemeral service state")?;
+
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(nickname)
+            .build()
+            .context("Failed to build hidden service config")?;
+        let client = tor_manager.client();
+        let netdir: Arc<dyn NetDirProvider> = client.dirmgr().clone();
+        let (service, rend_requests) = OnionService::builder()
+            .config(config)
+            .keymgr(Arc::new(keymgr))
+            .state_dir(state)
+            .build()
+            .context("Failed to create the ephemeral service")?
+            .launch(
+                client.runtime().clone(),
+                netdir,
+                client.hs_circ_pool().clone(),
+            )
+            .context("Failed to launch the ephemeral service")?;
+
+        let onion_name = service
+            .onion_name()
+            .context("Failed to get onion name")?
+            .to_string();
+        let onion_url = match onion_port {
+            80 => format!("http://{}", onion_name),
+            port => format!("http://{}:{}", onion_name, port),
+        };
+
+        let forwarder = tokio::spawn(async move {
+            // the copies die with the forwarder, dropping the set aborts them
+            let mut streams = JoinSet::new();
+            let mut stream_requests = Box::pin(tor_hsservice::handle_rend_requests(rend_requests));
+            while let Some(stream_request) = stream_requests.next().await {
+                while streams.try_join_next().is_some() {}
+
+                let port = match stream_request.request() {
+                    IncomingStreamRequest::Begin(begin) => begin.port(),
+                    _ => 0,
+                };
+                if port != onion_port {
+                    debug!("rejecting stream to port {}", port);
+                    let _ = stream_request.shutdown_circuit();
+                    continue;
+                }
+
+                streams.spawn(async move {
+                    let mut local = match tokio::net::TcpStream::connect(target).await {
+                        Ok(local) => local,
+                        Err(e) => {
+                            debug!("ephemeral target unreachable: {}", e);
+                            let _ = stream_request.shutdown_circuit();
+                            return;
+                        }
+                    };
+                    match stream_request.accept(Connected::new_empty()).await {
+                        Ok(mut onion) => {
+                            let _ = tokio::io::copy_bidirectional(&mut onion, &mut local).await;
+                        }
+                        Err(e) => error!("failed to accept onion stream: {}", e),
+                    }
+                });
+            }
+        });
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        info!("ephemeral onion service {} started", id);
+        Ok(Self {
+            info: EphemeralInfo {
+                id,
+                onion_url,
+                target: target.to_string(),
+                onion_port,
+                created_at: now,
+                expires_at: ttl.map(|ttl| now.saturating_add(ttl.as_secs())),
+            },
+            _service: service,
+            forwarder,
+            state_dir,
+        })
+    }
+
+    pub fn info(&self) -> &EphemeralInfo {
+        &self.info
+    }
+}
+
+impl Drop for EphemeralService {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+        wipe::shred_tree(&self.state_dir);
+        info!("ephemeral onion service {} stopped", self.info.id);
+    }
+}
@@ -1,130 +1,266 @@
+use crate::dropbox::{self, DropBoxServer};
+use crate::share::{self, ShareManager};
+use crate::signing::ContentSigner;
+use crate::tor::access_log::AccessLog;
+use crate::tor::backend::{OnionBackend, OnionStreamRequest};
+use crate::tor::ephemeral::{EphemeralInfo, EphemeralService};
+use crate::tor::identity;
+use crate::tor::metrics::{ServiceCounters, ServiceStats};
+use crate::tor::proxy::{self, Upstream};
 use crate::tor::TorClientManager;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tor_hsrproxy::config::{
-    Encapsulation, ProxyAction, ProxyConfigBuilder, ProxyPattern, ProxyRule, TargetAddr,
-};
-use tor_hsrproxy::OnionServiceReverseProxy;
-use tor_hsservice::config::OnionServiceConfigBuilder;
-use tor_hsservice::{HsNickname, RunningOnionService};
-use tracing::{error, info};
-
-/// manages an Arti-based hidden service
-pub struct HiddenServiceManager {
-    onion_service: Option<Arc<RunningOnionService>>,
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// relaunch attempts on resume, 250 ms apart
+const RESUME_ATTEMPTS: u32 = 40;
+
+/// manages the blog's hidden service, on Arti unless a test says otherwise
+pub struct HiddenServiceManager<B: OnionBackend = TorClientManager> {
+    onion_service: Option<B::Service>,
     onion_address: Option<String>,
     proxy_handle: Option<tokio::task::JoinHandle<()>>,
+    tor_manager: Option<Arc<B>>,
+    upstream: Upstream,
+    onion_port: u16,
+    /// what the service was started with, to launch it again on resume
+    routes: Option<Routes>,
+    /// taken offline by [`HiddenServiceManager::pause`], keys kept
+    paused: bool,
+    access_log: Option<Arc<AccessLog>>,
+    /// totals across restarts of the service, see [`HiddenServiceManager::metrics`]
+    counters: Arc<ServiceCounters>,
+    /// throwaway services by id, unrelated to the blog's address
+    ephemeral: Arc<Mutex<HashMap<String, EphemeralService>>>,
+    /// Arti state of the throwaway services, shredded when they stop
+    ephemeral_dir: PathBuf,
+}
+
+/// what the onion service serves besides the blog
+#[derive(Clone, Default)]
+pub struct Routes {
+    /// signed post index under `/.well-known/freedom-kit/`
+    pub signatures: Option<Arc<ContentSigner>>,
+    /// shared files on [`share::SHARE_PORT`]
+    pub shares: Option<Arc<ShareManager>>,
+    /// the drop box on [`dropbox::DROPBOX_PORT`]
+    pub dropbox: Option<Arc<DropBoxServer>>,
 }
 
 /// configuration for hidden service
 #[derive(Clone, Debug)]
-#[allow(dead_code)]
 pub struct HiddenServiceConfig {
     /// Directory to store Tor configuration and state
     pub tor_data_dir: PathBuf,
-    /// local port to forward to (e.g., Ghost port)
-    pub local_port: u16,
+    /// where Ghost listens
+    pub upstream: Upstream,
     /// port to expose on the .onion address
     pub onion_port: u16,
+    /// records requests to the blog, `None` when logging is off
+    pub access_log: Option<Arc<AccessLog>>,
+}
+
+/// Bring Tor up with `connect` and publish the blog on it
+///
+/// `on_client` gets the client before the onion service is launched, so
+/// the webview can browse while the descriptor is still being published.
+/// The launch waits for the future it returns, e.g. until Ghost is up.
+pub async fn publish<B: OnionBackend, Ready: Future<Output = Result<()>>>(
+    connect: impl Future<Output = Result<Arc<B>>>,
+    on_client: impl FnOnce(Arc<B>) -> Ready,
+    config: HiddenServiceConfig,
+    routes: Routes,
+) -> Result<HiddenServiceManager<B>> {
+    let tor_manager = connect.await?;
+    on_client(tor_manager.clone()).await?;
+
+    async {
+        info!("creating hidden service...");
+        let mut hidden_service = HiddenServiceManager::new(config)?;
+        hidden_service.start(tor_manager, routes).await?;
+        Ok(hidden_service)
+    }
+    .instrument(info_span!("hs"))
+    .await
 }
 
-impl HiddenServiceManager {
+impl<B: OnionBackend> HiddenServiceManager<B> {
     /// create a new hidden service manager
-    pub fn new(_config: HiddenServiceConfig) -> Result<Self> {
+    pub fn new(config: HiddenServiceConfig) -> Result<Self> {
+        // left behind if the app did not exit cleanly
+        let ephemeral_dir = config.tor_data_dir.join("ephemeral");
+        if ephemeral_dir.exists() {
+            let report = crate::wipe::shred_tree(&ephemeral_dir);
+            if !report.failed.is_empty() {
+                warn!("could not remove old ephemeral service state");
+            }
+        }
+
         Ok(Self {
             onion_service: None,
             onion_address: None,
             proxy_handle: None,
+            tor_manager: None,
+            upstream: config.upstream,
+            onion_port: config.onion_port,
+            routes: None,
+            paused: false,
+            access_log: config.access_log,
+            counters: Arc::new(ServiceCounters::default()),
+            ephemeral: Arc::new(Mutex::new(HashMap::new())),
+            ephemeral_dir,
         })
     }
 
-    /// start the hidden service using Arti
-    pub async fn start(
-        &mut self,
-        tor_manager: Arc<TorClientManager>,
-        local_proxy_port: u16, // Changed: this is now the LOCAL proxy port
-        onion_port: u16,
-    ) -> Result<()> {
-        info!("starting Arti-based hidden service...");
-
-        // create a unique nickname for this hidden service
-        let nickname: HsNickname = "ghost_freedom_hs"
-            .to_string()
-            .try_into()
-            .context("Invalid hidden service nickname")?;
-
-        // configure the hidden service
-        let hs_config = OnionServiceConfigBuilder::default()
-            .nickname(nickname.clone())
-            .build()
-            .context("Failed to build hidden service config")?;
-
-        // launch the onion service (this is NOT async, returns immediately)
-        let (onion_service, rend_requests) = tor_manager
-            .client()
-            .launch_onion_service(hs_config)
-            .context("Failed to launch onion service")?;
-
-        // get the onion address
-        let onion_name = onion_service
-            .onion_name()
-            .context("Failed to get onion name")?;
-        let onion_address = format!("{}:{}", onion_name, onion_port);
+    /// start the hidden service on `tor_manager`
+    ///
+    /// Onion streams are served in-process and forwarded straight to Ghost,
+    /// there is no local listener between Tor and the proxy. `routes` adds
+    /// the shared files, the drop box and the signatures next to the blog.
+    pub async fn start(&mut self, tor_manager: Arc<B>, routes: Routes) -> Result<()> {
+        info!("starting hidden service...");
 
-        info!("✅ Hidden service established at: {}", onion_address);
-        self.onion_address = Some(onion_address.clone());
-
-        // set up reverse proxy configuration to forward to the LOCAL proxy
-        let local_addr: SocketAddr = format!("127.0.0.1:{}", local_proxy_port)
-            .parse()
-            .context("Invalid local address")?;
-
-        // create a proxy pattern that matches the onion port
-        let pattern =
-            ProxyPattern::one_port(onion_port).context("Failed to create proxy pattern")?;
-
-        // create a proxy rule that forwards to the local proxy
-        let rule = ProxyRule::new(
-            pattern,
-            ProxyAction::Forward(Encapsulation::Simple, TargetAddr::Inet(local_addr)),
-        );
-
-        // build the proxy configuration
-        let mut proxy_config_builder = ProxyConfigBuilder::default();
-        proxy_config_builder.proxy_ports().push(rule);
-        let proxy_config = proxy_config_builder
-            .build()
-            .context("Failed to build proxy config")?;
-
-        // create the reverse proxy
-        let proxy = OnionServiceReverseProxy::new(proxy_config);
-
-        // run the proxy in the background
-        let runtime = tor_rtcompat::tokio::TokioNativeTlsRuntime::current()
-            .context("Failed to get Tokio runtime")?;
-        let proxy_clone = Arc::clone(&proxy);
-
-        let proxy_handle = tokio::spawn(async move {
-            info!("starting reverse proxy for hidden service...");
-            if let Err(e) = proxy_clone
-                .handle_requests(runtime, nickname, rend_requests)
-                .await
-            {
-                error!("reverse proxy error: {}", e);
+        self.tor_manager = Some(tor_manager);
+        self.routes = Some(routes);
+        self.launch_service()?;
+
+        info!("✅ Hidden service and proxy started successfully");
+        Ok(())
+    }
+
+    /// Take the onion service offline and put Tor to sleep
+    ///
+    /// The identity keys stay in the keystore, so [`HiddenServiceManager::resume`]
+    /// brings the same address back. Throwaway services are stopped.
+    pub async fn pause(&mut self) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        let tor_manager = self
+            .tor_manager
+            .clone()
+            .context("The hidden service is not running")?;
+        info!("pausing hidden service...");
+
+        self.ephemeral.lock().unwrap().clear();
+        if let Some(handle) = self.proxy_handle.take() {
+            handle.abort();
+            let _ = handle.await;
+        }
+        // descriptors are no longer republished once the service is dropped
+        self.onion_service = None;
+        self.paused = true;
+        tor_manager.set_dormant(true);
+
+        info!("hidden service paused");
+        Ok(())
+    }
+
+    /// Bring a paused onion service back under the same address
+    pub async fn resume(&mut self) -> Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+        let tor_manager = self
+            .tor_manager
+            .clone()
+            .context("The hidden service is not running")?;
+        info!("resuming hidden service...");
+        tor_manager.set_dormant(false);
+
+        // the old service's tasks let go of its state shortly after it is dropped
+        let mut attempts = 0;
+        loop {
+            match self.launch_service() {
+                Ok(()) => break,
+                Err(e) if attempts < RESUME_ATTEMPTS => {
+                    debug!("hidden service not ready to relaunch yet: {:#}", e);
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                }
+                Err(e) => return Err(e),
             }
-        });
+        }
+        self.paused = false;
+
+        info!("hidden service resumed");
+        Ok(())
+    }
+
+    /// what the onion service has carried, aggregated over all visitors
+    pub fn metrics(&self) -> ServiceStats {
+        let state = match (&self.onion_service, self.paused) {
+            (_, true) => "paused".to_string(),
+            (Some(service), false) => B::state(service),
+            (None, false) => "stopped".to_string(),
+        };
+        ServiceStats::new(&self.counters, state, self.paused)
+    }
+
+    /// whether the service is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// launch the blog's onion service and serve its streams
+    fn launch_service(&mut self) -> Result<()> {
+        let tor_manager = self
+            .tor_manager
+            .clone()
+            .context("The hidden service is not running")?;
+        let routes = self
+            .routes
+            .clone()
+            .context("The hidden service was never started")?;
+
+        let (onion_service, onion_name, requests) =
+            tor_manager.launch(identity::HS_NICKNAME, &self.counters)?;
+        let onion_address = format!("{}:{}", onion_name, self.onion_port);
+
+        info!("✅ Hidden service established at: {}", onion_address);
+        self.onion_address = Some(onion_address);
+
+        // accept streams for the onion port and serve HTTP on them directly
+        let proxy_handle = tokio::spawn(serve_streams::<B>(
+            requests,
+            self.upstream.clone(),
+            self.onion_port,
+            routes,
+            self.access_log.clone(),
+            self.counters.clone(),
+        ));
 
         self.onion_service = Some(onion_service);
         self.proxy_handle = Some(proxy_handle);
-
-        info!("✅ Hidden service and proxy started successfully");
         Ok(())
     }
 
+    /// the throwaway services that are running
+    pub fn ephemeral_services(&self) -> Vec<EphemeralInfo> {
+        let mut services: Vec<_> = self
+            .ephemeral
+            .lock()
+            .unwrap()
+            .values()
+            .map(|service| service.info().clone())
+            .collect();
+        services.sort_by_key(|info| info.created_at);
+        services
+    }
+
+    /// stop a throwaway service, returns whether it was running
+    pub fn stop_ephemeral(&self, id: &str) -> bool {
+        self.ephemeral.lock().unwrap().remove(id).is_some()
+    }
+
     /// get the .onion address (without http://)
-    #[allow(dead_code)]
     pub fn onion_address(&self) -> Option<&str> {
         self.onion_address.as_deref()
     }
@@ -141,11 +277,19 @@ impl HiddenServiceManager {
         })
     }
 
-    /// stop the hidden service
-    #[allow(dead_code)]
+    /// stop the hidden service and every throwaway service
     pub async fn stop(&mut self) -> Result<()> {
         info!("stopping hidden service...");
 
+        self.ephemeral.lock().unwrap().clear();
+        if let Some(tor_manager) = self.tor_manager.take() {
+            if self.paused {
+                tor_manager.set_dormant(false);
+            }
+        }
+        self.routes = None;
+        self.paused = false;
+
         // stop the proxy
         if let Some(handle) = self.proxy_handle.take() {
             handle.abort();
@@ -171,10 +315,138 @@ impl HiddenServiceManager {
     }
 }
 
-impl Drop for HiddenServiceManager {
+impl<B: OnionBackend> Drop for HiddenServiceManager<B> {
     fn drop(&mut self) {
+        self.ephemeral.lock().unwrap().clear();
         if let Some(handle) = self.proxy_handle.take() {
             handle.abort();
         }
     }
 }
+
+impl HiddenServiceManager<TorClientManager> {
+    /// Start a throwaway onion service forwarding `onion_port` to `target`
+    ///
+    /// It gets its own address, unlinked from the blog, and stops after
+    /// `ttl`, when stopped explicitly or when the app exits.
+    pub async fn launch_ephemeral(
+        &self,
+        target: SocketAddr,
+        onion_port: u16,
+        ttl: Option<Duration>,
+    ) -> Result<EphemeralInfo> {
+        let tor_manager = self
+            .tor_manager
+            .as_ref()
+            .context("The hidden service is not running")?;
+        if self.paused {
+            bail!("Publishing is paused");
+        }
+        let service =
+            EphemeralService::launch(tor_manager, &self.ephemeral_dir, target, onion_port, ttl)
+                .await?;
+        let info = service.info().clone();
+        self.ephemeral
+            .lock()
+            .unwrap()
+            .insert(info.id.clone(), service);
+
+        if let Some(ttl) = ttl {
+            let ephemeral = self.ephemeral.clone();
+            let id = info.id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                if ephemeral.lock().unwrap().remove(&id).is_some() {
+                    info!("ephemeral onion service {} expired", id);
+                }
+            });
+        }
+        Ok(info)
+    }
+}
+
+/// Route the streams visitors open to the blog, the shares and the drop box
+async fn serve_streams<B: OnionBackend>(
+    mut requests: BoxStream<'static, B::Request>,
+    upstream: Upstream,
+    onion_port: u16,
+    routes: Routes,
+    access_log: Option<Arc<AccessLog>>,
+    counters: Arc<ServiceCounters>,
+) {
+    info!("starting reverse proxy for hidden service...");
+    let Routes {
+        signatures,
+        shares,
+        dropbox,
+    } = routes;
+    while let Some(stream_request) = requests.next().await {
+        let port = stream_request.port();
+
+        // shared files never go through Ghost
+        if let Some(shares) = shares.as_ref().filter(|_| port == share::SHARE_PORT) {
+            let shares = shares.clone();
+            let counters = counters.shares.clone();
+            tokio::spawn(async move {
+                match stream_request.open().await {
+                    Ok(stream) => share::serve_connection(counters.meter(stream), shares).await,
+                    Err(e) => {
+                        counters.count_failure();
+                        error!("failed to accept onion stream: {:#}", e)
+                    }
+                }
+            });
+            continue;
+        }
+
+        // neither are the drop box uploads
+        if let Some(dropbox) = dropbox.as_ref().filter(|_| port == dropbox::DROPBOX_PORT) {
+            let dropbox = dropbox.clone();
+            let counters = counters.dropbox.clone();
+            tokio::spawn(async move {
+                match stream_request.open().await {
+                    Ok(stream) => {
+                        dropbox::server::serve_connection(counters.meter(stream), dropbox).await
+                    }
+                    Err(e) => {
+                        counters.count_failure();
+                        error!("failed to accept onion stream: {:#}", e)
+                    }
+                }
+            });
+            continue;
+        }
+
+        // like C tor, anything but the published ports tears the circuit down
+        if port != onion_port {
+            debug!("rejecting stream to port {}", port);
+            counters.count_rejected();
+            stream_request.reject();
+            continue;
+        }
+
+        let upstream = upstream.clone();
+        let signatures = signatures.clone();
+        let access_log = access_log.clone();
+        let counters = counters.blog.clone();
+        tokio::spawn(async move {
+            match stream_request.open().await {
+                Ok(stream) => {
+                    proxy::serve_connection(
+                        counters.meter(stream),
+                        upstream,
+                        signatures,
+                        access_log,
+                        None,
+                    )
+                    .await
+                }
+                Err(e) => {
+                    counters.count_failure();
+                    error!("failed to accept onion stream: {:#}", e)
+                }
+            }
+        });
+    }
+    info!("hidden service stopped accepting requests");
+}
@@ -0,0 +1,208 @@
+use crate::wipe;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use tor_hscrypto::pk::{HsId, HsIdKey, HsIdKeypair};
+use tor_hsservice::{HsIdKeypairSpecifier, HsNickname};
+use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder};
+use tor_llcrypto::pk::ed25519::{ExpandedKeypair, Signature, Verifier};
+use tracing::info;
+
+/// nickname of the blog's onion service, names its keys in the keystore
+pub const HS_NICKNAME: &str = "ghost_freedom_hs";
+
+/// prefix of everything we sign, so a signature can never be mistaken for
+/// one Tor itself makes with the identity key
+pub const SIGNATURE_CONTEXT: &str = "freedom-kit signed content v1\n";
+
+/// the onion service's long-term identity key
+///
+/// This is the key the .onion address is derived from, so anyone who
+/// knows the address can check a signature without any other key exchange.
+pub struct OnionIdentity {
+    keypair: HsIdKeypair,
+}
+
+impl OnionIdentity {
+    /// load the identity key Arti created for the blog's onion service
+    pub fn load(tor_dir: &Path) -> Result<Self> {
+        let keystore_dir = tor_dir.join("keystore");
+        if !keystore_dir.exists() {
+            bail!("No onion service keys yet, start TOR once to create them");
+        }
+
+        let keystore =
+            ArtiNativeKeystore::from_path_and_mistrust(&keystore_dir, &Default::default())
+                .with_context(|| format!("Failed to open keystore {:?}", keystore_dir))?;
+        let keymgr = KeyMgrBuilder::default()
+            .primary_store(Box::new(keystore))
+            .build()
+            .context("Failed to open keystore")?;
+
+        let nickname = HsNickname::from_str(HS_NICKNAME)?;
+        let keypair = keymgr
+            .get::<HsIdKeypair>(&HsIdKeypairSpecifier::new(nickname))
+            .context("Failed to read the onion service identity key")?
+            .context("No onion service identity key yet, start TOR once to create it")?;
+        Ok(Self { keypair })
+    }
+
+    /// a fresh identity that is never written anywhere
+    #[cfg(test)]
+    pub fn generate() -> Self {
+        use rand::RngCore;
+        use tor_llcrypto::pk::ed25519::Keypair;
+
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut seed);
+        let keypair = ExpandedKeypair::from(&Keypair::from_bytes(&seed));
+        Self {
+            keypair: HsIdKeypair::from(keypair),
+        }
+    }
+
+    /// the `.onion` address this key belongs to
+    pub fn onion_address(&self) -> String {
+        HsId::from(HsIdKey::from(&self.keypair)).to_string()
+    }
+
+    /// sign `message`, returns the hex encoded signature
+    pub fn sign(&self, message: &[u8]) -> String {
+        let keypair: &ExpandedKeypair = self.keypair.as_ref();
+        let signature = keypair.sign(&[SIGNATURE_CONTEXT.as_bytes(), message].concat());
+        hex::encode(signature.to_bytes())
+    }
+}
+
+/// Check a signature made by [`OnionIdentity::sign`]
+///
+/// The public key is taken from the v3 onion address itself.
+pub fn verify(onion_address: &str, message: &[u8], signature: &str) -> Result<()> {
+    let address = onion_address
+        .trim()
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    let address = address.split(':').next().unwrap_or_default();
+    let hs_id = HsId::from_str(address).context("Not a v3 onion address")?;
+    let key = HsIdKey::try_from(hs_id).context("Onion address holds no valid key")?;
+
+    let bytes: [u8; 64] = hex::decode(signature.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .context("Malformed signature")?;
+    key.verify(
+        &[SIGNATURE_CONTEXT.as_bytes(), message].concat(),
+        &Signature::from_bytes(&bytes),
+    )
+    .context("Signature does not match the onion address")?;
+    Ok(())
+}
+
+/// Move the keystore from the Tor data dir `from` into `to`
+///
+/// Without it a new data dir means a new .onion address. When `to` holds
+/// keys of its own neither set is dropped, the user has to pick one.
+pub fn move_keystore(from: &Path, to: &Path) -> Result<()> {
+    let (old, new) = (from.join("keystore"), to.join("keystore"));
+    if old == new || !old.exists() {
+        return Ok(());
+    }
+    if new.exists() {
+        bail!(
+            "Both {:?} and {:?} hold onion service keys, remove the ones that should not be used",
+            from,
+            to
+        );
+    }
+
+    info!("moving the onion service keys to the new tor data directory");
+    fs::create_dir_all(to).with_context(|| format!("Failed to create {:?}", to))?;
+    if fs::rename(&old, &new).is_ok() {
+        return Ok(());
+    }
+    // another file system
+    if let Err(e) = copy_tree(&old, &new) {
+        wipe::shred_tree(&new);
+        return Err(e).context("Failed to copy the onion service keys");
+    }
+    wipe::shred_tree(&old);
+    Ok(())
+}
+
+/// copy a directory, permissions included since Arti checks them
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_verify_with_the_address_alone() {
+        let identity = OnionIdentity::generate();
+        let address = identity.onion_address();
+        assert!(address.ends_with(".onion"));
+        let signature = identity.sign(b"post");
+
+        verify(&address, b"post", &signature).unwrap();
+        // as it is often copied out of a browser
+        verify(&format!(" http://{}:80/\n", address), b"post", &signature).unwrap();
+    }
+
+    #[test]
+    fn bad_signatures_are_refused() {
+        let identity = OnionIdentity::generate();
+        let address = identity.onion_address();
+        let signature = identity.sign(b"post");
+
+        let err = verify(&address, b"p0st", &signature).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Signature does not match the onion address"
+        );
+        let other = OnionIdentity::generate().onion_address();
+        assert!(verify(&other, b"post", &signature).is_err());
+        // a plain ed25519 signature without our context is not accepted
+        let keypair: &ExpandedKeypair = identity.keypair.as_ref();
+        let bare = hex::encode(keypair.sign(b"post").to_bytes());
+        assert!(verify(&address, b"post", &bare).is_err());
+
+        let err = verify(&address, b"post", "abcd").unwrap_err();
+        assert_eq!(err.to_string(), "Malformed signature");
+        let err = verify("example.com", b"post", &signature).unwrap_err();
+        assert_eq!(err.to_string(), "Not a v3 onion address");
+    }
+
+    #[test]
+    fn keystore_moves_to_the_new_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("old"), dir.path().join("new"));
+        fs::create_dir_all(from.join("keystore/hs")).unwrap();
+        fs::write(from.join("keystore/hs/key"), "secret").unwrap();
+
+        move_keystore(&from, &to).unwrap();
+        assert_eq!(
+            fs::read_to_string(to.join("keystore/hs/key")).unwrap(),
+            "secret"
+        );
+        assert!(!from.join("keystore").exists());
+
+        // two sets of keys, neither is dropped
+        fs::create_dir_all(from.join("keystore")).unwrap();
+        assert!(move_keystore(&from, &to).is_err());
+        assert!(to.join("keystore/hs/key").exists());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::tor::Purpose;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// how often the UI is sent fresh metrics
+pub const EVENT_INTERVAL_SECS: u64 = 10;
+
+/// Totals for one kind of stream since the app started
+///
+/// Only sums are kept: nothing ties a count to a visitor, a destination or
+/// the time of a request.
+#[derive(Default)]
+pub struct StreamCounters {
+    opened: AtomicU64,
+    failed: AtomicU64,
+    open: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+/// snapshot of [`StreamCounters`]
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StreamStats {
+    pub opened: u64,
+    /// streams refused or that could not be set up
+    pub failed: u64,
+    /// streams open right now
+    pub open: u64,
+    /// bytes received from the other end
+    pub bytes_in: u64,
+    /// bytes sent to the other end
+    pub bytes_out: u64,
+}
+
+impl StreamCounters {
+    /// count `stream` as opened and its traffic from now on
+    pub fn meter<S>(self: &Arc<Self>, stream: S) -> Metered<S> {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.open.fetch_add(1, Ordering::Relaxed);
+        Metered {
+            inner: stream,
+            counters: self.clone(),
+        }
+    }
+
+    pub fn count_failure(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StreamStats {
+        StreamStats {
+            opened: self.opened.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            open: self.open.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// a stream whose traffic is added to its [`StreamCounters`]
+pub struct Metered<S> {
+    inner: S,
+    counters: Arc<StreamCounters>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.counters
+            .bytes_in
+            .fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &result {
+            self.counters
+                .bytes_out
+                .fetch_add(*written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S> Drop for Metered<S> {
+    fn drop(&mut self) {
+        self.counters.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// what the onion service has seen since the app started
+#[derive(Default)]
+pub struct ServiceCounters {
+    /// introductions that reached us, before any stream is opened
+    pub rend_requests: AtomicU64,
+    /// streams to a port nothing is published on
+    pub rejected_streams: AtomicU64,
+    pub blog: Arc<StreamCounters>,
+    pub shares: Arc<StreamCounters>,
+    pub dropbox: Arc<StreamCounters>,
+}
+
+impl ServiceCounters {
+    pub fn count_rend_request(&self) {
+        self.rend_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_rejected(&self) {
+        self.rejected_streams.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// the blog's onion service
+#[derive(Serialize, Clone, Debug)]
+pub struct ServiceStats {
+    /// Arti's view of the service: running, degraded, bootstrapping, ...
+    pub state: String,
+    pub paused: bool,
+    pub rend_requests: u64,
+    pub rejected_streams: u64,
+    pub blog: StreamStats,
+    pub shares: StreamStats,
+    pub dropbox: StreamStats,
+}
+
+impl ServiceStats {
+    pub fn new(counters: &ServiceCounters, state: String, paused: bool) -> Self {
+        Self {
+            state,
+            paused,
+            rend_requests: counters.rend_requests.load(Ordering::Relaxed),
+            rejected_streams: counters.rejected_streams.load(Ordering::Relaxed),
+            blog: counters.blog.snapshot(),
+            shares: counters.shares.snapshot(),
+            dropbox: counters.dropbox.snapshot(),
+        }
+    }
+}
+
+/// The Tor client
+///
+/// Arti does not expose its circuits, the streams opened per purpose and
+/// their traffic stand in for them.
+#[derive(Serialize, Clone, Debug)]
+pub struct ClientStats {
+    pub bootstrap_percent: u8,
+    pub ready_for_traffic: bool,
+    /// why bootstrapping is stuck, e.g. a blocked network
+    pub blocked: Option<String>,
+    pub dormant: bool,
+    pub streams: BTreeMap<Purpose, StreamStats>,
+}
+
+/// everything the dashboard shows, `None` parts are not running
+#[derive(Serialize, Clone, Debug)]
+pub struct TorMetrics {
+    pub client: Option<ClientStats>,
+    pub service: Option<ServiceStats>,
+}
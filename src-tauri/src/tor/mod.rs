@@ -1,6 +1,13 @@
+pub mod access_log;
+pub mod backend;
+pub mod ephemeral;
 pub mod hidden_service;
+pub mod identity;
+pub mod metrics;
 pub mod proxy;
+pub mod socks;
 pub mod tor_client;
 
 pub use hidden_service::{HiddenServiceConfig, HiddenServiceManager};
-pub use tor_client::TorClientManager;
+pub use metrics::TorMetrics;
+pub use tor_client::{Purpose, ReconfigureReport, TorClientManager};
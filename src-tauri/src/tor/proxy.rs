@@ -1,37 +1,117 @@
+use crate::ghost::AdminSession;
+use crate::signing::{ContentSigner, WELL_KNOWN_PREFIX};
+use crate::tor::access_log::{AccessLog, Source};
 use anyhow::{Context, Result};
-use hyper::body::Incoming;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
 use std::convert::Infallible;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tracing::{error, info};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
 
-/// Start a local HTTP reverse proxy that forwards to Ghost
-/// Returns the local port it's listening on and a handle to the background task
-pub async fn start_local_proxy(ghost_port: u16) -> Result<(u16, tokio::task::JoinHandle<()>)> {
-    // Bind to a random available port
-    let listener = TcpListener::bind("127.0.0.1:0")
+/// where proxied requests are sent
+#[derive(Clone, Debug)]
+pub enum Upstream {
+    /// Ghost listening on a loopback TCP port
+    Tcp(u16),
+    /// Ghost listening on a Unix domain socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Content-Security-Policy added to webview responses when third-party
+/// requests have to be blocked, everything outside Ghost's origin is refused
+const LOCKDOWN_CSP: &str =
+    "default-src 'self' 'unsafe-inline' 'unsafe-eval' data: blob:; form-action 'self'";
+
+/// restrictions on traffic from the app webview
+#[derive(Clone, Debug)]
+pub struct WebviewPolicy {
+    /// Host headers that are forwarded, anything else is refused
+    allowed_hosts: Arc<[String]>,
+    /// add [`LOCKDOWN_CSP`] to every response
+    lockdown: bool,
+    /// gets every post the admin publishes before Ghost does
+    publish: Option<PublishHook>,
+    /// learns the admin's session from the requests
+    session: Option<AdminSession>,
+}
+
+/// hands publish requests over before they are forwarded to Ghost
+#[derive(Clone, Debug)]
+pub struct PublishHook {
+    pub requests: mpsc::UnboundedSender<Publish>,
+    /// how long a publish waits for its replacement body
+    pub timeout: Duration,
+}
+
+/// a publish request held back until its post is localized
+#[derive(Debug)]
+pub struct Publish {
+    pub post_id: String,
+    /// `posts` or `pages`
+    pub kind: &'static str,
+    pub body: Bytes,
+    /// takes the body to forward instead, dropping it forwards the original
+    pub replace: oneshot::Sender<Bytes>,
+    /// resolves once Ghost has accepted the request
+    pub saved: oneshot::Receiver<()>,
+}
+
+/// Start the HTTP proxy the webview uses to reach Ghost
+///
+/// Binds `port` on 127.0.0.1 and only forwards requests whose Host header
+/// names that port on localhost, so a web page that rebinds its own domain
+/// to 127.0.0.1 cannot reach Ghost through it. With `lockdown` the pages
+/// it serves may not load anything from other origins, for webviews that
+/// cannot be routed through Tor. Posts published through it go to
+/// `publish` first, and the admin's session is kept in `session`.
+pub async fn start_local_proxy(
+    port: u16,
+    upstream: Upstream,
+    lockdown: bool,
+    publish: Option<PublishHook>,
+    session: Option<AdminSession>,
+    signatures: Option<Arc<ContentSigner>>,
+    access_log: Option<Arc<AccessLog>>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
         .await
         .context("Failed to bind proxy listener")?;
 
     let local_addr = listener.local_addr()?;
     info!("Local proxy listening on {}", local_addr);
 
+    let policy = WebviewPolicy {
+        allowed_hosts: Arc::from(vec![
+            format!("localhost:{}", port),
+            format!("127.0.0.1:{}", port),
+        ]),
+        lockdown,
+        publish,
+        session,
+    };
+
     let handle = tokio::spawn(async move {
         loop {
             match listener.accept().await {
                 Ok((stream, _)) => {
-                    let io = TokioIo::new(stream);
-
-                    tokio::spawn(async move {
-                        let service = service_fn(move |req| proxy_request(req, ghost_port));
-
-                        if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
-                            error!("Error serving connection: {}", e);
-                        }
-                    });
+                    tokio::spawn(serve_connection(
+                        stream,
+                        upstream.clone(),
+                        signatures.clone(),
+                        access_log.clone(),
+                        Some(policy.clone()),
+                    ));
                 }
                 Err(e) => {
                     error!("Failed to accept connection: {}", e);
@@ -40,37 +120,204 @@ pub async fn start_local_proxy(ghost_port: u16) -> Result<(u16, tokio::task::Joi
         }
     });
 
-    Ok((local_addr.port(), handle))
+    Ok(handle)
+}
+
+/// Serve HTTP on an accepted connection (a webview socket or an onion stream)
+///
+/// `signatures` answers the `/.well-known/freedom-kit/` paths instead of
+/// Ghost, `access_log` records requests as its mode allows. `policy`
+/// applies to webview connections, onion streams pass `None`.
+pub async fn serve_connection<S>(
+    io: S,
+    upstream: Upstream,
+    signatures: Option<Arc<ContentSigner>>,
+    access_log: Option<Arc<AccessLog>>,
+    policy: Option<WebviewPolicy>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    let service = service_fn(move |req| {
+        proxy_request(
+            req,
+            upstream.clone(),
+            signatures.clone(),
+            access_log.clone(),
+            policy.clone(),
+        )
+    });
+
+    if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
+        error!("Error serving connection: {}", e);
+    }
 }
 
 async fn proxy_request(
     req: Request<Incoming>,
+    upstream: Upstream,
+    signatures: Option<Arc<ContentSigner>>,
+    access_log: Option<Arc<AccessLog>>,
+    policy: Option<WebviewPolicy>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let Some(access_log) = access_log else {
+        return handle_request(req, upstream, signatures, policy).await;
+    };
+
+    let source = if policy.is_some() {
+        Source::Webview
+    } else {
+        Source::Onion
+    };
+    let method = req.method().clone();
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|x| x.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let response = handle_request(req, upstream, signatures, policy).await?;
+    access_log.record(source, &method, &path, response.status().as_u16());
+    Ok(response)
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    upstream: Upstream,
+    signatures: Option<Arc<ContentSigner>>,
+    policy: Option<WebviewPolicy>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if let Some(policy) = &policy {
+        let host = req
+            .headers()
+            .get(hyper::header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        if !policy
+            .allowed_hosts
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(host))
+        {
+            warn!("Refusing request for unexpected host {:?}", host);
+            return Ok(error_response(421, "Misdirected request".to_string()));
+        }
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|x| x.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+
+    if let Some(signer) = signatures.filter(|_| path.starts_with(WELL_KNOWN_PREFIX)) {
+        return Ok(match signer.serve(&path).await {
+            Ok(Some((content_type, body))) => Response::builder()
+                .header(hyper::header::CONTENT_TYPE, content_type)
+                .body(Full::new(Bytes::from(body)))
+                .unwrap(),
+            Ok(None) => error_response(404, "Not found".to_string()),
+            Err(e) => {
+                error!("Failed to serve signatures: {:#}", e);
+                error_response(503, "Signatures are not available yet".to_string())
+            }
+        });
+    }
+
+    if let Some(session) = policy.as_ref().and_then(|p| p.session.as_ref()) {
+        if let Some(cookie) = req
+            .headers()
+            .get(hyper::header::COOKIE)
+            .and_then(|c| c.to_str().ok())
+        {
+            session.observe(cookie);
+        }
+    }
+
+    let (parts, body) = req.into_parts();
+    let mut body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return Ok(error_response(400, format!("Bad request: {}", e)));
+        }
+    };
+
+    let mut saved = None;
+    let hook = policy.as_ref().and_then(|p| p.publish.as_ref());
+    if let Some(((post_id, kind), hook)) = published_post(&parts.method, &path, &body).zip(hook) {
+        let (replace, replaced) = oneshot::channel();
+        let (done, wait) = oneshot::channel();
+        let publish = Publish {
+            post_id,
+            kind,
+            body: body.clone(),
+            replace,
+            saved: wait,
+        };
+        if hook.requests.send(publish).is_ok() {
+            match tokio::time::timeout(hook.timeout, replaced).await {
+                Ok(Ok(replacement)) => {
+                    body = replacement;
+                    saved = Some(done);
+                }
+                Ok(Err(_)) => {}
+                Err(_) => {
+                    warn!("publishing without waiting any longer for the post to be localized")
+                }
+            }
+        }
+    }
+
+    let result = match &upstream {
+        Upstream::Tcp(port) => forward_tcp(*port, &path, parts, body).await,
+        #[cfg(unix)]
+        Upstream::Unix(socket) => forward_unix(socket, &path, parts, body).await,
+    };
+
+    if let (Some(saved), Ok(response)) = (saved, &result) {
+        if response.status().is_success() {
+            let _ = saved.send(());
+        }
+    }
+
+    let mut response = result.unwrap_or_else(|e| {
+        error!("Failed to proxy request: {:#}", e);
+        error_response(502, format!("Proxy error: {}", e))
+    });
+
+    // browsers enforce every CSP header they get, Ghost's own stay in effect
+    if policy.is_some_and(|p| p.lockdown) {
+        response.headers_mut().append(
+            hyper::header::CONTENT_SECURITY_POLICY,
+            hyper::header::HeaderValue::from_static(LOCKDOWN_CSP),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Forward to Ghost on a loopback port
+async fn forward_tcp(
     ghost_port: u16,
-) -> Result<Response<String>, Infallible> {
+    path: &str,
+    parts: hyper::http::request::Parts,
+    body: Bytes,
+) -> Result<Response<Full<Bytes>>> {
     // Build the Ghost URL
-    let ghost_url = format!(
-        "http://127.0.0.1:{}{}",
-        ghost_port,
-        req.uri()
-            .path_and_query()
-            .map(|x| x.as_str())
-            .unwrap_or("/")
-    );
-
-    info!("Proxying {} to {}", req.uri(), ghost_url);
+    let ghost_url = format!("http://127.0.0.1:{}{}", ghost_port, path);
 
     // Forward the request to Ghost with automatic decompression support
     let client = reqwest::Client::builder()
         .gzip(true) // Enable automatic gzip decompression
         .brotli(true) // Enable automatic brotli decompression
         .deflate(true) // Enable automatic deflate decompression
+        .redirect(reqwest::redirect::Policy::none()) // Ghost's redirects go back to the browser
         .build()
         .unwrap_or_else(|_| reqwest::Client::new());
 
-    let mut ghost_req = client.request(req.method().clone(), &ghost_url);
+    let mut ghost_req = client.request(parts.method.clone(), &ghost_url).body(body);
 
     // Copy headers (but not Content-Encoding related ones)
-    for (name, value) in req.headers() {
+    for (name, value) in parts.headers.iter() {
         let name_lower = name.as_str().to_lowercase();
         // Skip headers that will be recalculated or handled by reqwest
         if name_lower != "content-encoding" && name_lower != "content-length" {
@@ -81,39 +328,107 @@ async fn proxy_request(
     }
 
     // Send request and get response
-    match ghost_req.send().await {
-        Ok(resp) => {
-            let status = resp.status();
-            let headers: reqwest::header::HeaderMap = resp.headers().clone();
-
-            // Get the body (already decompressed by reqwest)
-            let body = resp.text().await.unwrap_or_default();
-
-            let mut response_builder: hyper::http::response::Builder =
-                Response::builder().status(status);
-
-            // Forward response headers (excluding compression-related headers)
-            for (name, value) in headers.iter() {
-                let name_lower: String = name.as_str().to_lowercase();
-                // Skip Content-Encoding and Content-Length since body is already decompressed
-                if name_lower != "content-encoding" && name_lower != "content-length" {
-                    if let Ok(value_str) = value.to_str() {
-                        response_builder = response_builder.header(name.as_str(), value_str);
-                    }
-                }
-            }
+    let resp = ghost_req.send().await?;
+    let status = resp.status();
+    let headers: reqwest::header::HeaderMap = resp.headers().clone();
+
+    // Get the body (already decompressed by reqwest)
+    let body = resp.bytes().await?;
 
-            let response = response_builder.body(body).unwrap();
+    let mut response_builder: hyper::http::response::Builder = Response::builder().status(status);
 
-            Ok(response)
+    // Forward response headers (excluding compression-related headers)
+    for (name, value) in headers.iter() {
+        if forward_response_header(name) {
+            response_builder = response_builder.header(name, value);
         }
-        Err(e) => {
-            error!("Failed to proxy request: {}", e);
-            let response = Response::builder()
-                .status(502)
-                .body(format!("Proxy error: {}", e))
-                .unwrap();
-            Ok(response)
+    }
+
+    Ok(response_builder.body(Full::new(body))?)
+}
+
+/// Forward to Ghost on a Unix domain socket, one connection per request
+#[cfg(unix)]
+async fn forward_unix(
+    socket: &std::path::Path,
+    path: &str,
+    parts: hyper::http::request::Parts,
+    body: Bytes,
+) -> Result<Response<Full<Bytes>>> {
+    let stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("Failed to connect to {:?}", socket))?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Ghost connection error: {}", e);
+        }
+    });
+
+    let mut ghost_req = Request::builder().method(parts.method).uri(path);
+    for (name, value) in parts.headers.iter() {
+        // Ghost answers uncompressed, matching what the TCP path hands back
+        if name != hyper::header::ACCEPT_ENCODING && name != hyper::header::CONTENT_LENGTH {
+            ghost_req = ghost_req.header(name, value);
         }
     }
+
+    let resp = sender
+        .send_request(ghost_req.body(Full::new(body))?)
+        .await?;
+    let (resp_parts, resp_body) = resp.into_parts();
+    let body = resp_body.collect().await?.to_bytes();
+
+    let mut response_builder = Response::builder().status(resp_parts.status);
+    for (name, value) in resp_parts.headers.iter() {
+        if forward_response_header(name) {
+            response_builder = response_builder.header(name, value);
+        }
+    }
+
+    Ok(response_builder.body(Full::new(body))?)
+}
+
+/// Id and kind of the post or page an admin API request publishes, if it does
+///
+/// Ghost publishes with `PUT /ghost/api/admin/{posts,pages}/{id}/` carrying
+/// the new status.
+fn published_post(
+    method: &hyper::Method,
+    path: &str,
+    body: &Bytes,
+) -> Option<(String, &'static str)> {
+    if method != hyper::Method::PUT {
+        return None;
+    }
+    let path = path.split('?').next().unwrap_or_default();
+    let (kind, rest) = ["posts", "pages"].into_iter().find_map(|kind| {
+        let rest = path.strip_prefix(&format!("/ghost/api/admin/{}/", kind))?;
+        Some((kind, rest))
+    })?;
+    let id = rest.trim_end_matches('/');
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let body: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let status = body
+        .pointer(&format!("/{}/0/status", kind))
+        .and_then(|s| s.as_str())?;
+    matches!(status, "published" | "scheduled").then(|| (id.to_string(), kind))
+}
+
+/// Skip headers that no longer describe the buffered, decoded body
+fn forward_response_header(name: &hyper::header::HeaderName) -> bool {
+    name != hyper::header::CONTENT_ENCODING
+        && name != hyper::header::CONTENT_LENGTH
+        && name != hyper::header::TRANSFER_ENCODING
+}
+
+fn error_response(status: u16, message: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(Bytes::from(message)))
+        .unwrap()
 }
@@ -0,0 +1,412 @@
+use crate::tor::{Purpose, TorClientManager};
+use anyhow::{bail, Context, Result};
+use rand::RngCore;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+const SOCKS_VERSION: u8 = 5;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+/// version of the username/password subnegotiation (RFC 1929)
+const AUTH_VERSION: u8 = 1;
+const AUTH_SUCCEEDED: u8 = 0;
+const AUTH_FAILED: u8 = 1;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_GENERAL_FAILURE: u8 = 1;
+const REPLY_HOST_UNREACHABLE: u8 = 4;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// how long a connection waits for Tor to finish bootstrapping
+const BOOTSTRAP_WAIT: Duration = Duration::from_secs(120);
+
+/// the Tor client, `None` until it has bootstrapped
+pub type TorClientWatch = watch::Receiver<Option<Arc<TorClientManager>>>;
+
+/// username and password a SOCKS listener requires
+///
+/// Like Tor's IsolateSOCKSAuth: made fresh for every run and only given to
+/// the listener's consumer, so no other program on this computer can use
+/// the listener to reach Tor or to share the consumer's circuits.
+#[derive(Clone)]
+pub struct SocksAuth {
+    username: String,
+    password: String,
+}
+
+impl SocksAuth {
+    pub fn random() -> Self {
+        let token = || {
+            let mut bytes = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            hex::encode(bytes)
+        };
+        Self {
+            username: token(),
+            password: token(),
+        }
+    }
+
+    /// `socks5://` URL of a listener on `port` with the credentials in it
+    pub fn proxy_url(&self, port: u16) -> String {
+        format!(
+            "socks5://{}:{}@127.0.0.1:{}",
+            self.username, self.password, port
+        )
+    }
+
+    /// compares every byte, so the time taken does not give a prefix away
+    fn matches(&self, username: &[u8], password: &[u8]) -> bool {
+        let expected = [self.username.as_bytes(), self.password.as_bytes()];
+        [username, password]
+            .iter()
+            .zip(expected)
+            .fold(true, |ok, (given, expected)| {
+                let same = given.len() == expected.len()
+                    && given
+                        .iter()
+                        .zip(expected)
+                        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                        == 0;
+                ok & same
+            })
+    }
+}
+
+/// Start a SOCKS5 proxy on 127.0.0.1 that sends everything through Tor
+///
+/// Loopback destinations (Ghost, the dev server) are connected directly,
+/// everything else goes over Tor and fails rather than leaking when Tor is
+/// not available. Each listener is isolated by its `purpose`, so give every
+/// consumer its own. Clients have to log in with `auth`. Returns the port
+/// it listens on.
+pub async fn start_socks_proxy(
+    tor: TorClientWatch,
+    purpose: Purpose,
+    auth: SocksAuth,
+) -> Result<(u16, tokio::task::JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to bind SOCKS listener")?;
+    let port = listener.local_addr()?.port();
+    info!(
+        "socks proxy for {:?} listening on 127.0.0.1:{}",
+        purpose, port
+    );
+
+    let handle = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let tor = tor.clone();
+                    let auth = auth.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(stream, tor, purpose, &auth).await {
+                            debug!("socks connection failed: {:#}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("failed to accept socks connection: {}", e),
+            }
+        }
+    });
+
+    Ok((port, handle))
+}
+
+/// where the client wants to go
+#[derive(Debug)]
+enum Target {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+impl Target {
+    fn is_loopback(&self) -> bool {
+        match self {
+            Target::Ip(ip) => ip.is_loopback(),
+            Target::Domain(name) => {
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                name == "localhost" || name.ends_with(".localhost")
+            }
+        }
+    }
+}
+
+async fn handle_client(
+    mut client: TcpStream,
+    tor: TorClientWatch,
+    purpose: Purpose,
+    auth: &SocksAuth,
+) -> Result<()> {
+    negotiate_auth(&mut client, auth).await?;
+
+    let (target, port) = match read_request(&mut client).await {
+        Ok(request) => request,
+        Err(RequestError::Reply(code, e)) => {
+            reply(&mut client, code).await?;
+            return Err(e);
+        }
+    };
+
+    if target.is_loopback() {
+        // resolving `localhost` here never touches the network
+        let address = match &target {
+            Target::Ip(ip) => (*ip, port).into(),
+            Target::Domain(_) => (Ipv4Addr::LOCALHOST, port).into(),
+        };
+        let mut upstream = match TcpStream::connect::<std::net::SocketAddr>(address).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                reply(&mut client, REPLY_HOST_UNREACHABLE).await?;
+                bail!("local connection to {} failed: {}", address, e);
+            }
+        };
+        reply(&mut client, REPLY_SUCCEEDED).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        return Ok(());
+    }
+
+    let Some(tor) = wait_for_tor(tor).await else {
+        reply(&mut client, REPLY_GENERAL_FAILURE).await?;
+        bail!("tor is not available, refusing {:?}:{}", target, port);
+    };
+
+    let host = match &target {
+        Target::Ip(ip) => ip.to_string(),
+        Target::Domain(name) => name.clone(),
+    };
+    let mut upstream = match tor.connect(purpose, &host, port).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            reply(&mut client, REPLY_HOST_UNREACHABLE).await?;
+            bail!("tor connection to {}:{} failed: {}", host, port, e);
+        }
+    };
+    reply(&mut client, REPLY_SUCCEEDED).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+/// wait until Tor has bootstrapped, `None` on timeout or shutdown
+pub async fn wait_for_tor(mut tor: TorClientWatch) -> Option<Arc<TorClientManager>> {
+    let ready = tokio::time::timeout(BOOTSTRAP_WAIT, tor.wait_for(|client| client.is_some()))
+        .await
+        .ok()?
+        .ok()?;
+    ready.clone()
+}
+
+async fn negotiate_auth<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: &SocksAuth,
+) -> Result<()> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        bail!("unsupported socks version {}", header[0]);
+    }
+
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&USERNAME_PASSWORD) {
+        stream
+            .write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHOD])
+            .await?;
+        bail!("client offered no username and password");
+    }
+    stream
+        .write_all(&[SOCKS_VERSION, USERNAME_PASSWORD])
+        .await?;
+
+    if stream.read_u8().await? != AUTH_VERSION {
+        bail!("unsupported socks auth version");
+    }
+    let mut username = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut username).await?;
+    let mut password = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut password).await?;
+
+    if !auth.matches(&username, &password) {
+        stream.write_all(&[AUTH_VERSION, AUTH_FAILED]).await?;
+        bail!("client sent the wrong username or password");
+    }
+    stream.write_all(&[AUTH_VERSION, AUTH_SUCCEEDED]).await?;
+    Ok(())
+}
+
+enum RequestError {
+    /// tell the client with this reply code, then give up
+    Reply(u8, anyhow::Error),
+}
+
+impl From<std::io::Error> for RequestError {
+    fn from(e: std::io::Error) -> Self {
+        RequestError::Reply(REPLY_GENERAL_FAILURE, e.into())
+    }
+}
+
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(Target, u16), RequestError> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _, address_type] = header;
+    if version != SOCKS_VERSION {
+        return Err(RequestError::Reply(
+            REPLY_GENERAL_FAILURE,
+            anyhow::anyhow!("unsupported socks version {}", version),
+        ));
+    }
+
+    let target = match address_type {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Target::Ip(Ipv4Addr::from(ip).into())
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Target::Ip(Ipv6Addr::from(ip).into())
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await?;
+            let name = String::from_utf8(name)
+                .map_err(|e| RequestError::Reply(REPLY_ADDRESS_NOT_SUPPORTED, e.into()))?;
+            Target::Domain(name)
+        }
+        other => {
+            return Err(RequestError::Reply(
+                REPLY_ADDRESS_NOT_SUPPORTED,
+                anyhow::anyhow!("unknown address type {}", other),
+            ))
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    // UDP and BIND would bypass Tor's stream model
+    if command != CMD_CONNECT {
+        warn!("refusing socks command {} to {:?}", command, target);
+        return Err(RequestError::Reply(
+            REPLY_COMMAND_NOT_SUPPORTED,
+            anyhow::anyhow!("unsupported socks command {}", command),
+        ));
+    }
+
+    Ok((target, port))
+}
+
+async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> Result<()> {
+    // the bound address is meaningless for us, report 0.0.0.0:0
+    stream
+        .write_all(&[SOCKS_VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// run the client side of a handshake, returns what the proxy answered
+    async fn handshake(
+        auth: &SocksAuth,
+        methods: &[u8],
+        username: &str,
+        password: &str,
+    ) -> (Result<()>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let mut hello = vec![SOCKS_VERSION, methods.len() as u8];
+        hello.extend_from_slice(methods);
+        hello.push(AUTH_VERSION);
+        hello.push(username.len() as u8);
+        hello.extend_from_slice(username.as_bytes());
+        hello.push(password.len() as u8);
+        hello.extend_from_slice(password.as_bytes());
+        client.write_all(&hello).await.unwrap();
+
+        let result = negotiate_auth(&mut server, auth).await;
+        drop(server);
+        let mut answer = Vec::new();
+        client.read_to_end(&mut answer).await.unwrap();
+        (result, answer)
+    }
+
+    #[tokio::test]
+    async fn accepts_the_credentials() {
+        let auth = SocksAuth::random();
+        let (result, answer) = handshake(
+            &auth,
+            &[0, USERNAME_PASSWORD],
+            &auth.username,
+            &auth.password,
+        )
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(
+            answer,
+            [
+                SOCKS_VERSION,
+                USERNAME_PASSWORD,
+                AUTH_VERSION,
+                AUTH_SUCCEEDED
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_wrong_credentials() {
+        let auth = SocksAuth::random();
+        let other = SocksAuth::random();
+        for (username, password) in [
+            (&auth.username, &other.password),
+            (&other.username, &auth.password),
+            (&auth.password, &auth.username),
+        ] {
+            let (result, answer) = handshake(&auth, &[USERNAME_PASSWORD], username, password).await;
+            assert!(result.is_err());
+            assert_eq!(
+                answer,
+                [SOCKS_VERSION, USERNAME_PASSWORD, AUTH_VERSION, AUTH_FAILED]
+            );
+        }
+        let (result, _) = handshake(
+            &auth,
+            &[USERNAME_PASSWORD],
+            &auth.username[..8],
+            &auth.password,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_clients_without_credentials() {
+        let auth = SocksAuth::random();
+        let (result, answer) = handshake(&auth, &[0], "", "").await;
+        assert!(result.is_err());
+        assert_eq!(answer, [SOCKS_VERSION, NO_ACCEPTABLE_METHOD]);
+    }
+
+    #[test]
+    fn credentials_are_fresh_every_time() {
+        let (a, b) = (SocksAuth::random(), SocksAuth::random());
+        assert_ne!(a.username, b.username);
+        assert_ne!(a.password, b.password);
+        assert!(a.proxy_url(9050).starts_with(&format!(
+            "socks5://{}:{}@127.0.0.1:9050",
+            a.username, a.password
+        )));
+    }
+}
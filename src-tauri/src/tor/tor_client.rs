@@ -1,50 +1,194 @@
-use anyhow::Result;
-use arti_client::{TorClient, TorClientConfig};
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::watch;
+use crate::settings::TorSettings;
+use crate::tor::metrics::{ClientStats, Metered, StreamCounters};
+use anyhow::{bail, Context, Result};
+use arti_client::config::pt::TransportConfigBuilder;
+use arti_client::config::{BoolOrAuto, BridgeConfigBuilder, PtTransportName, Reconfigure};
+use arti_client::{DataStream, DormantMode, TorClient, TorClientConfig};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tor_config::CfgPath;
 use tracing::{info, warn};
 
+/// what a connection over Tor is made for
+///
+/// Every purpose gets its own isolation token, so streams of different
+/// purposes never share a circuit and an exit cannot link, say, the
+/// media fetched for a post to the admin's browsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Purpose {
+    /// the app webview, through its SOCKS listener
+    Webview,
+    /// fetching remote media and embeds to localize posts
+    Localize,
+}
+
+/// which setting changes took effect and which wait for a restart
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ReconfigureReport {
+    /// names of the settings the running client now uses
+    pub applied: Vec<String>,
+    /// names of the settings saved for the next start
+    pub restart_required: Vec<String>,
+    /// the data dir moved, and with it the keystore holding the blog's
+    /// onion identity
+    pub identity_change: bool,
+}
+
+impl ReconfigureReport {
+    /// report for a client that is not running, every change waits for its start
+    pub fn pending(old: &TorSettings, new: &TorSettings) -> Self {
+        let mut restart_required: Vec<String> = LIVE_CHANGES
+            .iter()
+            .filter(|(_, apply)| {
+                let mut candidate = old.clone();
+                apply(&mut candidate, new);
+                candidate != *old
+            })
+            .map(|(name, _)| name.to_string())
+            .collect();
+        let identity_change = old.data_dir != new.data_dir;
+        if identity_change {
+            restart_required.push("data_dir".to_string());
+        }
+        Self {
+            applied: Vec::new(),
+            restart_required,
+            identity_change,
+        }
+    }
+}
+
+/// copies one setting from the second argument to the first
+type ApplyChange = fn(&mut TorSettings, &TorSettings);
+
+/// settings that can be applied to a running client, each on its own
+const LIVE_CHANGES: [(&str, ApplyChange); 2] = [
+    ("onion_client", |to, from| {
+        to.onion_client = from.onion_client
+    }),
+    ("bridges", |to, from| {
+        to.use_bridges = from.use_bridges;
+        to.bridges = from.bridges.clone();
+        to.transport_path = from.transport_path.clone();
+    }),
+];
+
+/// Check a Tor data dir picked in the settings
+///
+/// It holds the onion identity: it has to be absolute and, while the
+/// encrypted storage is unlocked, inside it. Whether it is private is up to
+/// the storage hardening check, like the default one.
+pub fn check_data_dir(dir: &Path, vault_root: Option<&Path>) -> Result<()> {
+    if !dir.is_absolute() || dir.components().any(|c| c == Component::ParentDir) {
+        bail!("Tor data directory {:?} must be an absolute path", dir);
+    }
+    if let Some(root) = vault_root {
+        if !dir.starts_with(root) {
+            bail!(
+                "Tor data directory {:?} is outside the encrypted storage, the onion identity would be stored unencrypted",
+                dir
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Build the Arti config for `data_dir` and the user's Tor settings
+///
+/// Also the validation of the settings: bridge lines must parse and
+/// bridges behind a pluggable transport need its binary.
+pub fn build_config(data_dir: Option<&Path>, settings: &TorSettings) -> Result<TorClientConfig> {
+    let mut builder = TorClientConfig::builder();
+
+    if let Some(dir) = data_dir {
+        // set the storage directory
+        let cfg_path = CfgPath::new(dir.to_string_lossy().into_owned());
+        builder.storage().state_dir(cfg_path.clone());
+        builder.storage().cache_dir(cfg_path);
+    }
+
+    // .onion address connections
+    builder
+        .address_filter()
+        .allow_onion_addrs(settings.onion_client);
+
+    if settings.use_bridges {
+        let mut transports = BTreeSet::new();
+        let mut count = 0;
+        for line in settings.bridges.iter().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("Bridge ").unwrap_or(line);
+            let bridge: BridgeConfigBuilder = line
+                .parse()
+                .with_context(|| format!("Invalid bridge line {:?}", line))?;
+            if let Some(transport) = bridge
+                .get_transport()
+                .filter(|t| !matches!(*t, "" | "-" | "bridge"))
+            {
+                transports.insert(transport.to_string());
+            }
+            builder.bridges().bridges().push(bridge);
+            count += 1;
+        }
+        if count == 0 {
+            bail!("Bridges are turned on but no bridge line was given");
+        }
+        builder.bridges().enabled(BoolOrAuto::Explicit(true));
+
+        if !transports.is_empty() {
+            let Some(path) = &settings.transport_path else {
+                bail!(
+                    "{} bridges need a pluggable transport binary such as lyrebird or obfs4proxy",
+                    transports.into_iter().collect::<Vec<_>>().join(", ")
+                );
+            };
+            let protocols = transports
+                .iter()
+                .map(|t| t.parse::<PtTransportName>())
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid pluggable transport name")?;
+            let mut transport = TransportConfigBuilder::default();
+            transport
+                .protocols(protocols)
+                .path(CfgPath::new_literal(path.clone()))
+                .run_on_startup(false);
+            builder.bridges().transports().push(transport);
+        }
+    }
+
+    builder.build().context("Invalid Tor configuration")
+}
+
 /// wrapper for the Arti TOR client
 #[derive(Clone)]
 pub struct TorClientManager {
     client: TorClient<tor_rtcompat::PreferredRuntime>,
-    ready_rx: watch::Receiver<bool>,
+    /// one isolated handle per purpose, created on first use
+    isolated: Arc<Mutex<HashMap<Purpose, TorClient<tor_rtcompat::PreferredRuntime>>>>,
+    data_dir: Option<PathBuf>,
+    /// settings the running client was last configured with
+    settings: Arc<Mutex<TorSettings>>,
+    /// streams opened through [`TorClientManager::connect`], per purpose
+    streams: Arc<Mutex<HashMap<Purpose, Arc<StreamCounters>>>>,
+    dormant: Arc<AtomicBool>,
 }
 
 impl TorClientManager {
     /// create and bootstrap a new Tor client
-    /// returns the client and a receiver that signals when bootstrap is complete
-    pub async fn new(data_dir: Option<PathBuf>) -> Result<Self> {
+    pub async fn new(data_dir: Option<PathBuf>, settings: &TorSettings) -> Result<Self> {
         info!("initializing TOR client...");
 
-        // configure Tor client with custom or default data directory
-        let config = if let Some(dir) = data_dir {
-            info!("using custom TOR data directory: {:?}", dir);
-            let mut builder = TorClientConfig::builder();
-
-            // set the storage directory
-            let cfg_path = CfgPath::new(dir.to_string_lossy().into_owned());
-            builder.storage().state_dir(cfg_path.clone());
-            builder.storage().cache_dir(cfg_path);
-
-            // enable .onion address connections
-            builder.address_filter().allow_onion_addrs(true);
-
-            builder.build()?
-        } else {
-            info!("using default TOR data directory (~/.local/share/arti)");
-            let mut builder = TorClientConfig::builder();
-
-            // enable .onion address connections
-            builder.address_filter().allow_onion_addrs(true);
-
-            builder.build()?
-        };
-
-        // create a channel to signal when bootstrap is complete
-        let (ready_tx, ready_rx) = watch::channel(false);
+        match &data_dir {
+            Some(dir) => info!("using custom TOR data directory: {:?}", dir),
+            None => info!("using default TOR data directory (~/.local/share/arti)"),
+        }
+        let config = build_config(data_dir.as_deref(), settings)?;
 
         info!("starting TOR client bootstrap...");
 
@@ -52,35 +196,277 @@ impl TorClientManager {
 
         info!("TOR client bootstrapped successfully!");
 
-        // signal that we're ready
-        if ready_tx.send(true).is_err() {
-            warn!("failed to send ready signal (receiver dropped)");
+        Ok(Self {
+            client,
+            isolated: Arc::new(Mutex::new(HashMap::new())),
+            data_dir,
+            settings: Arc::new(Mutex::new(settings.clone())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            dormant: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Apply new settings to the running client
+    ///
+    /// Every change Arti can take live is applied, the others are listed
+    /// in the report and wait for a restart. Invalid settings change nothing.
+    pub fn reconfigure(&self, settings: &TorSettings) -> Result<ReconfigureReport> {
+        build_config(self.data_dir.as_deref(), settings)?;
+
+        let mut current = self.settings.lock().unwrap();
+        let mut live = current.clone();
+        let mut report = ReconfigureReport::default();
+        for (name, apply) in LIVE_CHANGES {
+            let mut candidate = live.clone();
+            apply(&mut candidate, settings);
+            if candidate == live {
+                continue;
+            }
+
+            let config = build_config(self.data_dir.as_deref(), &candidate)?;
+            match self
+                .client
+                .reconfigure(&config, Reconfigure::CheckAllOrNothing)
+            {
+                Ok(()) => {
+                    live = candidate;
+                    report.applied.push(name.to_string());
+                }
+                Err(e) => {
+                    warn!("tor setting {} cannot change while running: {}", name, e);
+                    report.restart_required.push(name.to_string());
+                }
+            }
+        }
+        if settings.data_dir != current.data_dir {
+            report.restart_required.push("data_dir".to_string());
+            report.identity_change = true;
         }
 
-        Ok(Self { client, ready_rx })
+        let config = build_config(self.data_dir.as_deref(), &live)?;
+        self.client
+            .reconfigure(&config, Reconfigure::AllOrNothing)
+            .context("Failed to reconfigure Tor")?;
+        info!(
+            "tor reconfigured, applied {:?}, waiting for a restart {:?}",
+            report.applied, report.restart_required
+        );
+        *current = live;
+        Ok(report)
     }
 
     /// get the underlying TorClient
+    ///
+    /// Used to run onion services, outgoing connections should go through
+    /// [`TorClientManager::isolated_client`] instead.
     pub fn client(&self) -> &TorClient<tor_rtcompat::PreferredRuntime> {
         &self.client
     }
 
-    /// get a receiver to watch for ready status
-    #[allow(dead_code)]
-    pub fn ready_receiver(&self) -> watch::Receiver<bool> {
-        self.ready_rx.clone()
+    /// Client handle for `purpose`, isolated from every other purpose
+    ///
+    /// Connections made for the same purpose may share circuits with each
+    /// other, never with those of another purpose.
+    pub fn isolated_client(&self, purpose: Purpose) -> TorClient<tor_rtcompat::PreferredRuntime> {
+        self.isolated
+            .lock()
+            .unwrap()
+            .entry(purpose)
+            .or_insert_with(|| self.client.isolated_client())
+            .clone()
+    }
+
+    /// Open a stream to `host`:`port` for `purpose`, counted in the metrics
+    pub async fn connect(
+        &self,
+        purpose: Purpose,
+        host: &str,
+        port: u16,
+    ) -> Result<Metered<DataStream>> {
+        let counters = self.stream_counters(purpose);
+        match self.isolated_client(purpose).connect((host, port)).await {
+            Ok(stream) => Ok(counters.meter(stream)),
+            Err(e) => {
+                counters.count_failure();
+                Err(e.into())
+            }
+        }
+    }
+
+    /// bootstrap progress and the traffic of every purpose
+    pub fn metrics(&self) -> ClientStats {
+        let status = self.client.bootstrap_status();
+        let streams = self
+            .streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(purpose, counters)| (*purpose, counters.snapshot()))
+            .collect();
+        ClientStats {
+            bootstrap_percent: (status.as_frac() * 100.0).round() as u8,
+            ready_for_traffic: status.ready_for_traffic(),
+            blocked: status.blocked().map(|blockage| blockage.to_string()),
+            dormant: self.dormant.load(Ordering::Relaxed),
+            streams,
+        }
     }
 
-    /// check if the Tor client is ready
-    #[allow(dead_code)]
-    pub fn is_ready(&self) -> bool {
-        *self.ready_rx.borrow()
+    fn stream_counters(&self, purpose: Purpose) -> Arc<StreamCounters> {
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(purpose)
+            .or_default()
+            .clone()
+    }
+
+    /// Put the client to sleep or wake it up
+    ///
+    /// A dormant client stops its background tasks (directory fetches,
+    /// circuit building) until it is used again.
+    pub fn set_dormant(&self, dormant: bool) {
+        let mode = if dormant {
+            DormantMode::Soft
+        } else {
+            DormantMode::Normal
+        };
+        self.client.set_dormant(mode);
+        self.dormant.store(dormant, Ordering::Relaxed);
+        info!("tor client {}", if dormant { "dormant" } else { "awake" });
     }
 }
 
 /// start bootstrapping the Tor client in the background
 /// returns immediately with a handle that can be used to check status
-pub async fn bootstrap_tor_client(data_dir: Option<PathBuf>) -> Result<Arc<TorClientManager>> {
-    let manager = TorClientManager::new(data_dir).await?;
+pub async fn bootstrap_tor_client(
+    data_dir: Option<PathBuf>,
+    settings: &TorSettings,
+) -> Result<Arc<TorClientManager>> {
+    let manager = TorClientManager::new(data_dir, settings).await?;
     Ok(Arc::new(manager))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRIDGE: &str = "192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567";
+    const OBFS4_BRIDGE: &str =
+        "obfs4 192.0.2.2:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=AAAA iat-mode=0";
+
+    fn bridged(lines: &[&str]) -> TorSettings {
+        TorSettings {
+            use_bridges: true,
+            bridges: lines.iter().map(|l| l.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// a client that never touches the network
+    fn unbootstrapped(dir: &Path, settings: &TorSettings) -> TorClientManager {
+        let config = build_config(Some(dir), settings).unwrap();
+        let client = TorClient::builder()
+            .config(config)
+            .create_unbootstrapped()
+            .unwrap();
+        TorClientManager {
+            client,
+            isolated: Arc::new(Mutex::new(HashMap::new())),
+            data_dir: Some(dir.to_path_buf()),
+            settings: Arc::new(Mutex::new(settings.clone())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            dormant: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn pending_changes_wait_for_the_next_start() {
+        let old = TorSettings::default();
+        let report = ReconfigureReport::pending(&old, &old);
+        assert!(report.applied.is_empty() && report.restart_required.is_empty());
+
+        let new = TorSettings {
+            onion_client: false,
+            ..bridged(&[BRIDGE])
+        };
+        let report = ReconfigureReport::pending(&old, &new);
+        assert!(report.applied.is_empty());
+        assert_eq!(report.restart_required, ["onion_client", "bridges"]);
+        assert!(!report.identity_change);
+
+        let moved = TorSettings {
+            data_dir: Some("/elsewhere/tor".into()),
+            ..Default::default()
+        };
+        let report = ReconfigureReport::pending(&old, &moved);
+        assert_eq!(report.restart_required, ["data_dir"]);
+        assert!(report.identity_change);
+    }
+
+    #[tokio::test]
+    async fn running_clients_take_live_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = unbootstrapped(dir.path(), &TorSettings::default());
+
+        let new = TorSettings {
+            onion_client: false,
+            data_dir: Some(dir.path().join("moved")),
+            ..Default::default()
+        };
+        let report = manager.reconfigure(&new).unwrap();
+        assert_eq!(report.applied, ["onion_client"]);
+        assert_eq!(report.restart_required, ["data_dir"]);
+        assert!(report.identity_change);
+        assert!(!manager.settings.lock().unwrap().onion_client);
+
+        // invalid settings change nothing
+        assert!(manager.reconfigure(&bridged(&["nonsense"])).is_err());
+        assert!(!manager.settings.lock().unwrap().use_bridges);
+    }
+
+    #[test]
+    fn data_dir_stays_inside_the_vault() {
+        let vault = Path::new("/dev/shm/freedom-kit/vault");
+        check_data_dir(&vault.join("tor"), Some(vault)).unwrap();
+        check_data_dir(Path::new("/home/me/tor"), None).unwrap();
+
+        let err = check_data_dir(Path::new("/home/me/tor"), Some(vault)).unwrap_err();
+        assert!(err.to_string().contains("is outside the encrypted storage"));
+        let err = check_data_dir(&vault.join("../tor"), Some(vault)).unwrap_err();
+        assert!(err.to_string().ends_with("must be an absolute path"));
+        assert!(check_data_dir(Path::new("tor"), None).is_err());
+    }
+
+    #[test]
+    fn bridge_lines_are_checked() {
+        build_config(None, &bridged(&[BRIDGE])).unwrap();
+        build_config(
+            None,
+            &bridged(&["# mine", "", &format!("Bridge {}", BRIDGE)]),
+        )
+        .unwrap();
+        // not used, not checked
+        let off = TorSettings {
+            use_bridges: false,
+            ..bridged(&["nonsense"])
+        };
+        build_config(None, &off).unwrap();
+
+        let err = build_config(None, &bridged(&["nonsense"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid bridge line \"nonsense\"");
+        let err = build_config(None, &bridged(&["# none yet"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bridges are turned on but no bridge line was given"
+        );
+        let err = build_config(None, &bridged(&[OBFS4_BRIDGE])).unwrap_err();
+        assert!(err.to_string().starts_with("obfs4 bridges need"));
+
+        let obfs4 = TorSettings {
+            transport_path: Some("/usr/bin/lyrebird".into()),
+            ..bridged(&[OBFS4_BRIDGE])
+        };
+        build_config(None, &obfs4).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Runtime, Wry};
+
+/// id of the one tray icon the app has
+pub const TRAY_ID: &str = "main";
+
+/// what the tray menu can ask for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    CopyAddress,
+    TogglePublishing,
+    OpenAdmin,
+    Quit,
+}
+
+impl Action {
+    const ALL: [Action; 4] = [
+        Action::CopyAddress,
+        Action::TogglePublishing,
+        Action::OpenAdmin,
+        Action::Quit,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Action::CopyAddress => "copy-address",
+            Action::TogglePublishing => "toggle-publishing",
+            Action::OpenAdmin => "open-admin",
+            Action::Quit => "quit",
+        }
+    }
+
+    fn from_event(event: &MenuEvent) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| event.id() == action.id())
+    }
+}
+
+/// what the tray shows about the blog
+#[derive(Clone, Debug, Default)]
+pub struct TrayStatus {
+    /// the blog's onion URL, once it was published
+    pub onion_url: Option<String>,
+    /// the onion service is reachable right now
+    pub online: bool,
+    /// publishing was paused by hand
+    pub paused: bool,
+}
+
+impl TrayStatus {
+    fn text(&self) -> String {
+        match (&self.onion_url, self.online) {
+            (None, _) => "Onion service not running".to_string(),
+            (Some(url), true) => format!("Online at {}", shorten(url)),
+            (Some(_), false) if self.paused => "Paused".to_string(),
+            (Some(_), false) => "Offline outside publishing hours".to_string(),
+        }
+    }
+}
+
+/// first characters of the onion address in `url`, the rest does not fit a menu
+fn shorten(url: &str) -> String {
+    let host = url.trim_start_matches("http://");
+    let host = host.split(':').next().unwrap_or(host);
+    let name = host.trim_end_matches(".onion");
+    match name.get(..8) {
+        Some(start) if start.len() < name.len() => format!("{}….onion", start),
+        _ => host.to_string(),
+    }
+}
+
+/// Tray icon menu entries whose text follows the blog's state
+pub struct Tray<R: Runtime = Wry> {
+    status: MenuItem<R>,
+    copy_address: MenuItem<R>,
+    publishing: MenuItem<R>,
+}
+
+impl<R: Runtime> Tray<R> {
+    /// Put the icon in the tray / menu bar, menu clicks go to `on_action`
+    pub fn create(
+        app: &AppHandle<R>,
+        on_action: impl Fn(&AppHandle<R>, Action) + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let status = MenuItem::with_id(app, "status", "", false, None::<&str>)?;
+        let copy_address = MenuItem::with_id(
+            app,
+            Action::CopyAddress.id(),
+            "Copy onion address",
+            false,
+            None::<&str>,
+        )?;
+        let publishing = MenuItem::with_id(
+            app,
+            Action::TogglePublishing.id(),
+            "Stop publishing",
+            true,
+            None::<&str>,
+        )?;
+        let open_admin = MenuItem::with_id(
+            app,
+            Action::OpenAdmin.id(),
+            "Open admin",
+            true,
+            None::<&str>,
+        )?;
+        let quit = MenuItem::with_id(app, Action::Quit.id(), "Quit", true, None::<&str>)?;
+        let menu = Menu::with_items(
+            app,
+            &[
+                &status,
+                &copy_address,
+                &PredefinedMenuItem::separator(app)?,
+                &publishing,
+                &open_admin,
+                &PredefinedMenuItem::separator(app)?,
+                &quit,
+            ],
+        )?;
+
+        let icon = app
+            .default_window_icon()
+            .context("No app icon for the tray")?
+            .clone();
+        TrayIconBuilder::with_id(TRAY_ID)
+            .icon(icon)
+            .tooltip("Freedom Kit")
+            .menu(&menu)
+            .show_menu_on_left_click(true)
+            .on_menu_event(move |app, event| {
+                if let Some(action) = Action::from_event(&event) {
+                    on_action(app, action);
+                }
+            })
+            .build(app)
+            .context("Failed to create the tray icon")?;
+
+        let tray = Self {
+            status,
+            copy_address,
+            publishing,
+        };
+        tray.update(&TrayStatus::default())?;
+        Ok(tray)
+    }
+
+    /// Show `status` in the menu
+    pub fn update(&self, status: &TrayStatus) -> Result<()> {
+        self.status.set_text(status.text())?;
+        self.copy_address.set_enabled(status.onion_url.is_some())?;
+        self.publishing.set_text(if status.paused {
+            "Start publishing"
+        } else {
+            "Stop publishing"
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,775 @@
+use crate::crypto::{
+    self, derive_key, random_salt, random_stream_nonce, DecryptReader, EncryptWriter, SecretKey,
+    NONCE_SIZE, SALT_SIZE, SEAL_OVERHEAD,
+};
+use crate::wipe;
+use anyhow::{bail, Context, Result};
+use rand::RngCore;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// size of one encrypted volume header slot
+const SLOT_SIZE: u64 = 512;
+/// outer volume and optional hidden volume
+const VOLUME_COUNT: u64 = 2;
+/// slots 0 and 1 describe the outer volume, 2 and 3 the hidden one
+///
+/// A seal writes the slot not in use, so one of the two always points at
+/// a complete archive. Both slots of a volume share the salt, for the
+/// hidden volume too when there is none, so that says nothing.
+const SLOT_COUNT: u64 = VOLUME_COUNT * 2;
+/// first byte usable for volume data
+const DATA_START: u64 = SLOT_SIZE * SLOT_COUNT;
+/// plaintext size of a header slot
+const HEADER_PLAIN_SIZE: usize = SLOT_SIZE as usize - SALT_SIZE - SEAL_OVERHEAD;
+/// marker inside the (encrypted) header
+const VOLUME_MAGIC: &[u8; 8] = b"FKVOLUME";
+const VOLUME_VERSION: u8 = 2;
+/// smallest volume we agree to create
+const MIN_VOLUME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// decrypted contents of a header slot
+#[derive(Clone, Debug)]
+struct VolumeHeader {
+    /// where the volume starts in the container
+    start: u64,
+    /// how many bytes the volume may use
+    capacity: u64,
+    /// where the current archive starts, somewhere inside the volume
+    offset: u64,
+    /// length of the encrypted archive currently stored
+    payload_len: u64,
+    /// bumped on every seal, the newer of a volume's two slots wins
+    generation: u64,
+    /// STREAM nonce of the current archive
+    nonce: [u8; NONCE_SIZE],
+}
+
+impl VolumeHeader {
+    /// an empty volume
+    fn new(start: u64, capacity: u64) -> Self {
+        Self {
+            start,
+            capacity,
+            offset: start,
+            payload_len: 0,
+            generation: 0,
+            nonce: [0u8; NONCE_SIZE],
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.start + self.capacity
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_PLAIN_SIZE);
+        out.extend_from_slice(VOLUME_MAGIC);
+        out.push(VOLUME_VERSION);
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.capacity.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.payload_len.to_le_bytes());
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        out.extend_from_slice(&self.nonce);
+        out.resize(HEADER_PLAIN_SIZE, 0);
+        out
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        if raw.len() != HEADER_PLAIN_SIZE || &raw[..8] != VOLUME_MAGIC || raw[8] != VOLUME_VERSION {
+            return None;
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(raw[at..at + 8].try_into().unwrap());
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&raw[49..49 + NONCE_SIZE]);
+
+        let header = Self {
+            start: u64_at(9),
+            capacity: u64_at(17),
+            offset: u64_at(25),
+            payload_len: u64_at(33),
+            generation: u64_at(41),
+            nonce,
+        };
+        let inside = header.offset >= header.start
+            && header.offset.checked_add(header.payload_len)?
+                <= header.start.checked_add(header.capacity)?;
+        inside.then_some(header)
+    }
+
+    /// Where the archive of header `slot` goes: twin slots get fixed halves
+    ///
+    /// A seal writes the half the current archive is not in, so archives
+    /// never wander through the volume however often it is sealed.
+    fn region(&self, slot: u64) -> (u64, u64) {
+        let half = self.capacity / 2;
+        match slot % 2 {
+            0 => (self.start, half),
+            _ => (self.start + half, self.capacity - half),
+        }
+    }
+}
+
+/// writer that refuses to go past the end of a volume
+struct BoundedWriter<W> {
+    inner: W,
+    remaining: u64,
+    written: u64,
+}
+
+impl<W: Write> Write for BoundedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "encrypted volume is full",
+            ));
+        }
+        let n = self.inner.write(buf)?;
+        self.remaining -= n as u64;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// an unlocked volume, its files live decrypted in `workdir` until locked
+pub struct UnlockedVault {
+    container: PathBuf,
+    /// header slot describing the current archive, the next seal uses its twin
+    slot: u64,
+    key: SecretKey,
+    salt: [u8; SALT_SIZE],
+    header: VolumeHeader,
+    workdir: PathBuf,
+}
+
+/// create a new container file filled with random data
+///
+/// The outer volume is opened with `outer_passphrase`. When `hidden` is
+/// given, a second volume of the requested size is placed at the end of
+/// the container and opened with its own passphrase. Without that
+/// passphrase the hidden header and data are indistinguishable from the
+/// random filler, so the outer volume can be disclosed under pressure.
+///
+/// The outer volume only ever uses the first half of the container, with
+/// or without a hidden volume, so sealing it never reaches the hidden one
+/// and its size gives nothing away. A seal keeps the previous archive until
+/// the new one is on disk, so each volume holds up to half its size.
+pub fn create(
+    container: &Path,
+    size: u64,
+    outer_passphrase: &str,
+    hidden: Option<(&str, u64)>,
+) -> Result<()> {
+    if container.exists() {
+        bail!("{:?} already exists", container);
+    }
+    if size < DATA_START + MIN_VOLUME_SIZE {
+        bail!("Container must be at least {} MiB", MIN_VOLUME_SIZE >> 20);
+    }
+    // it could never be unlocked here
+    ram_dir()?;
+    if let Some((passphrase, hidden_size)) = hidden {
+        if passphrase == outer_passphrase {
+            bail!("The hidden volume needs a different passphrase");
+        }
+        if hidden_size < MIN_VOLUME_SIZE || hidden_size > size - DATA_START - outer_capacity(size) {
+            bail!("Hidden volume must be between 16 MiB and half the container size");
+        }
+    }
+
+    info!("creating encrypted container ({} bytes)", size);
+    if let Some(parent) = container.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let result = (|| -> Result<()> {
+        fill_random(container, size)?;
+        let mut file = OpenOptions::new().read(true).write(true).open(container)?;
+
+        let outer = VolumeHeader::new(DATA_START, outer_capacity(size));
+        write_new_header(&mut file, 0, outer_passphrase, &outer)?;
+        if let Some((passphrase, hidden_size)) = hidden {
+            let hidden = VolumeHeader::new(size - hidden_size, hidden_size);
+            write_new_header(&mut file, 1, passphrase, &hidden)?;
+        }
+
+        // twin slots start with the same salt, random or not
+        for volume in 0..VOLUME_COUNT {
+            let mut salt = [0u8; SALT_SIZE];
+            file.seek(SeekFrom::Start(volume * 2 * SLOT_SIZE))?;
+            file.read_exact(&mut salt)?;
+            file.seek(SeekFrom::Start((volume * 2 + 1) * SLOT_SIZE))?;
+            file.write_all(&salt)?;
+        }
+        file.sync_all()?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(container);
+    }
+    result
+}
+
+/// bytes of the outer volume, the rest of the container is left for a hidden one
+fn outer_capacity(size: u64) -> u64 {
+    (size - DATA_START) / 2
+}
+
+/// unlock whichever volume `passphrase` opens and decrypt it to a work dir
+pub fn unlock(container: &Path, passphrase: &str) -> Result<UnlockedVault> {
+    let mut file =
+        File::open(container).with_context(|| format!("Failed to open {:?}", container))?;
+    let container_len = file.metadata()?.len();
+    if container_len < DATA_START {
+        bail!("Encrypted container is truncated");
+    }
+
+    // twin slots share a salt, derive each key only once
+    let mut keys: Vec<([u8; SALT_SIZE], SecretKey)> = Vec::new();
+    for volume in 0..VOLUME_COUNT {
+        // slot, header and index into `keys`
+        let mut newest: Option<(u64, VolumeHeader, usize)> = None;
+        for slot in [volume * 2, volume * 2 + 1] {
+            let mut raw = vec![0u8; SLOT_SIZE as usize];
+            file.seek(SeekFrom::Start(slot * SLOT_SIZE))?;
+            file.read_exact(&mut raw)?;
+
+            let mut salt = [0u8; SALT_SIZE];
+            salt.copy_from_slice(&raw[..SALT_SIZE]);
+            let key = match keys.iter().position(|(known, _)| *known == salt) {
+                Some(key) => key,
+                None => {
+                    keys.push((salt, derive_key(passphrase, &salt)?));
+                    keys.len() - 1
+                }
+            };
+
+            let Some(header) = crypto::open(&keys[key].1, &raw[SALT_SIZE..])
+                .ok()
+                .and_then(|plain| VolumeHeader::decode(&plain))
+            else {
+                continue;
+            };
+            if newest
+                .as_ref()
+                .is_none_or(|(_, newest, _)| header.generation > newest.generation)
+            {
+                newest = Some((slot, header, key));
+            }
+        }
+        let Some((slot, header, key)) = newest else {
+            continue;
+        };
+        let (salt, key) = &keys[key];
+        let (salt, key) = (*salt, SecretKey::from_bytes(*key.as_bytes()));
+
+        if header.offset + header.payload_len > container_len {
+            bail!("Encrypted container is truncated");
+        }
+        let workdir = tempfile_dir()?;
+        if header.payload_len > 0 {
+            file.seek(SeekFrom::Start(header.offset))?;
+            let payload = BufReader::new(&mut file).take(header.payload_len);
+            let reader = DecryptReader::headerless(payload, &key, &header.nonce);
+
+            let mut archive = tar::Archive::new(reader);
+            archive.set_preserve_permissions(true);
+            if let Err(e) = archive.unpack(&workdir) {
+                wipe::shred_tree(&workdir);
+                return Err(e).context("Encrypted volume is corrupted");
+            }
+        }
+
+        info!("unlocked encrypted volume");
+        return Ok(UnlockedVault {
+            container: container.to_path_buf(),
+            slot,
+            key,
+            salt,
+            header,
+            workdir,
+        });
+    }
+
+    bail!("Wrong passphrase")
+}
+
+impl UnlockedVault {
+    /// directory holding the decrypted files
+    pub fn root(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// encrypt the work dir back into the container
+    ///
+    /// The previous archive stays intact until the new one and its header
+    /// are on disk, an interrupted seal leaves the last sealed state.
+    pub fn seal(&mut self) -> Result<()> {
+        info!("sealing encrypted volume...");
+        let slot = self.slot ^ 1;
+        self.header = write_volume(
+            &self.container,
+            slot,
+            &self.key,
+            self.salt,
+            &self.header,
+            &self.workdir,
+        )?;
+        self.slot = slot;
+        Ok(())
+    }
+
+    /// seal, then destroy the decrypted copy
+    ///
+    /// If sealing fails the decrypted copy is left in place so nothing is lost.
+    pub fn lock(mut self) -> Result<()> {
+        self.seal()
+            .with_context(|| format!("Decrypted files were kept in {:?}", self.workdir))?;
+        wipe::shred_tree(&self.workdir);
+        Ok(())
+    }
+
+    /// lock, then overwrite the previous sealed copy and its header
+    ///
+    /// After a panic wipe the twin slot still decrypts what was just wiped,
+    /// this leaves only the new copy readable.
+    pub fn lock_without_history(mut self) -> Result<()> {
+        self.seal()
+            .with_context(|| format!("Decrypted files were kept in {:?}", self.workdir))?;
+        wipe::shred_tree(&self.workdir);
+
+        let stale = self.slot ^ 1;
+        let (offset, room) = self.header.region(stale);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.container)
+            .with_context(|| format!("Failed to open {:?}", self.container))?;
+        overwrite_random(&mut file, stale * SLOT_SIZE, SLOT_SIZE)?;
+        overwrite_random(&mut file, offset, room)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// destroy the decrypted copy without writing it back
+    pub fn discard(self) {
+        wipe::shred_tree(&self.workdir);
+    }
+}
+
+/// fill a new file with random bytes
+fn fill_random(path: &Path, size: u64) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut block = vec![0u8; 1024 * 1024];
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = remaining.min(block.len() as u64) as usize;
+        rand::thread_rng().fill_bytes(&mut block[..n]);
+        file.write_all(&block[..n])?;
+        remaining -= n as u64;
+    }
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// overwrite `len` bytes at `at` with random data
+fn overwrite_random(file: &mut File, at: u64, len: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(at))?;
+    let mut writer = BufWriter::new(file);
+    let mut block = vec![0u8; 1024 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(block.len() as u64) as usize;
+        rand::thread_rng().fill_bytes(&mut block[..n]);
+        writer.write_all(&block[..n])?;
+        remaining -= n as u64;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// write the first header of a volume, keyed by `passphrase` with a fresh salt
+fn write_new_header(
+    file: &mut File,
+    volume: u64,
+    passphrase: &str,
+    header: &VolumeHeader,
+) -> Result<()> {
+    let salt = random_salt();
+    let key = derive_key(passphrase, &salt)?;
+    write_header(file, volume * 2, &key, salt, header)
+}
+
+fn write_header(
+    file: &mut File,
+    slot: u64,
+    key: &SecretKey,
+    salt: [u8; SALT_SIZE],
+    header: &VolumeHeader,
+) -> Result<()> {
+    let mut slot_bytes = salt.to_vec();
+    slot_bytes.extend_from_slice(&crypto::seal(key, &header.encode())?);
+    file.seek(SeekFrom::Start(slot * SLOT_SIZE))?;
+    file.write_all(&slot_bytes)?;
+    Ok(())
+}
+
+/// archive `source` into the region of header `slot`, then point the slot at it
+///
+/// The new archive is synced to disk before the header is written, and the
+/// header goes to the slot `current` is not in. Until that header is on
+/// disk the volume still opens to `current`.
+fn write_volume(
+    container: &Path,
+    slot: u64,
+    key: &SecretKey,
+    salt: [u8; SALT_SIZE],
+    current: &VolumeHeader,
+    source: &Path,
+) -> Result<VolumeHeader> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(container)
+        .with_context(|| format!("Failed to open {:?}", container))?;
+
+    // refuse early, the last sealed copy is never overwritten
+    let (offset, room) = current.region(slot);
+    if offset < current.offset + current.payload_len && current.offset < offset + room {
+        bail!("The last sealed copy is in the way of the next one, the container is damaged");
+    }
+    let needed = estimate_archive_size(source)?;
+    if needed > room {
+        bail!(
+            "Encrypted volume is too small to seal ({} MiB needed, {} MiB available next to the last copy)",
+            needed >> 20,
+            room >> 20
+        );
+    }
+
+    let mut header = VolumeHeader {
+        offset,
+        nonce: random_stream_nonce(),
+        generation: current.generation + 1,
+        ..current.clone()
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    header.payload_len = {
+        let bounded = BoundedWriter {
+            inner: BufWriter::new(&mut file),
+            remaining: room,
+            written: 0,
+        };
+        let writer = EncryptWriter::headerless(bounded, key, &header.nonce);
+        let mut builder = tar::Builder::new(writer);
+        builder.follow_symlinks(false);
+        builder
+            .append_dir_all(".", source)
+            .context("Failed to archive the work dir into the encrypted volume")?;
+
+        let mut bounded = builder.into_inner()?.finish()?;
+        bounded.flush()?;
+        bounded.written
+    };
+    file.sync_all()?;
+
+    write_header(&mut file, slot, key, salt, &header)?;
+    file.sync_all()?;
+
+    Ok(header)
+}
+
+/// upper bound of the encrypted tar size for everything below `dir`
+fn estimate_archive_size(dir: &Path) -> Result<u64> {
+    fn walk(dir: &Path) -> Result<u64> {
+        let mut total = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            // header block (plus long name extension) and data rounded to 512 bytes
+            total += 3 * 512;
+            if metadata.is_dir() {
+                total += walk(&entry.path())?;
+            } else {
+                total += metadata.len().div_ceil(512) * 512;
+            }
+        }
+        Ok(total)
+    }
+
+    let plain = walk(dir)? + 3 * 512 + 1024;
+    // 16 byte tag per 64 KiB chunk
+    Ok(plain + (plain / (64 * 1024) + 1) * 16)
+}
+
+/// work dirs are named `{WORKDIR_PREFIX}{random}`
+const WORKDIR_PREFIX: &str = "freedom-kit-";
+
+/// Directory in RAM for the decrypted files
+///
+/// Decrypting to disk would leave the blog and its keys readable after a
+/// crash and defeat the container, so there is no fallback: on systems
+/// without a tmpfs (macOS, Windows) the container cannot be unlocked.
+fn ram_dir() -> Result<PathBuf> {
+    #[cfg(target_os = "linux")]
+    {
+        let candidates = std::iter::once(PathBuf::from("/dev/shm")).chain(dirs::runtime_dir());
+        for dir in candidates {
+            if dir.is_dir() && is_ram_backed(&dir) {
+                return Ok(dir);
+            }
+        }
+    }
+    bail!("No RAM-backed directory to decrypt into, the decrypted files would be written to disk")
+}
+
+/// whether `dir` is on a tmpfs or ramfs mount
+#[cfg(target_os = "linux")]
+fn is_ram_backed(dir: &Path) -> bool {
+    let (Ok(dir), Ok(mounts)) = (dir.canonicalize(), fs::read_to_string("/proc/self/mounts"))
+    else {
+        return false;
+    };
+    // the most specific mount point holding `dir` decides
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            Some((Path::new(fields.next()?), fields.next()?))
+        })
+        .filter(|(mount, _)| dir.starts_with(mount))
+        .max_by_key(|(mount, _)| mount.as_os_str().len())
+        .is_some_and(|(_, fs_type)| matches!(fs_type, "tmpfs" | "ramfs"))
+}
+
+/// create a private directory for decrypted files, see [`ram_dir`]
+fn tempfile_dir() -> Result<PathBuf> {
+    let mut name = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut name);
+    let dir = ram_dir()?.join(format!("{}{}", WORKDIR_PREFIX, hex::encode(name)));
+    fs::create_dir(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(dir)
+}
+
+/// Shred work dirs a crashed or killed previous run left decrypted in RAM
+///
+/// What changed since the last seal is lost, see
+/// [`StorageSettings::seal_interval_secs`](crate::settings::StorageSettings).
+pub fn shred_leftovers() {
+    let Ok(base) = ram_dir() else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(&base) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_workdir = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WORKDIR_PREFIX)
+            && entry.file_type().is_ok_and(|kind| kind.is_dir());
+        if is_workdir {
+            warn!("shredding decrypted files left over from the last run");
+            wipe::shred_tree(&entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u64 = DATA_START + MIN_VOLUME_SIZE;
+
+    fn container() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let container = dir.path().join("storage.vault");
+        create(&container, SIZE, "outer passphrase", None).unwrap();
+        (dir, container)
+    }
+
+    fn write_note(vault: &UnlockedVault, note: &str) {
+        fs::create_dir_all(vault.root().join("data")).unwrap();
+        fs::write(vault.root().join("data/note"), note).unwrap();
+    }
+
+    fn read_note(vault: &UnlockedVault) -> String {
+        fs::read_to_string(vault.root().join("data/note")).unwrap()
+    }
+
+    /// overwrite `len` bytes at `at` with random data, as a torn write would
+    fn scribble(container: &Path, at: u64, len: usize) {
+        let mut junk = vec![0u8; len];
+        rand::thread_rng().fill_bytes(&mut junk);
+        let mut file = OpenOptions::new().write(true).open(container).unwrap();
+        file.seek(SeekFrom::Start(at)).unwrap();
+        file.write_all(&junk).unwrap();
+    }
+
+    #[test]
+    fn the_newest_seal_wins() {
+        let (_dir, container) = container();
+        let mut vault = unlock(&container, "outer passphrase").unwrap();
+        for note in ["first", "second", "third"] {
+            write_note(&vault, note);
+            vault.seal().unwrap();
+        }
+        vault.lock().unwrap();
+
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(read_note(&vault), "third");
+        vault.discard();
+    }
+
+    #[test]
+    fn an_interrupted_seal_keeps_the_last_copy() {
+        let (_dir, container) = container();
+        let mut vault = unlock(&container, "outer passphrase").unwrap();
+        write_note(&vault, "sealed");
+        vault.seal().unwrap();
+
+        // the next seal died half way: its archive and its header are garbage
+        let sealed = vault.header.clone();
+        let (next, _) = sealed.region(vault.slot ^ 1);
+        scribble(&container, next, 64 * 1024);
+        scribble(&container, (vault.slot ^ 1) * SLOT_SIZE, SLOT_SIZE as usize);
+        vault.discard();
+
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(read_note(&vault), "sealed");
+        assert_eq!(vault.header.generation, sealed.generation);
+
+        // and sealing again from there works
+        write_note(&vault, "sealed again");
+        vault.lock().unwrap();
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(read_note(&vault), "sealed again");
+        vault.discard();
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_dir, container) = container();
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(vault.root().parent().unwrap(), ram_dir().unwrap());
+        write_note(&vault, "hello");
+        let workdir = vault.root().to_path_buf();
+        vault.lock().unwrap();
+        assert!(!workdir.exists());
+
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(read_note(&vault), "hello");
+        vault.discard();
+    }
+
+    #[test]
+    fn wrong_passphrase_is_refused() {
+        let (_dir, container) = container();
+        assert!(unlock(&container, "wrong passphrase").is_err());
+    }
+
+    #[test]
+    fn hidden_volume_survives_outer_seals() {
+        let dir = tempfile::tempdir().unwrap();
+        let container = dir.path().join("storage.vault");
+        let size = DATA_START + 3 * MIN_VOLUME_SIZE;
+        create(
+            &container,
+            size,
+            "outer passphrase",
+            Some(("hidden passphrase", MIN_VOLUME_SIZE)),
+        )
+        .unwrap();
+
+        let vault = unlock(&container, "hidden passphrase").unwrap();
+        assert_eq!(vault.header.start, size - MIN_VOLUME_SIZE);
+        write_note(&vault, "hidden");
+        vault.lock().unwrap();
+
+        let mut vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(vault.header.start, DATA_START);
+        write_note(&vault, &"decoy ".repeat(100_000));
+        for seal in 0..300 {
+            fs::write(vault.root().join("data/seal"), seal.to_string()).unwrap();
+            vault.seal().unwrap();
+            assert!(vault.header.offset + vault.header.payload_len <= size - MIN_VOLUME_SIZE);
+        }
+        vault.lock().unwrap();
+
+        let vault = unlock(&container, "hidden passphrase").unwrap();
+        assert_eq!(read_note(&vault), "hidden");
+        vault.discard();
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(
+            fs::read_to_string(vault.root().join("data/seal")).unwrap(),
+            "299"
+        );
+        vault.discard();
+    }
+
+    #[test]
+    fn locking_without_history_leaves_no_older_copy() {
+        let (_dir, container) = container();
+        let mut vault = unlock(&container, "outer passphrase").unwrap();
+        fs::create_dir_all(vault.root().join("tor")).unwrap();
+        fs::write(vault.root().join("tor/keystore"), "identity key").unwrap();
+        write_note(&vault, "kept");
+        // both slots now hold a copy with the keystore
+        vault.seal().unwrap();
+        vault.seal().unwrap();
+
+        fs::remove_file(vault.root().join("tor/keystore")).unwrap();
+        let slot = vault.slot ^ 1;
+        vault.lock_without_history().unwrap();
+
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        assert_eq!(read_note(&vault), "kept");
+        assert!(!vault.root().join("tor/keystore").exists());
+        vault.discard();
+
+        // without the new header nothing older is left to fall back to
+        scribble(&container, slot * SLOT_SIZE, SLOT_SIZE as usize);
+        let err = unlock(&container, "outer passphrase").err().unwrap();
+        assert_eq!(err.to_string(), "Wrong passphrase");
+    }
+
+    #[test]
+    fn truncated_container_is_refused() {
+        let (_dir, container) = container();
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        write_note(&vault, "hello");
+        vault.lock().unwrap();
+
+        let vault = unlock(&container, "outer passphrase").unwrap();
+        let cut = vault.header.offset + vault.header.payload_len / 2;
+        vault.discard();
+        for len in [cut, DATA_START / 2] {
+            File::options()
+                .write(true)
+                .open(&container)
+                .unwrap()
+                .set_len(len)
+                .unwrap();
+            let Err(err) = unlock(&container, "outer passphrase") else {
+                panic!("unlocked a container cut at {} bytes", len);
+            };
+            assert!(format!("{:#}", err).contains("truncated"), "{:#}", err);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::paths::AppPaths;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+/// how much a panic wipe destroys
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WipeScope {
+    /// onion identity, wallet, drop box and access log keys, the blog itself survives
+    #[default]
+    IdentityOnly,
+    /// identity, Ghost content and database, settings and logs
+    Everything,
+}
+
+/// what a wipe removed and what it could not
+#[derive(Serialize, Debug, Default)]
+pub struct WipeReport {
+    pub wiped: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+/// paths destroyed for a given scope
+pub fn wipe_targets(paths: &AppPaths, scope: WipeScope) -> Vec<PathBuf> {
+    match scope {
+        // without their keys the drop box submissions and access logs cannot be read
+        WipeScope::IdentityOnly => vec![
+            paths.tor_dir.clone(),
+            paths.wallet_dir.clone(),
+            paths.dropbox_key(),
+            paths.access_log_key(),
+        ],
+        // the data dir holds the Tor state, settings and our logs,
+        // the Ghost dir holds content, database, config and Ghost's own logs
+        WipeScope::Everything => {
+            let mut targets = vec![
+                paths.data_dir.clone(),
+                paths.ghost_dir.clone(),
+                paths.wallet_dir.clone(),
+            ];
+            targets.extend(paths.ghost_content.clone());
+            targets
+        }
+    }
+}
+
+/// securely destroy everything in `scope`
+///
+/// Best effort: a failure on one path is recorded and the wipe carries on.
+/// Files are overwritten with random data before being unlinked, which
+/// defeats simple undelete tools. On SSDs and copy-on-write filesystems
+/// old blocks may survive, full disk encryption is the real protection there.
+pub fn panic_wipe(paths: &AppPaths, scope: WipeScope) -> WipeReport {
+    warn!("🚨 panic wipe started (scope: {:?})", scope);
+
+    let mut report = WipeReport::default();
+    for target in wipe_targets(paths, scope) {
+        if fs::symlink_metadata(&target).is_err() {
+            continue;
+        }
+        shred_path(&target, &mut report);
+    }
+
+    info!(
+        "panic wipe finished ({} wiped, {} failed)",
+        report.wiped.len(),
+        report.failed.len()
+    );
+    report
+}
+
+/// shred a single file or directory tree outside the usual targets
+pub fn shred_tree(path: &Path) -> WipeReport {
+    let mut report = WipeReport::default();
+    if fs::symlink_metadata(path).is_ok() {
+        shred_path(path, &mut report);
+    }
+    report
+}
+
+/// shred a file or directory tree, never following symlinks
+fn shred_path(path: &Path, report: &mut WipeReport) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!("failed to stat {:?}: {}", path, e);
+            report.failed.push(path.to_string_lossy().into_owned());
+            return;
+        }
+    };
+
+    let result = if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                shred_path(&entry.path(), report);
+            }
+        }
+        fs::remove_dir(path)
+    } else if metadata.is_file() {
+        shred_file(path, metadata.len())
+    } else {
+        // symlinks and special files: remove the entry, leave the target alone
+        fs::remove_file(path)
+    };
+
+    match result {
+        Ok(()) => report.wiped.push(path.to_string_lossy().into_owned()),
+        Err(e) => {
+            error!("failed to wipe {:?}: {}", path, e);
+            report.failed.push(path.to_string_lossy().into_owned());
+        }
+    }
+}
+
+/// overwrite a file with random data, then rename and unlink it
+fn shred_file(path: &Path, len: u64) -> io::Result<()> {
+    {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut block = vec![0u8; 64 * 1024];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(block.len() as u64) as usize;
+            rand::thread_rng().fill_bytes(&mut block[..n]);
+            file.write_all(&block[..n])?;
+            remaining -= n as u64;
+        }
+        file.sync_all()?;
+    }
+
+    // scrub the name from the directory entry before unlinking
+    let mut random_name = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut random_name);
+    let renamed = path.with_file_name(hex::encode(random_name));
+    match fs::rename(path, &renamed) {
+        Ok(()) => fs::remove_file(&renamed),
+        Err(_) => fs::remove_file(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fake_install() -> (TempDir, AppPaths) {
+        let root = tempfile::tempdir().unwrap();
+        let data_dir = root.path().join("data");
+        let paths = AppPaths {
+            tor_dir: data_dir.join("tor"),
+            data_dir,
+            ghost_dir: root.path().join("ghost"),
+            wallet_dir: root.path().join("wallet"),
+            ghost_content: None,
+        };
+
+        fs::create_dir_all(paths.tor_dir.join("keystore/hss")).unwrap();
+        fs::write(paths.tor_dir.join("keystore/hss/ks_hs_id"), b"onion key").unwrap();
+        fs::create_dir_all(paths.ghost_dir.join("content/data")).unwrap();
+        fs::write(
+            paths.ghost_dir.join("content/data/ghost-local.db"),
+            vec![7u8; 200_000],
+        )
+        .unwrap();
+        fs::create_dir_all(&paths.wallet_dir).unwrap();
+        fs::write(paths.wallet_file(), b"wallet").unwrap();
+
+        (root, paths)
+    }
+
+    #[test]
+    fn identity_only_keeps_content() {
+        let (_root, paths) = fake_install();
+
+        let report = panic_wipe(&paths, WipeScope::IdentityOnly);
+
+        assert!(report.failed.is_empty());
+        assert!(!paths.tor_dir.exists());
+        assert!(!paths.wallet_dir.exists());
+        assert!(paths.ghost_dir.join("content/data/ghost-local.db").exists());
+        assert!(paths.data_dir.exists());
+    }
+
+    #[test]
+    fn everything_removes_all_data() {
+        let (_root, paths) = fake_install();
+
+        let report = panic_wipe(&paths, WipeScope::Everything);
+
+        assert!(report.failed.is_empty());
+        assert!(!paths.data_dir.exists());
+        assert!(!paths.ghost_dir.exists());
+        assert!(!paths.wallet_dir.exists());
+    }
+
+    #[test]
+    fn missing_paths_are_not_errors() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = AppPaths {
+            data_dir: root.path().join("data"),
+            tor_dir: root.path().join("data/tor"),
+            ghost_dir: root.path().join("ghost"),
+            wallet_dir: root.path().join("wallet"),
+            ghost_content: None,
+        };
+
+        let report = panic_wipe(&paths, WipeScope::Everything);
+
+        assert!(report.wiped.is_empty());
+        assert!(report.failed.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_contents_are_overwritten() {
+        let (root, paths) = fake_install();
+        // a hard link keeps the inode alive so we can inspect it after the wipe
+        let db = paths.ghost_dir.join("content/data/ghost-local.db");
+        let witness = root.path().join("witness");
+        fs::hard_link(&db, &witness).unwrap();
+
+        let mut report = WipeReport::default();
+        shred_path(&db, &mut report);
+
+        assert!(!db.exists());
+        let remains = fs::read(&witness).unwrap();
+        assert_eq!(remains.len(), 200_000);
+        assert!(!remains.windows(16).any(|w| w.iter().all(|b| *b == 7)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_followed() {
+        let (root, paths) = fake_install();
+        let outside = root.path().join("outside.txt");
+        fs::write(&outside, b"not ours").unwrap();
+        std::os::unix::fs::symlink(&outside, paths.tor_dir.join("link")).unwrap();
+
+        let report = panic_wipe(&paths, WipeScope::IdentityOnly);
+
+        assert!(report.failed.is_empty());
+        assert!(!paths.tor_dir.exists());
+        assert_eq!(fs::read(&outside).unwrap(), b"not ours");
+    }
+}
@@ -86,8 +86,117 @@ function showOnionAddress(address: string) {
   }
 }
 
+// Ask for the passphrase of the encrypted storage
+function showUnlockForm() {
+  updateStatus("🔒 Encrypted storage locked", false);
+  const infoEl = document.querySelector(".info");
+  if (!infoEl) return;
+
+  infoEl.innerHTML = `
+    <form id="unlock-form" style="background: rgba(255, 255, 255, 0.2); padding: 16px; border-radius: 8px; margin-top: 20px;">
+      <input id="unlock-passphrase" type="password" placeholder="Passphrase" autofocus style="
+        width: 100%;
+        box-sizing: border-box;
+        padding: 8px;
+        border-radius: 6px;
+        border: none;
+        margin-bottom: 12px;
+      ">
+      <button type="submit" style="
+        background: rgba(255, 255, 255, 0.3);
+        border: none;
+        color: white;
+        padding: 8px 16px;
+        border-radius: 6px;
+        cursor: pointer;
+        font-size: 14px;
+        font-weight: 500;
+      ">🔓 Unlock</button>
+      <div id="unlock-error" style="margin-top: 12px; font-size: 12px;"></div>
+    </form>
+  `;
+
+  const form = document.getElementById("unlock-form") as HTMLFormElement;
+  const input = document.getElementById("unlock-passphrase") as HTMLInputElement;
+  const errorEl = document.getElementById("unlock-error");
+
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    updateStatus("⏳ Unlocking...");
+    try {
+      await invoke("unlock_storage", { passphrase: input.value });
+      input.value = "";
+      infoEl.innerHTML = "";
+      updateStatus("⏳ Starting Ghost CMS & TOR...");
+    } catch (error) {
+      updateStatus("🔒 Encrypted storage locked", false);
+      if (errorEl) errorEl.textContent = `❌ ${error}`;
+    }
+  });
+}
+
+// Show why the data directories are unsafe and offer to fix them
+function showStorageProblems(message: string) {
+  updateStatus("❌ Unsafe storage location", false);
+  const infoEl = document.querySelector(".info");
+  if (!infoEl) return;
+
+  infoEl.innerHTML = `
+    <div style="background: rgba(255, 0, 0, 0.2); padding: 16px; border-radius: 8px; margin-top: 20px;">
+      <div style="font-weight: 600; margin-bottom: 8px;">Ghost and TOR were not started</div>
+      <div id="storage-problems" style="font-size: 12px; white-space: pre-line; word-break: break-all; margin-bottom: 12px;"></div>
+      <button id="repair-storage" style="
+        background: rgba(255, 255, 255, 0.3);
+        border: none;
+        color: white;
+        padding: 8px 16px;
+        border-radius: 6px;
+        cursor: pointer;
+        font-size: 14px;
+        font-weight: 500;
+      ">🔧 Fix permissions and retry</button>
+    </div>
+  `;
+
+  // paths come from the filesystem, never render them as HTML
+  const problemsEl = document.getElementById("storage-problems");
+  if (problemsEl) problemsEl.textContent = message;
+
+  const repairBtn = document.getElementById("repair-storage");
+  repairBtn?.addEventListener("click", async () => {
+    updateStatus("⏳ Checking storage...");
+    try {
+      await invoke("repair_storage");
+      infoEl.innerHTML = "";
+      updateStatus("⏳ Starting Ghost CMS & TOR...");
+    } catch (error) {
+      showStorageProblems(`${error}`);
+    }
+  });
+}
+
 // Initialize the app
 async function initApp() {
+  // Listen for the encrypted storage waiting to be unlocked
+  await listen("storage-locked", () => showUnlockForm());
+
+  // Listen for the data directories failing the startup checks
+  await listen<string>("storage-error", (event) =>
+    showStorageProblems(event.payload)
+  );
+
+  // Listen for Ghost startup progress
+  const phaseMessages: Record<string, string> = {
+    decompressing: "⏳ Unpacking Ghost (first run)...",
+    migrating: "⏳ Setting up the Ghost database...",
+    booting: "⏳ Booting Ghost CMS...",
+    ready: "⏳ Ghost is up, finishing startup...",
+  };
+  await listen<{ phase: string; detail?: string }>("ghost-phase", (event) => {
+    const message = phaseMessages[event.payload.phase];
+    if (message) updateStatus(message);
+  });
+
   // Listen for Ghost ready event
   await listen("ghost-ready", () => {
     console.log("Ghost ready event received");
@@ -133,6 +242,23 @@ async function initApp() {
     }
   });
 
+  // The locked event may have fired before we started listening
+  const storage = await invoke<{
+    encrypted: boolean;
+    locked: boolean;
+    problems: { path: string; problem: string }[];
+  }>("storage_status");
+  if (storage.locked) {
+    showUnlockForm();
+    return;
+  }
+  if (storage.problems.length > 0) {
+    showStorageProblems(
+      storage.problems.map((p) => `${p.path}: ${p.problem}`).join("\n")
+    );
+    return;
+  }
+
   // Poll for initial TOR status
   try {
     const address = await invoke<string | null>("get_onion_address");